@@ -23,6 +23,8 @@ pub fn help_panel(screen: usize) -> impl View {
                 .child(help_item("d", "Delete selected realm."))
                 .child(help_item("n", "Create a new realm."))
                 .child(help_item("r", "Restart currently selected realm."))
+                .child(help_item("S", "Manage snapshots of selected realm."))
+                .child(help_item("H", "Show event history of selected realm."))
                 .child(help_item("u", "Open shell to update RealmFS image of selected realm."))
                 .child(help_item(".", "Toggle display of system realms."))
                 .child(DummyView)
@@ -42,6 +44,7 @@ pub fn help_panel(screen: usize) -> impl View {
         .child(help_item("q", "Exit application."))
         .child(help_item("l", "Toggle visibility of log panel."))
         .child(help_item("L", "Display full sized log view."))
+        .child(help_item("E", "Display recent realm events."))
         .child(help_item("T", "Select a UI color theme."))
         .child(DummyView)
         .child(TextView::new(footer_text()));
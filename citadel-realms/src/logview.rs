@@ -1,5 +1,5 @@
 use cursive::views::{TextContent, OnEventView};
-use libcitadel::{Result, LogLevel, Logger, LogOutput, DefaultLogOutput};
+use libcitadel::{Result, LogLevel, Logger, LogOutput, DefaultLogOutput, EventJournal, Realm};
 use cursive::traits::{Boxable,Identifiable};
 use cursive::views::TextView;
 use cursive::views::HideableView;
@@ -21,6 +21,8 @@ pub struct LogView {
 }
 
 impl LogView {
+    const MAX_EVENTS: usize = 500;
+
     pub fn create(content: TextContent) -> impl View {
         Self::new(content).with_id("log").max_height(8)
     }
@@ -35,6 +37,30 @@ impl LogView {
         s.add_fullscreen_layer(view);
     }
 
+    /// Display the event journal in a full sized view, either the history of `realm`
+    /// or the most recent events of all realms.
+    pub fn open_events_popup(s: &mut Cursive, realm: Option<&Realm>) {
+        let (title, entries) = match realm {
+            Some(realm) => (format!("History of realm-{}", realm.name()), EventJournal::history(realm, Self::MAX_EVENTS)),
+            None => ("Realm Events".to_string(), EventJournal::recent(Self::MAX_EVENTS)),
+        };
+        let mut content = TextContent::new("");
+        match entries {
+            Ok(ref entries) if entries.is_empty() => content.append("No events recorded\n"),
+            Ok(entries) => for entry in entries {
+                content.append(format!("{}\n", entry));
+            },
+            Err(e) => content.append(format!("Error reading event journal: {}\n", e)),
+        }
+        let scroll = ScrollView::new(TextView::new_with_content(content))
+            .scroll_strategy(ScrollStrategy::StickToBottom);
+        let view = Panel::new(scroll).title(title).full_screen();
+        let view = OnEventView::new(view)
+            .on_pre_event('E', |s| { s.pop_layer(); })
+            .on_pre_event('H', |s| { s.pop_layer(); });
+        s.add_fullscreen_layer(view);
+    }
+
     fn new(content: TextContent) -> Self {
         let panel = Self::create_panel(content);
         let hideable = HideableView::new(panel).with_id("log-hide");
@@ -8,9 +8,11 @@ use crate::realm::config_realm::ConfigDialog;
 use crate::ui::{DeferredAction, GlobalState};
 use crate::realm::delete_realm::DeleteRealmDialog;
 use crate::realm::new_realm::NewRealmDialog;
+use crate::realm::snapshots::SnapshotDialog;
 use crate::dialogs::confirm_dialog;
 use crate::item_list::ItemList;
 use crate::notes::NotesDialog;
+use crate::logview::LogView;
 use cursive::views::Dialog;
 use crate::realmfs::RealmFSAction;
 
@@ -42,10 +44,7 @@ impl RealmAction {
 
         Self::confirm_action(title, msg, |r| {
             let manager = r.manager();
-            if !Self::log_fail("stopping realm", || manager.stop_realm(r)) {
-                return;
-            }
-            Self::log_fail("re-starting realm", || manager.start_realm(r));
+            Self::log_fail("restarting realm", || manager.restart_realm(r));
         })
     }
 
@@ -59,11 +58,25 @@ impl RealmAction {
 
     fn stop_realm() -> EventResult {
         let title = "Stop Realm?";
-        let msg = "Do you want to stop realm '$REALM'?";
 
-        Self::confirm_action(title, msg, |r| {
-            let manager = r.manager();
-            Self::log_fail("stopping realm", || manager.stop_realm(r));
+        EventResult::with_cb(move |s| {
+            let realm = Self::current_realm(s);
+            let dependents = realm.manager().active_dependents(&realm);
+            let result = if dependents.is_empty() {
+                let msg = "Do you want to stop realm '$REALM'?";
+                Self::confirm_action(title, msg, |r| {
+                    let manager = r.manager();
+                    Self::log_fail("stopping realm", || manager.stop_realm(r));
+                })
+            } else {
+                let names = dependents.iter().map(|r| r.name()).collect::<Vec<_>>();
+                let msg = format!("Running realms {} depend on realm '$REALM' and will also be stopped.\n\nDo you want to stop realm '$REALM'?", names.join(", "));
+                Self::confirm_action(title, msg, |r| {
+                    let manager = r.manager();
+                    Self::log_fail("stopping realm", || manager.stop_realm_and_dependents(r));
+                })
+            };
+            result.process(s);
         })
     }
 
@@ -156,6 +169,20 @@ impl RealmAction {
 
     }
 
+    pub fn history() -> EventResult {
+        EventResult::with_cb(|s| {
+            let realm = RealmAction::current_realm(s);
+            LogView::open_events_popup(s, Some(&realm));
+        })
+    }
+
+    pub fn snapshots() -> EventResult {
+        EventResult::with_cb(|s| {
+            let realm = RealmAction::current_realm(s);
+            SnapshotDialog::open(s, realm);
+        })
+    }
+
     fn log_fail<F>(msg: &str, f: F) -> bool
         where F: Fn() -> Result<()>
     {
@@ -176,9 +203,10 @@ impl RealmAction {
         })
     }
 
-    pub fn confirm_action<F>(title: &'static str, message: &'static str, callback: F) -> EventResult
+    pub fn confirm_action<F>(title: &'static str, message: impl Into<String>, callback: F) -> EventResult
         where F: Fn(&Realm), F: 'static + Send+Sync,
     {
+        let message = message.into();
         EventResult::with_cb({
             let callback = Arc::new(callback);
             move |s| {
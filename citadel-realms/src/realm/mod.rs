@@ -9,7 +9,7 @@ use cursive::{
 };
 
 
-use libcitadel::{Realm, RealmManager, RealmConfig, RealmFS};
+use libcitadel::{Realm, RealmManager, RealmConfig, RealmFS, RealmStats};
 
 
 use self::actions::RealmAction;
@@ -20,16 +20,41 @@ mod actions;
 mod new_realm;
 mod delete_realm;
 mod config_realm;
+mod snapshots;
 
 pub struct RealmListContent {
     show_system_realms: bool,
     manager: Arc<RealmManager>,
+    // Last resource usage sample of the realm shown in the info panel
+    stats: Option<(String, RealmStats)>,
 }
 
 impl RealmListContent {
 
     pub fn new(manager: Arc<RealmManager>) -> Self {
-        RealmListContent { show_system_realms: false, manager }
+        RealmListContent { show_system_realms: false, manager, stats: None }
+    }
+
+    // Returns the new sample and the previous sample of the same realm if there is one.
+    fn sample_stats(&mut self, realm: &Realm) -> Option<(RealmStats, Option<RealmStats>)> {
+        let previous = match self.stats.take() {
+            Some((name, stats)) if name == realm.name() => Some(stats),
+            _ => None,
+        };
+        let result = match previous {
+            Some(ref stats) => stats.refresh_usage(realm),
+            None => RealmStats::load(realm),
+        };
+        match result {
+            Ok(stats) => {
+                self.stats = Some((realm.name().to_string(), stats.clone()));
+                Some((stats, previous))
+            },
+            Err(e) => {
+                warn!("error reading resource usage of realm-{}: {}", realm.name(), e);
+                None
+            }
+        }
     }
 
     fn realm_fg_color(realm: &Realm, current: ColorStyle, selected: bool, focused: bool) -> ColorType {
@@ -102,7 +127,8 @@ impl ItemListContent<Realm> for RealmListContent {
     }
 
     fn update_info(&mut self, realm: &Realm, state: Rc<ItemRenderState>) {
-        RealmInfoRender::new(state, realm).render()
+        let stats = self.sample_stats(realm);
+        RealmInfoRender::new(state, realm, stats).render()
     }
 
     fn on_event(&mut self, item: Option<&Realm>, event: Event) -> EventResult {
@@ -116,6 +142,8 @@ impl ItemListContent<Realm> for RealmListContent {
             Event::Char('n') => RealmAction::new_realm(self.manager.clone()),
             Event::Char('d') => RealmAction::delete_realm(),
             Event::Char('e') => RealmAction::edit_notes(),
+            Event::Char('S') => RealmAction::snapshots(),
+            Event::Char('H') => RealmAction::history(),
             Event::Char('$') => RealmAction::open_shell(false),
             Event::Char('#') => RealmAction::open_shell(true),
             Event::Char('u') => RealmAction::update_realmfs(),
@@ -133,11 +161,12 @@ impl ItemListContent<Realm> for RealmListContent {
 struct RealmInfoRender<'a> {
     state: Rc<ItemRenderState>,
     realm: &'a Realm,
+    stats: Option<(RealmStats, Option<RealmStats>)>,
 }
 
 impl <'a> RealmInfoRender <'a> {
-    fn new(state: Rc<ItemRenderState>, realm: &'a Realm) -> Self {
-        RealmInfoRender { state, realm }
+    fn new(state: Rc<ItemRenderState>, realm: &'a Realm, stats: Option<(RealmStats, Option<RealmStats>)>) -> Self {
+        RealmInfoRender { state, realm, stats }
     }
 
     fn render(&mut self) {
@@ -145,6 +174,7 @@ impl <'a> RealmInfoRender <'a> {
         let config = self.realm.config();
         self.render_realmfs_info(&config);
         self.render_options(&config);
+        self.render_stats();
         self.render_notes();
     }
 
@@ -263,6 +293,42 @@ impl <'a> RealmInfoRender <'a> {
         self.newline();
     }
 
+    fn render_stats(&self) {
+        let (stats, previous) = match self.stats {
+            Some((ref stats, ref previous)) => (stats, previous),
+            None => return,
+        };
+
+        self.heading("Resources").newlines(2);
+
+        if self.realm.is_active() {
+            let cpu = match previous {
+                Some(previous) => format!("{:.1}%", stats.cpu_percent(previous)),
+                None => "-".to_string(),
+            };
+            self.print("   CPU: ").dim_style().print(cpu).pop();
+            self.print("  Tasks: ").dim_style().println(stats.tasks_current().to_string()).pop();
+
+            self.print("   Memory: ").dim_style().print(format_bytes(stats.memory_current())).pop();
+            if stats.memory_peak() > 0 {
+                self.print("  Peak: ").dim_style().print(format_bytes(stats.memory_peak())).pop();
+            }
+            self.newline();
+
+            self.print("   IO Read: ").dim_style().print(format_bytes(stats.io_read_bytes())).pop();
+            self.print("  Written: ").dim_style().println(format_bytes(stats.io_write_bytes())).pop();
+        }
+
+        if stats.realmfs_size() > 0 {
+            let used = format!("{} of {}", format_bytes(stats.realmfs_used()), format_bytes(stats.realmfs_size()));
+            self.print("   RealmFS: ").dim_style().println(used).pop();
+        }
+        if stats.overlay_used() > 0 {
+            self.print("   Overlay: ").dim_style().println(format_bytes(stats.overlay_used())).pop();
+        }
+        self.newline();
+    }
+
     fn render_notes(&self) {
         let notes = match self.realm.notes() {
             Some(notes) => notes,
@@ -283,3 +349,17 @@ impl <'a> InfoRenderer for RealmInfoRender<'a> {
         self.state.clone()
     }
 }
+
+fn format_bytes(n: u64) -> String {
+    const UNITS: &[&str] = &["K", "M", "G", "T"];
+    if n < 1024 {
+        return format!("{}B", n);
+    }
+    let mut value = n as f64 / 1024.0;
+    let mut unit = 0;
+    while value >= 1024.0 && unit < UNITS.len() - 1 {
+        value /= 1024.0;
+        unit += 1;
+    }
+    format!("{:.1}{}", value, UNITS[unit])
+}
@@ -131,10 +131,10 @@ impl NewRealmDialog {
             }
         };
         realm.with_mut_config(|c| c.realmfs = Some(realmfs_name.to_string()));
-        let config = realm.config();
-        if let Err(err) = config.write() {
+        if let Err(err) = realm.write_config() {
             warn!("error writing config file for new realm: {}", err);
         }
+        let config = realm.config();
         let scheme_name = config.terminal_scheme().unwrap_or("default-dark").to_string();
         if let Some(scheme) = Base16Scheme::by_name(&scheme_name) {
             if let Err(e) = scheme.apply_to_realm(&self.manager, &realm) {
@@ -0,0 +1,190 @@
+use cursive::view::ViewWrapper;
+use cursive::traits::{View,Boxable,Identifiable,Scrollable,Finder};
+use cursive::views::{ViewBox, DummyView, PaddedView, TextView, Dialog, LinearLayout, SelectView, EditView};
+use cursive::Cursive;
+use cursive::event::{Event, EventResult};
+use libcitadel::{Realm, RealmSnapshot, Result};
+use crate::dialogs::{confirm_dialog, DialogButtonAdapter};
+
+pub struct SnapshotDialog {
+    inner: ViewBox,
+    realm: Realm,
+}
+
+impl SnapshotDialog {
+
+    pub fn call<F,R>(s: &mut Cursive, callback: F) -> R
+        where F: FnOnce(&mut Self) -> R
+    {
+        s.call_on_id("snapshot-dialog", callback)
+            .expect("snapshot dialog not found")
+    }
+
+    pub fn open(s: &mut Cursive, realm: Realm) {
+        let dialog = Self::new(realm)
+            .with_id("snapshot-dialog");
+        s.add_layer(dialog);
+    }
+
+    fn new(realm: Realm) -> Self {
+        let select = SelectView::<String>::new()
+            .with_id("snapshot-select")
+            .scrollable()
+            .min_size((50, 8));
+
+        let content = PaddedView::new((2,2,1,1), LinearLayout::vertical()
+            .child(TextView::new(format!("Snapshots of home directory and overlay of realm-{}", realm.name())))
+            .child(DummyView)
+            .child(select));
+
+        let dialog = Dialog::around(content)
+            .title("Realm Snapshots")
+            .button("New", Self::handle_new)
+            .button("Restore", Self::handle_restore)
+            .button("Delete", Self::handle_delete)
+            .dismiss_button("Close");
+
+        let inner = ViewBox::boxed(dialog.with_id("snapshot-dialog-inner"));
+
+        let mut dialog = SnapshotDialog { inner, realm };
+        dialog.reload();
+        dialog
+    }
+
+    fn reload(&mut self) {
+        let snapshots = self.realm.list_snapshots().unwrap_or_else(|e| {
+            warn!("error listing snapshots for realm-{}: {}", self.realm.name(), e);
+            Vec::new()
+        });
+        self.call_on_id("snapshot-select", |v: &mut SelectView<String>| {
+            v.clear();
+            for snapshot in &snapshots {
+                v.add_item(Self::snapshot_line(snapshot), snapshot.label().to_string());
+            }
+        });
+    }
+
+    fn snapshot_line(snapshot: &RealmSnapshot) -> String {
+        if snapshot.has_overlay() {
+            format!("{}  (with overlay)", snapshot.label())
+        } else {
+            snapshot.label().to_string()
+        }
+    }
+
+    fn selected_label(&mut self) -> Option<String> {
+        self.call_on_id("snapshot-select", |v: &mut SelectView<String>| {
+            v.selection().map(|label| (*label).clone())
+        }).and_then(|label| label)
+    }
+
+    fn handle_new(s: &mut Cursive) {
+        let edit = EditView::new()
+            .on_submit(|s, _| Self::create_snapshot(s))
+            .with_id("snapshot-label")
+            .fixed_width(32);
+
+        let content = PaddedView::new((2,2,1,1), LinearLayout::vertical()
+            .child(TextView::new("Enter a label for the new snapshot"))
+            .child(DummyView)
+            .child(edit));
+
+        let dialog = Dialog::around(content)
+            .title("New Snapshot")
+            .dismiss_button("Cancel")
+            .button("Create", Self::create_snapshot);
+
+        s.add_layer(dialog);
+    }
+
+    fn create_snapshot(s: &mut Cursive) {
+        let label = s.call_on_id("snapshot-label", |v: &mut EditView| v.get_content())
+            .expect("snapshot label edit view not found");
+        s.pop_layer();
+
+        if !RealmSnapshot::is_valid_label(&label) {
+            s.add_layer(Dialog::info("Snapshot label is invalid.").title("Invalid Label"));
+            return;
+        }
+
+        let result = Self::call(s, |v| {
+            let result = v.realm.snapshot(&label).map(|_| ());
+            v.reload();
+            result
+        });
+        Self::show_result(s, "creating snapshot", result);
+    }
+
+    fn handle_restore(s: &mut Cursive) {
+        let (realm, label) = Self::call(s, |v| (v.realm.clone(), v.selected_label()));
+        let label = match label {
+            Some(label) => label,
+            None => return,
+        };
+
+        if realm.is_active() {
+            let msg = format!("Stop realm-{} before restoring a snapshot.", realm.name());
+            s.add_layer(Dialog::info(msg).title("Realm Running"));
+            return;
+        }
+
+        let msg = format!("Replace home directory of realm-{} with snapshot '{}'?", realm.name(), label);
+        let dialog = confirm_dialog("Restore Snapshot?", &msg, move |s| {
+            let result = realm.restore_snapshot(&label);
+            Self::show_result(s, "restoring snapshot", result);
+        });
+        s.add_layer(dialog);
+    }
+
+    fn handle_delete(s: &mut Cursive) {
+        let label = match Self::call(s, |v| v.selected_label()) {
+            Some(label) => label,
+            None => return,
+        };
+
+        let msg = format!("Are you sure you want to delete snapshot '{}'?", label);
+        let dialog = confirm_dialog("Delete Snapshot?", &msg, move |s| {
+            let result = Self::call(s, |v| {
+                let result = v.realm.delete_snapshot(&label);
+                v.reload();
+                result
+            });
+            Self::show_result(s, "deleting snapshot", result);
+        });
+        s.add_layer(dialog);
+    }
+
+    fn show_result(s: &mut Cursive, msg: &str, result: Result<()>) {
+        if let Err(e) = result {
+            let msg = format!("Error {}: {}", msg, e);
+            warn!("{}", msg);
+            s.add_layer(Dialog::info(msg));
+        }
+    }
+}
+
+impl DialogButtonAdapter for SnapshotDialog {
+    fn inner_id(&self) -> &'static str {
+        "snapshot-dialog-inner"
+    }
+}
+
+impl ViewWrapper for SnapshotDialog {
+    type V = dyn View;
+
+    fn with_view<F, R>(&self, f: F) -> Option<R>
+        where F: FnOnce(&Self::V) -> R
+    {
+        Some(f(&*self.inner))
+    }
+
+    fn with_view_mut<F, R>(&mut self, f: F) -> Option<R>
+        where F: FnOnce(&mut Self::V) -> R
+    {
+        Some(f(&mut *self.inner))
+    }
+
+    fn wrap_on_event(&mut self, event: Event) -> EventResult {
+        self.handle_event("nrdc", event)
+    }
+}
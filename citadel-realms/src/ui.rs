@@ -11,6 +11,8 @@ use crate::terminal::TerminalTools;
 use crate::logview::TextContentLogOutput;
 use std::sync::{Arc,RwLock, RwLockReadGuard, RwLockWriteGuard};
 use std::mem;
+use std::thread;
+use std::time::Duration;
 use crate::item_list::ItemList;
 use crate::realm::RealmListContent;
 use crate::realmfs::RealmFSListContent;
@@ -73,6 +75,7 @@ impl Inner {
 impl RealmUI {
     const SCREEN_REALMFS: ScreenId = 0;
     const SCREEN_REALM  : ScreenId = 1;
+    const STATS_REFRESH_INTERVAL: Duration = Duration::from_secs(2);
 
     pub fn create() -> Result<Self> {
 
@@ -117,6 +120,21 @@ impl RealmUI {
         if let Err(e) = self.manager.start_event_task() {
             warn!("error starting realm manager event task: {}", e);
         }
+
+        self.start_stats_refresh();
+    }
+
+    // Redraw the realm info panel periodically so resource usage stays current
+    fn start_stats_refresh(&self) {
+        let ui = self.clone();
+        thread::spawn(move || loop {
+            thread::sleep(Self::STATS_REFRESH_INTERVAL);
+            ui.send_sink(|s| {
+                if s.active_screen() == Self::SCREEN_REALM {
+                    ItemList::<Realm>::call_update_info("realms", s);
+                }
+            });
+        });
     }
 
 
@@ -282,6 +300,12 @@ impl RealmUI {
             }
         });
 
+        siv.add_global_callback('E', |s| {
+            if is_top_layer(s) {
+                LogView::open_events_popup(s, None);
+            }
+        });
+
         siv.add_global_callback('T', |s| {
             if is_top_layer(s) {
                 ThemeChooser::open(s);
@@ -1,3 +1,4 @@
+use std::cell::Cell;
 use std::path::Path;
 use std::ffi::OsStr;
 use std::fs;
@@ -164,7 +165,16 @@ fn decompress_images(sync: bool) -> Result<()> {
 fn decompress_one_image_sync(image: ResourceImage) -> Result<()> {
         let start = Instant::now();
         info!("Decompressing {}", image.path().display());
-        image.decompress(true)
+        let name = image.path().file_name().unwrap();
+        let reported = Cell::new(0);
+        let progress = |read: u64, total: u64| {
+            let percent = (read * 100).checked_div(total).unwrap_or(100);
+            if percent >= reported.get() + 10 {
+                reported.set(percent);
+                info!("Decompressing {:?}: {}%", name, percent);
+            }
+        };
+        image.decompress_with_progress(true, &progress)
             .map_err(|e| format_err!("Failed to decompress image file {}: {}", image.path().display(), e))?;
         cmd!("/usr/bin/du", "-h {}", image.path().display())?;
         info!("Decompress {:?} finished in {} seconds",
@@ -22,6 +22,7 @@ pub fn main(args: Vec<String>) {
         Some(s) if s == "setup" => do_setup(),
         Some(s) if s == "boot-automount" => do_boot_automount(),
         Some(s) if s == "start-realms" => do_start_realms(),
+        Some(s) if s == "stop-realms" => do_stop_realms(),
         _ => Err(format_err!("Bad or missing argument").into()),
     };
 
@@ -95,6 +96,12 @@ fn do_start_realms() -> Result<()> {
     manager.start_boot_realms()
 }
 
+fn do_stop_realms() -> Result<()> {
+    Logger::set_log_level(LogLevel::Info);
+    let manager = RealmManager::load()?;
+    manager.stop_all_realms()
+}
+
 // Write automount unit for /boot partition
 fn do_boot_automount() -> Result<()> {
     Logger::set_log_level(LogLevel::Info);
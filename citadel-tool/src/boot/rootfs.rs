@@ -6,6 +6,7 @@ use libcitadel::verity::Verity;
 
 pub fn setup_rootfs() -> Result<()> {
     let mut p = choose_boot_partiton(true, CommandLine::revert_rootfs())?;
+    p.start_boot_attempt()?;
     if CommandLine::noverity() {
         setup_partition_unverified(&p)
     } else {
@@ -43,12 +44,14 @@ fn setup_partition_unverified(p: &Partition) -> Result<()> {
 fn setup_partition_verified(p: &mut Partition) -> Result<()> {
     info!("Creating /dev/mapper/rootfs dm-verity device");
     if !CommandLine::nosignatures() {
-        if !p.has_public_key() {
-            bail!("no public key available for channel {}", p.metainfo().channel())
-        }
+        let policy = match p.trust_policy() {
+            Some(policy) => policy.clone(),
+            None => bail!("no public key available for channel {}", p.metainfo().channel()),
+        };
         if !p.is_signature_valid() {
+            let count = policy.count_valid_signatures(p.header());
             p.write_status(ImageHeader::STATUS_BAD_SIG)?;
-            bail!("signature verification failed on partition");
+            bail!("signature verification failed on partition ({} of {} required signatures valid)", count, policy.threshold());
         }
         info!("Image signature is valid for channel {}", p.metainfo().channel());
     }
@@ -148,9 +151,9 @@ fn compare_boot_partitions(a: Option<Partition>, b: Partition) -> Option<Partiti
         }
     }
 
-    // choose NEW over GOOD if versions are the same or
-    // if versions cannot be compared because channels differ
-    if b.is_new() && a.is_good() {
+    // choose NEW (or TRY_BOOT with attempts remaining) over GOOD if versions are
+    // the same or if versions cannot be compared because channels differ
+    if (b.is_new() || b.is_trying_boot()) && a.is_good() {
         return Some(b);
     }
 
@@ -167,7 +170,9 @@ fn is_bootable(p: &Partition) -> bool {
         return false;
     }
 
-    if p.is_new() || p.is_good() {
+    // boot_scan() has already marked a TRY_BOOT partition with no
+    // boot attempts remaining as FAILED
+    if p.is_new() || p.is_trying_boot() || p.is_good() {
         return true;
     }
 
@@ -0,0 +1,64 @@
+use clap::App;
+use clap::ArgMatches;
+use clap::AppSettings::*;
+use clap::Arg;
+
+use libcitadel::{Result,Realm,RealmManager,Logger,LogLevel};
+use libcitadel::util::is_euid_root;
+use std::process::exit;
+
+pub fn main(args: Vec<String>) {
+
+    Logger::set_log_level(LogLevel::Info);
+
+    let app = App::new("citadel-firewall")
+        .about("Install the firewall rules generated from the policy of a running realm")
+        .settings(&[ArgRequiredElseHelp,ColoredHelp, DisableHelpSubcommand, DisableVersion, DeriveDisplayOrder])
+
+        .arg(Arg::with_name("dry-run")
+            .long("dry-run")
+            .help("Print the generated nftables ruleset instead of installing it"))
+
+        .arg(Arg::with_name("realm")
+            .help("Name of realm, which must be running unless --dry-run is given")
+            .required(true));
+
+    let matches = app.get_matches_from(args);
+    if let Err(ref e) = apply_firewall(&matches) {
+        eprintln!("Error: {}", e);
+        exit(1);
+    }
+}
+
+fn find_realm(manager: &RealmManager, arg_matches: &ArgMatches) -> Result<Realm> {
+    let name = arg_matches.value_of("realm").unwrap();
+    match manager.realm_by_name(name) {
+        Some(realm) => Ok(realm),
+        None => bail!("No realm named '{}' found", name),
+    }
+}
+
+fn apply_firewall(arg_matches: &ArgMatches) -> Result<()> {
+    let manager = RealmManager::load()?;
+    let realm = find_realm(&manager, arg_matches)?;
+
+    // A dry run generates the rules from the realm configuration, so the realm
+    // does not need to be running.
+    if arg_matches.is_present("dry-run") {
+        match manager.firewall_ruleset(&realm)? {
+            Some(ruleset) => print!("{}", ruleset),
+            None => info!("Realm '{}' has no firewall policy", realm.name()),
+        }
+        return Ok(());
+    }
+
+    if !realm.is_active() {
+        bail!("Realm '{}' is not running", realm.name());
+    }
+    if !is_euid_root() {
+        bail!("Installing firewall rules must be run as root");
+    }
+    manager.apply_firewall(&realm)?;
+    info!("Firewall rules installed for realm '{}'", realm.name());
+    Ok(())
+}
@@ -3,9 +3,11 @@ use std::process::exit;
 
 use clap::{App,Arg,SubCommand,ArgMatches};
 use clap::AppSettings::*;
-use libcitadel::{Result, ResourceImage, Logger, LogLevel, Partition, KeyPair, ImageHeader, util};
+use libcitadel::{Result, ResourceImage, Logger, LogLevel, Partition, KeyPair, KeyRing, ImageHeader};
 use hex;
 
+use crate::update;
+
 pub fn main(args: Vec<String>) {
 
     let app = App::new("citadel-image")
@@ -51,6 +53,39 @@ pub fn main(args: Vec<String>) {
                 .required_unless("choose")
                 .help("Path to image file")))
 
+        .subcommand(SubCommand::with_name("install")
+            .about("Install an image file of any type")
+            .arg(Arg::with_name("skip-sha")
+                .long("skip-sha")
+                .help("Skip verification of header sha256 value"))
+            .arg(Arg::with_name("no-prefer")
+                .long("no-prefer")
+                .help("Don't set PREFER_BOOT flag when installing a rootfs image"))
+            .arg(Arg::with_name("path")
+                .required(true)
+                .help("Path to image file")))
+
+        .subcommand(SubCommand::with_name("sign-image")
+            .about("Sign the metainfo of an image file")
+            .arg(Arg::with_name("keyring")
+                .long("keyring")
+                .takes_value(true)
+                .value_name("FILE")
+                .required_unless("kernel-key")
+                .help("Load signing key from an encrypted keyring file"))
+            .arg(Arg::with_name("kernel-key")
+                .long("kernel-key")
+                .conflicts_with("keyring")
+                .help("Load signing key from the kernel keyring"))
+            .arg(Arg::with_name("key-name")
+                .long("key-name")
+                .takes_value(true)
+                .required(true)
+                .help("Name of the signing key"))
+            .arg(Arg::with_name("path")
+                .required(true)
+                .help("Path to image file")))
+
         .subcommand(SubCommand::with_name("genkeys")
             .about("Generate a pair of keys"))
 
@@ -106,12 +141,20 @@ fn info_signature(img: &ResourceImage) -> Result<()> {
     } else {
         println!("Signature: No Signature");
     }
-    match img.header().public_key()? {
-        Some(pubkey) => {
-            if img.header().verify_signature(pubkey) {
-                println!("Signature is valid");
+    for (key_id, signature) in img.header().signature_block() {
+        println!("Signature [{}]: {}", key_id, hex::encode(&signature));
+    }
+    match img.header().trust_policy()? {
+        Some(policy) => {
+            for key in policy.keys() {
+                let valid = img.header().has_valid_signature_from(key);
+                println!("Channel key {}: {}", key.key_id(), if valid { "valid signature" } else { "no valid signature" });
+            }
+            let count = policy.count_valid_signatures(img.header());
+            if count >= policy.threshold() {
+                println!("Signature is valid ({} of {} required)", count, policy.threshold());
             } else {
-                println!("Signature verify FAILED");
+                println!("Signature verify FAILED ({} of {} required)", count, policy.threshold());
             }
         },
         None => { println!("No public key found for channel '{}'", img.metainfo().channel()) },
@@ -206,62 +249,48 @@ fn clear_prefer_boot() -> Result<()> {
 }
 
 fn sign_image(arg_matches: &ArgMatches) -> Result<()> {
-    let _img = load_image(arg_matches)?;
-    info!("Not implemented yet");
-    Ok(())
-}
-
-fn install_image(arg_matches: &ArgMatches) -> Result<()> {
-    let source = arg_matches.value_of("path").expect("path argument missing");
     let img = load_image(arg_matches)?;
-    let _hdr = img.header();
-    let metainfo = img.metainfo();
-
-    // XXX verify signature?
-
-    if !(metainfo.image_type() == "kernel" || metainfo.image_type() == "extra") {
-        bail!("Cannot install image type {}", metainfo.image_type());
-    }
-
-    let shasum = img.generate_shasum()?;
-    if shasum != img.metainfo().shasum() {
-        bail!("Image shasum does not match metainfo");
+    let keypair = load_signing_keys(arg_matches)?;
+    let pubkey = keypair.public_key();
+
+    let sig = keypair.sign(&img.header().metainfo_bytes());
+    img.header().add_signature(&pubkey, sig.to_bytes())?;
+    img.header().write_header_to(img.path())?;
+    info!("Signed image {} with key {}", img.path().display(), pubkey.key_id());
+
+    match img.header().trust_policy()? {
+        Some(policy) => {
+            if !policy.keys().iter().any(|k| k.key_id() == pubkey.key_id()) {
+                warn!("Signing key is not one of the public keys for channel '{}'", img.metainfo().channel());
+            }
+        },
+        None => warn!("No public key found for channel '{}'", img.metainfo().channel()),
     }
+    Ok(())
+}
 
-    img.generate_verity_hashtree()?;
-
-    let filename = if metainfo.image_type() == "kernel" {
-        let kernel_version = match metainfo.kernel_version() {
-            Some(version) => version,
-            None => bail!("Kernel image does not have a kernel version field in metainfo"),
-        };
-        if kernel_version.chars().any(|c| c == '/') {
-            bail!("Kernel version field has / char");
-        }
-        format!("citadel-kernel-{}-{:03}.img", kernel_version, metainfo.version())
-    } else {
-        format!("citadel-extra-{:03}.img", metainfo.version())
-    };
-
-    if !metainfo.channel().chars().all(|c| c.is_ascii_lowercase()) {
-        bail!("Refusing to build path from strange channel name {}", metainfo.channel());
+fn load_signing_keys(arg_matches: &ArgMatches) -> Result<KeyPair> {
+    let name = arg_matches.value_of("key-name").expect("key-name argument missing");
+    if arg_matches.is_present("kernel-key") {
+        return KeyRing::get_kernel_keypair(name);
     }
-    let image_dir = Path::new("/storage/resources").join(metainfo.channel());
-    let image_dest = image_dir.join(filename);
-    if image_dest.exists() {
-        rotate(&image_dest)?;
-    }
-    util::rename(source, &image_dest)
+    let path = arg_matches.value_of("keyring").expect("keyring argument missing");
+    let passphrase = rpassword::read_password_from_tty(Some("Keyring passphrase: "))
+        .map_err(context!("error reading passphrase"))?;
+    let keyring = KeyRing::load(path, &passphrase)?;
+    keyring.get_keypair(name)
 }
 
-fn rotate(path: &Path) -> Result<()> {
-    if !path.exists() || path.file_name().is_none() {
-        return Ok(());
+fn install_image(arg_matches: &ArgMatches) -> Result<()> {
+    let path = arg_matches.value_of("path").expect("path argument missing");
+    let mut flags = 0;
+    if arg_matches.is_present("skip-sha") {
+        flags |= update::FLAG_SKIP_SHA;
+    }
+    if arg_matches.is_present("no-prefer") {
+        flags |= update::FLAG_NO_PREFER;
     }
-    let filename = path.file_name().unwrap();
-    let dot_zero = path.with_file_name(format!("{}.0", filename.to_string_lossy()));
-    util::remove_file(&dot_zero)?;
-    util::rename(path, &dot_zero)
+    update::install_image(Path::new(path), flags)
 }
 
 fn genkeys() -> Result<()> {
@@ -12,10 +12,12 @@ use std::iter;
 use std::path::Path;
 
 mod boot;
+mod firewall;
 mod image;
 mod install;
 mod install_backend;
 mod mkimage;
+mod open;
 mod realmfs;
 mod sync;
 mod update;
@@ -46,6 +48,8 @@ fn main() {
         sync::main(args);
     } else if exe == Path::new("/usr/libexec/citadel-run") {
         do_citadel_run(args);
+    } else if exe == Path::new("/usr/libexec/citadel-open") {
+        open::main(args);
     } else if exe.file_name() == Some(OsStr::new("citadel-mkimage")) {
         mkimage::main(args);
     } else if exe.file_name() == Some(OsStr::new("citadel-tool")) {
@@ -59,6 +63,7 @@ fn dispatch_command(args: Vec<String>) {
     if let Some(command) = args.get(1) {
         match command.as_str() {
             "boot" => boot::main(rebuild_args("citadel-boot", args)),
+            "firewall" => firewall::main(rebuild_args("citadel-firewall", args)),
             "install" => install::main(rebuild_args("citadel-install", args)),
             "image" => image::main(rebuild_args("citadel-image", args)),
             "realmfs" => realmfs::main(rebuild_args("citadel-realmfs", args)),
@@ -66,6 +71,7 @@ fn dispatch_command(args: Vec<String>) {
             "mkimage" => mkimage::main(rebuild_args("citadel-mkimage", args)),
             "sync" => sync::main(rebuild_args("citadel-desktop-sync", args)),
             "run" => do_citadel_run(rebuild_args("citadel-run", args)),
+            "open" => open::main(rebuild_args("citadel-open", args)),
             _ => println!("Error: unknown command {}", command),
         }
     } else {
@@ -80,6 +86,12 @@ fn rebuild_args(command: &str, args: Vec<String>) -> Vec<String> {
 }
 
 fn do_citadel_run(args: Vec<String>) {
+    if args.len() > 2 && args[1] == "--realm" {
+        if let Err(e) = run_in_named_realm(&args[2], &args[3..]) {
+            println!("Running {:?} in realm-{} failed: {}", &args[3..], args[2], e);
+        }
+        return;
+    }
     if let Err(e) = RealmManager::run_in_current(&args[1..], true) {
         println!(
             "RealmManager::run_in_current({:?}) failed: {}",
@@ -88,3 +100,16 @@ fn do_citadel_run(args: Vec<String>) {
         );
     }
 }
+
+// Ask realmsd to run a command in realm `name`, starting the realm first if needed
+fn run_in_named_realm(name: &str, args: &[String]) -> zbus::Result<()> {
+    let connection = zbus::blocking::Connection::system()?;
+    connection.call_method(
+        Some("com.subgraph.realms"),
+        "/com/subgraph/realms",
+        Some("com.subgraph.realms.Manager"),
+        "Run",
+        &(name, args),
+    )?;
+    Ok(())
+}
@@ -1,11 +1,12 @@
 use std::path::PathBuf;
 use std::fs::OpenOptions;
 use std::fs::{self,File};
-use std::io::{self,Write};
+use std::io::{self,BufReader,BufWriter,Write};
 
-use libcitadel::{Result, ImageHeader, devkeys, util};
+use libcitadel::{Result, ImageHeader, ImageDelta, ResourceImage, TrustPolicy, devkeys, util};
 
 use super::config::BuildConfig;
+use super::signing::SigningKey;
 use std::path::Path;
 use libcitadel::verity::Verity;
 
@@ -17,6 +18,13 @@ pub struct UpdateBuilder {
     shasum: Option<String>,
     verity_salt: Option<String>,
     verity_root: Option<String>,
+
+    delta_data: PathBuf,
+    // (shasum, version) of the image a delta is generated against
+    delta_base: Option<(String, u32)>,
+
+    signing_key: Option<SigningKey>,
+    trust_policy: Option<TrustPolicy>,
 }
 
 
@@ -30,10 +38,14 @@ impl UpdateBuilder {
 
     pub fn new(config: BuildConfig) -> UpdateBuilder {
         let image_data = config.workdir_path(UpdateBuilder::build_filename(&config));
+        let delta_data = image_data.with_extension("delta");
         UpdateBuilder {
             config, image_data,
             nblocks: None, shasum: None, verity_salt: None,
             verity_root: None,
+            delta_data, delta_base: None,
+            signing_key: None,
+            trust_policy: None,
         }
     }
 
@@ -41,6 +53,10 @@ impl UpdateBuilder {
         format!("citadel-{}-{}-{:03}.img", self.config.img_name(), self.config.channel(), self.config.version())
     }
 
+    fn delta_filename(&self, base_version: u32) -> String {
+        format!("citadel-{}-{}-{:03}-delta-{:03}.img", self.config.img_name(), self.config.channel(), self.config.version(), base_version)
+    }
+
     fn build_filename(config: &BuildConfig) -> String {
         format!("citadel-{}-{}-{:03}", config.image_type(), config.channel(), config.version())
     }
@@ -50,6 +66,8 @@ impl UpdateBuilder {
     }
 
     pub fn build(&mut self) -> Result<()> {
+        self.load_signing_key()?;
+
         info!("Copying source file to {}", self.image_data.display());
         util::copy_file(self.config.source(), &self.image_data)?;
 
@@ -61,12 +79,45 @@ impl UpdateBuilder {
 
         self.calculate_shasum()?;
 
+        if self.config.delta_base().is_some() {
+            self.generate_delta()
+                .map_err(context!("failed generating delta image"))?;
+        }
+
         self.prepend_empty_block()?;
 
-        self.compress_image()?;
+        self.compress_image(self.image())?;
 
         self.write_final_image()?;
 
+        if self.delta_base.is_some() {
+            self.compress_image(&self.delta_data)?;
+            self.write_final_delta_image()?;
+        }
+
+        Ok(())
+    }
+
+    // Load the signing key before building so that a passphrase is prompted for and
+    // a missing or wrong key is reported before the slow steps of the build.
+    fn load_signing_key(&mut self) -> Result<()> {
+        let channel = self.config.channel();
+        self.trust_policy = self.config.trust_policy()?;
+        if let Some(signing) = self.config.signing() {
+            let key = signing.load_key()?;
+            let key_id = key.public_key().key_id();
+            let trusted = match self.trust_policy {
+                Some(ref policy) => policy.keys().iter().any(|k| k.key_id() == key_id),
+                None => true,
+            };
+            if !trusted {
+                bail!("signing key {} is not one of the keys configured for channel '{}'", key_id, channel);
+            }
+            info!("Signing image with key {}", key_id);
+            self.signing_key = Some(key);
+        } else if channel != "dev" && self.trust_policy.is_some() {
+            bail!("channel '{}' has configured public keys but no [signing] key source is configured", channel);
+        }
         Ok(())
     }
 
@@ -112,6 +163,8 @@ impl UpdateBuilder {
         Ok(())
     }
 
+    // The image data begins with an empty block which the image header is written over
+    // when the image is installed, or after the data is decompressed.
     fn prepend_empty_block(&mut self) -> Result<()> {
         let tmpfile = self.image().with_extension("tmp");
         cmd!("/bin/dd", "if={} of={} bs=4096 seek=1 conv=sparse", self.image().display(), tmpfile.display())?;
@@ -126,7 +179,7 @@ impl UpdateBuilder {
         let output = Verity::generate_initial_hashtree(self.image(), &hashfile)?;
 
         if let Err(err) = fs::write(outfile, output.output()) {
-            bail!("Failed to write veritysetup command output to a file: {}", err);
+            bail!("Failed to write verity format output to a file: {}", err);
         }
 
         let root = match output.root_hash() {
@@ -147,30 +200,93 @@ impl UpdateBuilder {
         Ok(())
     }
 
-    fn compress_image(&self) -> Result<()> {
-        if self.config.compress() {
-            info!("Compressing image data");
-            if let Err(err) = cmd!("xz", "-T0 {}", self.image().display()) {
-                bail!("failed to compress {:?}: {}", self.image(), err);
-            }
-            // Rename back to original image_data filename
-            util::rename(self.image().with_extension("xz"), self.image())?;
+    fn generate_delta(&mut self) -> Result<()> {
+        let base_copy = self.config.workdir_path("delta-base.img");
+        let base_path = self.config.delta_base().expect("no delta base configured");
+        info!("Copying delta base image {} to {}", base_path.display(), base_copy.display());
+        util::copy_file(base_path, &base_copy)?;
+        let result = self.generate_delta_from(&base_copy);
+        util::remove_file(&base_copy)?;
+        result
+    }
+
+    fn generate_delta_from(&mut self, base_path: &Path) -> Result<()> {
+        let base = ResourceImage::from_path(base_path)?;
+        let metainfo = base.metainfo();
+        if metainfo.image_type() != self.config.image_type() {
+            bail!("delta base image has type '{}' but building '{}' image", metainfo.image_type(), self.config.image_type());
+        }
+        if metainfo.realmfs_name() != self.config.realmfs_name() {
+            bail!("delta base image is for a different realmfs");
+        }
+        if metainfo.shasum().is_empty() {
+            bail!("delta base image does not have a shasum field");
+        }
+        if base.is_delta() {
+            bail!("delta base image cannot itself be a delta image");
+        }
+        if base.is_compressed() {
+            base.decompress(false)?;
+        }
+
+        let nblocks = self.nblocks.unwrap();
+        info!("Generating delta against base image version {}", metainfo.version());
+
+        let mut base_file = File::open(base_path)
+            .map_err(context!("could not open delta base image {:?}", base_path))?;
+        let mut target = BufReader::new(File::open(self.image())
+            .map_err(context!("could not open image data file {:?}", self.image()))?);
+        let mut out = BufWriter::new(File::create(&self.delta_data)
+            .map_err(context!("could not create delta data file {:?}", self.delta_data))?);
+
+        if self.config.compression().is_some() {
+            // The compressed data begins with an empty block which the header is written
+            // over after decompression, as in prepend_empty_block()
+            out.write_all(&[0u8; BLOCK_SIZE])
+                .map_err(context!("error writing delta data file {:?}", self.delta_data))?;
+        }
+
+        let ndata = ImageDelta::generate(&mut base_file, ImageHeader::HEADER_SIZE as u64, metainfo.nblocks(), &mut target, nblocks, &mut out)?;
+        out.flush()
+            .map_err(context!("error writing delta data file {:?}", self.delta_data))?;
+
+        info!("Delta stores {} of {} blocks as literal data", ndata, nblocks);
+        self.delta_base = Some((metainfo.shasum().to_string(), metainfo.version()));
+        Ok(())
+    }
+
+    fn compress_image(&self, path: &Path) -> Result<()> {
+        if let Some(compression) = self.config.compression() {
+            info!("Compressing image data {} with {}", path.display(), compression.as_str());
+            compression.compress_file(path)?;
         }
         Ok(())
     }
 
     fn write_final_image(&self) -> Result<()> {
-        let header = self.generate_header()?;
+        let header = self.generate_header(false)?;
         let target = self.config.workdir_path(self.target_filename());
+        self.write_image_file(&header, self.image(), &target)
+    }
+
+    fn write_final_delta_image(&self) -> Result<()> {
+        let base_version = self.delta_base.as_ref().map(|(_,v)| *v).unwrap();
+        let header = self.generate_header(true)?;
+        let target = self.config.workdir_path(self.delta_filename(base_version));
+        self.write_image_file(&header, &self.delta_data, &target)?;
+        util::remove_file(&self.delta_data)
+    }
 
-        let mut out = File::create(&target)
+    fn write_image_file(&self, header: &ImageHeader, data_path: &Path, target: &Path) -> Result<()> {
+
+        let mut out = File::create(target)
             .map_err(context!("could not open output file {:?}", target))?;
 
         header.write_header(&out)
             .map_err(context!("error writing header to {:?}", target))?;
 
-        let mut data = File::open(&self.image())
-            .map_err(context!("could not open image data file {:?}", self.image()))?;
+        let mut data = File::open(data_path)
+            .map_err(context!("could not open image data file {:?}", data_path))?;
 
         io::copy(&mut data, &mut out)
             .map_err(context!("error copying image data to output file"))?;
@@ -178,30 +294,59 @@ impl UpdateBuilder {
         Ok(())
     }
 
-    fn generate_header(&self) -> Result<ImageHeader> {
+    fn generate_header(&self, delta: bool) -> Result<ImageHeader> {
         let hdr = ImageHeader::new();
 
-        if self.config.compress() {
-            hdr.set_flag(ImageHeader::FLAG_DATA_COMPRESSED);
+        if let Some(compression) = self.config.compression() {
+            compression.set_header_flags(&hdr);
+        }
+
+        if delta {
+            hdr.set_flag(ImageHeader::FLAG_DELTA);
         }
 
-        let metainfo = self.generate_metainfo();
-        util::write_file(self.config.workdir_path("metainfo"), &metainfo)?;
+        let metainfo = self.generate_metainfo(delta);
+        let metainfo_file = if delta { "metainfo-delta" } else { "metainfo" };
+        util::write_file(self.config.workdir_path(metainfo_file), &metainfo)?;
         hdr.set_metainfo_bytes(&metainfo)?;
 
-        if self.config.channel() == "dev" {
-            let sig = devkeys().sign(&metainfo);
-            hdr.set_signature(sig.to_bytes());
+        if let Some(ref key) = self.signing_key {
+            let sig = key.sign(&metainfo)?;
+            hdr.add_signature(&key.public_key(), &sig)?;
+        } else if self.config.channel() == "dev" {
+            let keys = devkeys();
+            let sig = keys.sign(&metainfo);
+            hdr.add_signature(&keys.public_key(), sig.to_bytes())?;
         }
+        self.check_signatures(&hdr)?;
         Ok(hdr)
     }
 
-    fn generate_metainfo(&self) -> Vec<u8> {
+    // Refuse to write an image which will not be accepted on systems which have
+    // the public keys configured in mkimage.conf for the channel.
+    fn check_signatures(&self, hdr: &ImageHeader) -> Result<()> {
+        let channel = self.config.channel();
+        let policy = match self.trust_policy {
+            Some(ref policy) => policy,
+            None => return Ok(()),
+        };
+        let count = policy.count_valid_signatures(hdr);
+        if count == 0 {
+            bail!("image is not signed by any public key configured for channel '{}'", channel);
+        }
+        if count < policy.threshold() {
+            warn!("Image carries {} of {} signatures required for channel '{}', add more with citadel-image sign",
+                  count, policy.threshold(), channel);
+        }
+        Ok(())
+    }
+
+    fn generate_metainfo(&self, delta: bool) -> Vec<u8> {
         // writes to Vec can't fail, unwrap once to avoid clutter
-        self._generate_metainfo().unwrap()
+        self._generate_metainfo(delta).unwrap()
     }
 
-    fn _generate_metainfo(&self) -> io::Result<Vec<u8>> {
+    fn _generate_metainfo(&self, delta: bool) -> io::Result<Vec<u8>> {
         assert!(self.verity_salt.is_some() && self.verity_root.is_some(), 
                 "no verity-salt/verity-root in generate_metainfo()");
 
@@ -223,6 +368,11 @@ impl UpdateBuilder {
         writeln!(v, "shasum = \"{}\"", self.shasum.as_ref().unwrap())?;
         writeln!(v, "verity-salt = \"{}\"", self.verity_salt.as_ref().unwrap())?;
         writeln!(v, "verity-root = \"{}\"", self.verity_root.as_ref().unwrap())?;
+        if delta {
+            let (shasum, version) = self.delta_base.as_ref().unwrap();
+            writeln!(v, "delta-base-shasum = \"{}\"", shasum)?;
+            writeln!(v, "delta-base-version = {}", version)?;
+        }
         Ok(v)
     }
 }
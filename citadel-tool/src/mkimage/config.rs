@@ -2,7 +2,9 @@ use std::path::{Path, PathBuf};
 
 use toml;
 
-use libcitadel::{Result, util};
+use libcitadel::{Compression, PublicKey, Result, TrustPolicy, devkeys, util};
+
+use super::signing::SigningConfig;
 
 #[derive(Deserialize)]
 pub struct BuildConfig {
@@ -14,6 +16,7 @@ pub struct BuildConfig {
     source: String,
     #[serde(default)]
     compress: bool,
+    compression: Option<String>,
     #[serde(rename = "kernel-version")]
     kernel_version: Option<String>,
     #[serde(rename = "kernel-id")]
@@ -22,6 +25,18 @@ pub struct BuildConfig {
     #[serde(rename = "realmfs-name")]
     realmfs_name: Option<String>,
 
+    #[serde(rename = "delta-base")]
+    delta_base: Option<String>,
+
+    signing: Option<SigningConfig>,
+
+    #[serde(rename = "channel-keys", default)]
+    channel_keys: Vec<String>,
+    #[serde(rename = "channel-keys-file")]
+    channel_keys_file: Option<String>,
+    #[serde(rename = "signature-threshold")]
+    signature_threshold: Option<usize>,
+
     #[serde(skip)]
     basedir: PathBuf,
     #[serde(skip)]
@@ -72,6 +87,31 @@ impl BuildConfig {
         if self.image_type == "kernel" && self.kernel_version.is_none() {
             bail!("Cannot build 'kernel' image without kernel-version field");
         }
+        if let Some(ref base) = self.delta_base {
+            if itype != "rootfs" && itype != "realmfs" {
+                bail!("Delta images can only be built for 'rootfs' and 'realmfs' image types");
+            }
+            if !Path::new(base).is_file() {
+                bail!("Delta base image '{}' does not exist or is not a regular file", base);
+            }
+        }
+        if let Some(ref name) = self.compression {
+            if Compression::from_name(name).is_none() {
+                bail!("Invalid compression '{}', must be 'xz' or 'zstd'", name);
+            }
+        }
+        if let Some(ref signing) = self.signing {
+            signing.validate(&self.channel)?;
+        }
+        if let Some(ref path) = self.channel_keys_file {
+            if !Path::new(path).is_file() {
+                bail!("Channel keys file '{}' does not exist or is not a regular file", path);
+            }
+        }
+        if self.channel == "dev" && (!self.channel_keys.is_empty() || self.channel_keys_file.is_some()) {
+            bail!("Channel keys cannot be configured for the 'dev' channel, which only accepts the development key");
+        }
+        self.trust_policy()?;
 
         Ok(())
     }
@@ -102,6 +142,49 @@ impl BuildConfig {
         self.realmfs_name.as_ref().map(|s| s.as_str())
     }
 
+    /// Path to a previously built image of the same type. If set, a delta image
+    /// against this base is built in addition to the full image.
+    pub fn delta_base(&self) -> Option<&Path> {
+        self.delta_base.as_ref().map(Path::new)
+    }
+
+    /// Source of the key which signs the image, if configured in a `[signing]` table.
+    pub fn signing(&self) -> Option<&SigningConfig> {
+        self.signing.as_ref()
+    }
+
+    /// Policy which the signatures of images built for the channel must satisfy, or
+    /// `None` if no public keys are configured for the channel.
+    ///
+    /// The keys are listed in `channel-keys` and in the file named by `channel-keys-file`
+    /// with one hex encoded key on each line, and `signature-threshold` is the number of
+    /// them which must sign an image (1 if not set). The `dev` channel always accepts
+    /// only the development key.
+    pub fn trust_policy(&self) -> Result<Option<TrustPolicy>> {
+        if self.channel == "dev" {
+            return Ok(Some(TrustPolicy::single(devkeys().public_key())));
+        }
+        let mut hexkeys = self.channel_keys.clone();
+        if let Some(ref path) = self.channel_keys_file {
+            let content = util::read_to_string(path)?;
+            hexkeys.extend(content.lines()
+                .map(|line| line.trim())
+                .filter(|line| !line.is_empty() && !line.starts_with('#'))
+                .map(String::from));
+        }
+        if hexkeys.is_empty() {
+            return Ok(None);
+        }
+        let mut keys: Vec<PublicKey> = Vec::new();
+        for hex in &hexkeys {
+            let key = PublicKey::from_hex(hex)?;
+            if !keys.iter().any(|k| k.key_id() == key.key_id()) {
+                keys.push(key);
+            }
+        }
+        TrustPolicy::new(keys, self.signature_threshold.unwrap_or(1)).map(Some)
+    }
+
     pub fn version(&self) -> usize {
         self.version
     }
@@ -114,7 +197,13 @@ impl BuildConfig {
         &self.image_type
     }
 
-    pub fn compress(&self) -> bool {
-        self.compress
+    /// Algorithm to compress the image with, or `None` if the image is not compressed.
+    /// Setting `compression` implies `compress = true`, which alone selects xz.
+    pub fn compression(&self) -> Option<Compression> {
+        match self.compression {
+            Some(ref name) => Compression::from_name(name),
+            None if self.compress => Some(Compression::Xz),
+            None => None,
+        }
     }
 }
@@ -5,6 +5,7 @@ use libcitadel::Result;
 
 mod config;
 mod build;
+mod signing;
 
 pub fn main(args: Vec<String>) {
 
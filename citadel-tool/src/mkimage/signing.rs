@@ -0,0 +1,148 @@
+use std::io::Write;
+use std::path::Path;
+use std::process::{Command, Stdio};
+
+use libcitadel::{KeyPair, KeyRing, PublicKey, Result, util};
+
+const SIGNATURE_LENGTH: usize = 64;
+
+/// The `[signing]` table of `mkimage.conf` naming where the key which signs the
+/// image metainfo comes from. Exactly one source must be given:
+///
+///   * `key-file`: a file containing a hex encoded keypair as generated by
+///     `citadel-image genkeys`
+///
+///   * `keyring` and `key-name`: a keypair stored in an encrypted `KeyRing` file. The
+///     passphrase is read from `passphrase-file` if set, otherwise it is prompted for.
+///
+///   * `command` and `public-key`: an external command run with `/bin/sh -c` which
+///     reads the metainfo on stdin and writes a hex encoded signature on stdout, and
+///     the hex encoded public key of the key it signs with.
+///
+/// Images for the `dev` channel are always signed with the development key, so a
+/// `[signing]` table is not allowed for that channel.
+///
+#[derive(Deserialize)]
+pub struct SigningConfig {
+    #[serde(rename = "key-file")]
+    key_file: Option<String>,
+
+    keyring: Option<String>,
+    #[serde(rename = "key-name")]
+    key_name: Option<String>,
+    #[serde(rename = "passphrase-file")]
+    passphrase_file: Option<String>,
+
+    command: Option<String>,
+    #[serde(rename = "public-key")]
+    public_key: Option<String>,
+}
+
+impl SigningConfig {
+    pub fn validate(&self, channel: &str) -> Result<()> {
+        if channel == "dev" {
+            bail!("[signing] cannot be used for the 'dev' channel, which only accepts images signed with the development key");
+        }
+        let sources = [self.key_file.is_some(), self.keyring.is_some(), self.command.is_some()];
+        if sources.iter().filter(|b| **b).count() != 1 {
+            bail!("[signing] must contain exactly one of key-file, keyring or command");
+        }
+        if self.keyring.is_some() && self.key_name.is_none() {
+            bail!("[signing] keyring requires key-name field");
+        }
+        if self.command.is_some() {
+            match self.public_key {
+                Some(ref hex) => { PublicKey::from_hex(hex)?; },
+                None => bail!("[signing] command requires public-key field"),
+            }
+        }
+        for path in self.key_file.iter().chain(self.keyring.iter()).chain(self.passphrase_file.iter()) {
+            if !Path::new(path).is_file() {
+                bail!("Signing key file '{}' does not exist or is not a regular file", path);
+            }
+        }
+        Ok(())
+    }
+
+    /// Load the signing key, prompting for the keyring passphrase if needed.
+    pub fn load_key(&self) -> Result<SigningKey> {
+        if let Some(ref path) = self.key_file {
+            let hex = util::read_to_string(path)?;
+            return KeyPair::from_hex(hex.trim()).map(SigningKey::KeyPair);
+        }
+        if let (Some(path), Some(name)) = (&self.keyring, &self.key_name) {
+            let passphrase = self.keyring_passphrase()?;
+            let keyring = KeyRing::load(path, &passphrase)?;
+            return keyring.get_keypair(name).map(SigningKey::KeyPair);
+        }
+        if let (Some(command), Some(hex)) = (&self.command, &self.public_key) {
+            let public_key = PublicKey::from_hex(hex)?;
+            return Ok(SigningKey::Command { command: command.clone(), public_key });
+        }
+        bail!("no signing key source configured")
+    }
+
+    fn keyring_passphrase(&self) -> Result<String> {
+        match self.passphrase_file {
+            Some(ref path) => {
+                let passphrase = util::read_to_string(path)?;
+                Ok(passphrase.trim_end_matches('\n').to_string())
+            },
+            None => rpassword::read_password_from_tty(Some("Keyring passphrase: "))
+                .map_err(context!("error reading passphrase")),
+        }
+    }
+}
+
+/// A key which signs image metainfo.
+pub enum SigningKey {
+    KeyPair(KeyPair),
+    Command { command: String, public_key: PublicKey },
+}
+
+impl SigningKey {
+    pub fn public_key(&self) -> PublicKey {
+        match self {
+            SigningKey::KeyPair(keypair) => keypair.public_key(),
+            SigningKey::Command { public_key, .. } => public_key.clone(),
+        }
+    }
+
+    /// Sign `data` and return the signature, which is verified against the public key.
+    pub fn sign(&self, data: &[u8]) -> Result<Vec<u8>> {
+        let signature = match self {
+            SigningKey::KeyPair(keypair) => keypair.sign(data).to_bytes().to_vec(),
+            SigningKey::Command { command, .. } => Self::run_sign_command(command, data)?,
+        };
+        if signature.len() != SIGNATURE_LENGTH {
+            bail!("signature has invalid length: {}", signature.len());
+        }
+        if !self.public_key().verify(data, &signature) {
+            bail!("signature does not verify with public key {}", self.public_key().key_id());
+        }
+        Ok(signature)
+    }
+
+    fn run_sign_command(command: &str, data: &[u8]) -> Result<Vec<u8>> {
+        info!("Signing metainfo with command: {}", command);
+        let mut child = Command::new("/bin/sh")
+            .args(["-c", command])
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .spawn()
+            .map_err(context!("failed to run signing command {}", command))?;
+
+        if let Some(mut stdin) = child.stdin.take() {
+            stdin.write_all(data)
+                .map_err(context!("error writing metainfo to signing command"))?;
+        }
+        let output = child.wait_with_output()
+            .map_err(context!("error waiting for signing command"))?;
+        if !output.status.success() {
+            bail!("signing command failed: {}", output.status);
+        }
+        let hex = String::from_utf8_lossy(&output.stdout);
+        hex::decode(hex.trim())
+            .map_err(context!("signing command output is not a hex encoded signature"))
+    }
+}
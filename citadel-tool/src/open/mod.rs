@@ -0,0 +1,182 @@
+use std::ffi::OsStr;
+use std::fs::File;
+use std::os::unix::ffi::OsStrExt;
+use std::os::unix::io::AsRawFd;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use std::sync::Arc;
+
+use libcitadel::{Realm, RealmManager, Result, Logger, LogLevel};
+use zvariant::Fd;
+
+pub mod routes;
+
+use self::routes::OpenRoutes;
+
+const FILE_PATH: &str = "/usr/bin/file";
+const XDG_OPEN_PATH: &str = "/usr/bin/xdg-open";
+
+enum OpenTarget {
+    File(PathBuf),
+    Url(String),
+}
+
+impl OpenTarget {
+    fn parse(arg: &str) -> Result<Self> {
+        if let Some(path) = file_url_path(arg) {
+            Ok(OpenTarget::File(path))
+        } else if Path::new(arg).exists() {
+            Ok(OpenTarget::File(PathBuf::from(arg)))
+        } else if arg.contains("://") || arg.starts_with("mailto:") {
+            Ok(OpenTarget::Url(arg.to_string()))
+        } else {
+            bail!("no such file or URL: {}", arg)
+        }
+    }
+}
+
+/// Open files and URLs in the realm chosen by the routing rules.
+///
+/// Starting the realm and copying files into it require root, so realmsd is asked to
+/// do both, in the same way `citadel-run --realm` runs commands in a realm.
+struct RealmOpener {
+    manager: Arc<RealmManager>,
+    routes: OpenRoutes,
+}
+
+impl RealmOpener {
+    fn new() -> Result<Self> {
+        let manager = RealmManager::load()?;
+        let routes = OpenRoutes::load()?;
+        Ok(RealmOpener { manager, routes })
+    }
+
+    fn open(&self, realm_name: Option<&str>, arg: &str) -> Result<()> {
+        let target = OpenTarget::parse(arg)?;
+        let realm = match realm_name {
+            Some(name) => self.realm_by_name(name)?,
+            None => self.route(&target)?,
+        };
+        info!("Opening {} in realm-{}", arg, realm.name());
+        match target {
+            OpenTarget::File(ref path) => open_file_in_realm(realm.name(), path),
+            OpenTarget::Url(url) => crate::run_in_named_realm(realm.name(), &[XDG_OPEN_PATH.to_string(), url])
+                .map_err(context!("failed to ask realmsd to open URL in realm-{}", realm.name())),
+        }
+    }
+
+    fn realm_by_name(&self, name: &str) -> Result<Realm> {
+        self.manager.realm_by_name(name)
+            .ok_or_else(|| format_err!("realm '{}' does not exist", name))
+    }
+
+    // Choose a realm with the routing rules, falling back to the default realm of
+    // the rules and then the current realm
+    fn route(&self, target: &OpenTarget) -> Result<Realm> {
+        let routed = match target {
+            OpenTarget::File(path) => {
+                let mime_type = file_mime_type(path)?;
+                verbose!("MIME type of {:?} is {}", path, mime_type);
+                self.routes.realm_for_mime_type(&mime_type)
+            },
+            OpenTarget::Url(url) => self.routes.realm_for_url(url),
+        };
+        if let Some(name) = routed.or_else(|| self.routes.default_realm()) {
+            return self.realm_by_name(name);
+        }
+        self.manager.current_realm()
+            .ok_or_else(|| format_err!("no routing rule matches and there is no current realm"))
+    }
+}
+
+// Ask realmsd to copy file `path` into realm `name` and open it, starting the realm first
+// if needed. The file is passed as an open descriptor so that realmsd only reads files
+// which the caller is able to open.
+fn open_file_in_realm(name: &str, path: &Path) -> Result<()> {
+    let filename = path.file_name()
+        .and_then(|s| s.to_str())
+        .ok_or_else(|| format_err!("path {:?} has no filename component", path))?;
+    let file = File::open(path)
+        .map_err(context!("failed to open {:?}", path))?;
+    let connection = zbus::blocking::Connection::system()
+        .map_err(context!("failed to connect to system bus"))?;
+    connection.call_method(
+        Some("com.subgraph.realms"),
+        "/com/subgraph/realms",
+        Some("com.subgraph.realms.Manager"),
+        "OpenFile",
+        &(name, filename, Fd::from(file.as_raw_fd())),
+    ).map_err(context!("failed to ask realmsd to open {:?} in realm-{}", path, name))?;
+    Ok(())
+}
+
+fn file_mime_type(path: &Path) -> Result<String> {
+    let output = Command::new(FILE_PATH)
+        .args(["--brief", "--mime-type"])
+        .arg(path)
+        .output()
+        .map_err(context!("failed to execute {}", FILE_PATH))?;
+    if !output.status.success() {
+        bail!("{} failed to determine the MIME type of {:?}", FILE_PATH, path);
+    }
+    Ok(String::from_utf8_lossy(&output.stdout).trim().to_string())
+}
+
+// Convert a `file://` URL into a local path, decoding percent escapes
+fn file_url_path(url: &str) -> Option<PathBuf> {
+    let rest = url.strip_prefix("file://")?;
+    let path = rest.strip_prefix("localhost").unwrap_or(rest);
+    if !path.starts_with('/') {
+        return None;
+    }
+    let bytes = path.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        let escaped = bytes.get(i + 1..i + 3)
+            .filter(|_| bytes[i] == b'%')
+            .and_then(|hex| std::str::from_utf8(hex).ok())
+            .and_then(|hex| u8::from_str_radix(hex, 16).ok());
+        match escaped {
+            Some(b) => { decoded.push(b); i += 3; },
+            None => { decoded.push(bytes[i]); i += 1; },
+        }
+    }
+    Some(PathBuf::from(OsStr::from_bytes(&decoded)))
+}
+
+pub fn main(args: Vec<String>) {
+    Logger::set_log_level(LogLevel::Info);
+
+    let (realm, targets) = if args.len() > 2 && args[1] == "--realm" {
+        (Some(args[2].as_str()), &args[3..])
+    } else {
+        (None, &args[1..])
+    };
+
+    if targets.is_empty() {
+        println!("Usage: citadel-open [--realm NAME] FILE|URL...");
+        return;
+    }
+
+    let opener = match RealmOpener::new() {
+        Ok(opener) => opener,
+        Err(e) => {
+            println!("Error loading realms or routing rules: {}", e);
+            return;
+        }
+    };
+
+    for target in targets {
+        if let Err(e) = opener.open(realm, target) {
+            println!("Error opening {}: {}", target, e);
+        }
+    }
+}
+
+#[test]
+fn test_file_url_path() {
+    assert_eq!(file_url_path("file:///home/citadel/My%20File.pdf"), Some(PathBuf::from("/home/citadel/My File.pdf")));
+    assert_eq!(file_url_path("file://localhost/tmp/a%2"), Some(PathBuf::from("/tmp/a%2")));
+    assert_eq!(file_url_path("https://example.com/"), None);
+}
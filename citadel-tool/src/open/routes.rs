@@ -0,0 +1,222 @@
+use std::path::Path;
+
+use libcitadel::{Result, util};
+
+/// Rules which choose the realm a file or URL is opened in.
+///
+/// Rules are read from `/storage/realms/open-routes` and are checked in the order
+/// they appear, so more specific rules should be listed first:
+///
+/// ```toml
+///     default-realm = "main"
+///
+///     [[route]]
+///     domain = "github.com"
+///     realm = "dev"
+///
+///     [[route]]
+///     scheme = "https"
+///     realm = "browser"
+///
+///     [[route]]
+///     mime-type = "image/*"
+///     realm = "media"
+/// ```
+///
+/// A `domain` rule also matches subdomains and, if no `scheme` is given, only
+/// matches `http` and `https` URLs.
+#[derive(Deserialize,Default)]
+#[serde(rename_all = "kebab-case")]
+pub struct OpenRoutes {
+    default_realm: Option<String>,
+    #[serde(default, rename = "route")]
+    routes: Vec<Route>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "kebab-case")]
+struct Route {
+    realm: String,
+    mime_type: Option<String>,
+    scheme: Option<String>,
+    domain: Option<String>,
+}
+
+impl Route {
+    fn matches_mime_type(&self, mime_type: &str) -> bool {
+        match self.mime_type {
+            Some(ref pattern) => match pattern.strip_suffix("/*") {
+                Some(prefix) => mime_type.split('/').next() == Some(prefix),
+                None => pattern == mime_type,
+            },
+            None => false,
+        }
+    }
+
+    fn matches_url(&self, scheme: &str, host: Option<&str>) -> bool {
+        if self.mime_type.is_some() {
+            return false;
+        }
+        let scheme_matches = match self.scheme {
+            Some(ref s) => s.eq_ignore_ascii_case(scheme),
+            None => scheme == "http" || scheme == "https",
+        };
+        let domain_matches = match self.domain {
+            Some(ref domain) => host.is_some_and(|host| {
+                host == domain || host.ends_with(&format!(".{}", domain))
+            }),
+            None => true,
+        };
+        scheme_matches && domain_matches
+    }
+
+    // The scheme handler types which must be routed to citadel-open for this rule to be applied
+    fn scheme_types(&self) -> Vec<String> {
+        match (&self.scheme, &self.domain) {
+            (Some(scheme), _) => vec![scheme_type(scheme)],
+            (None, Some(_)) => vec![scheme_type("http"), scheme_type("https")],
+            (None, None) => Vec::new(),
+        }
+    }
+}
+
+impl OpenRoutes {
+    pub const ROUTES_PATH: &'static str = "/storage/realms/open-routes";
+
+    /// Load the routing rules, or an empty set of rules if no rules file exists.
+    pub fn load() -> Result<Self> {
+        let path = Path::new(Self::ROUTES_PATH);
+        if !path.exists() {
+            return Ok(Self::default());
+        }
+        let s = util::read_to_string(path)?;
+        Self::parse(&s)
+            .map_err(|e| format_err!("failed to parse routes file {:?}: {}", path, e))
+    }
+
+    fn parse(s: &str) -> Result<Self> {
+        let routes = toml::from_str::<OpenRoutes>(s)
+            .map_err(|e| format_err!("{}", e))?;
+        for route in &routes.routes {
+            if route.mime_type.is_some() && (route.scheme.is_some() || route.domain.is_some()) {
+                bail!("route to realm '{}' cannot match both a MIME type and a URL", route.realm);
+            }
+            if route.mime_type.is_none() && route.scheme.is_none() && route.domain.is_none() {
+                bail!("route to realm '{}' has no mime-type, scheme or domain", route.realm);
+            }
+        }
+        Ok(routes)
+    }
+
+    /// Realm to open URLs and files in when no rule matches.
+    pub fn default_realm(&self) -> Option<&str> {
+        self.default_realm.as_deref()
+    }
+
+    /// Realm of the first rule matching `mime_type`.
+    pub fn realm_for_mime_type(&self, mime_type: &str) -> Option<&str> {
+        if let Some(scheme) = mime_type.strip_prefix("x-scheme-handler/") {
+            return self.realm_for_scheme(scheme);
+        }
+        self.routes.iter()
+            .find(|r| r.matches_mime_type(mime_type))
+            .map(|r| r.realm.as_str())
+    }
+
+    /// Realm of the first rule matching the scheme and host of `url`.
+    pub fn realm_for_url(&self, url: &str) -> Option<&str> {
+        let (scheme, host) = parse_url(url)?;
+        self.routes.iter()
+            .find(|r| r.matches_url(&scheme, host))
+            .map(|r| r.realm.as_str())
+    }
+
+    // Realm of the first rule for `scheme` which does not depend on the domain
+    fn realm_for_scheme(&self, scheme: &str) -> Option<&str> {
+        self.routes.iter()
+            .find(|r| r.domain.is_none() && r.matches_url(scheme, None))
+            .map(|r| r.realm.as_str())
+    }
+
+    /// Names of all realms which have at least one rule.
+    pub fn realms(&self) -> Vec<&str> {
+        let mut realms = Vec::new();
+        for route in &self.routes {
+            if !realms.contains(&route.realm.as_str()) {
+                realms.push(route.realm.as_str());
+            }
+        }
+        realms
+    }
+
+    /// MIME types and scheme handler types (`x-scheme-handler/SCHEME`) routed to
+    /// `realm` by a rule without a wildcard.
+    pub fn types_for_realm(&self, realm: &str) -> Vec<String> {
+        let mut types = Vec::new();
+        for route in self.routes.iter().filter(|r| r.realm == realm) {
+            match route.mime_type {
+                Some(ref t) if !t.ends_with("/*") => types.push(t.clone()),
+                Some(_) => {},
+                None => types.extend(route.scheme_types()),
+            }
+        }
+        types
+    }
+
+    /// Return `true` if URLs with scheme `scheme` must be passed to citadel-open
+    /// because some rule depends on the domain of the URL.
+    pub fn has_domain_rules(&self, scheme: &str) -> bool {
+        self.routes.iter()
+            .any(|r| r.domain.is_some() && r.scheme_types().contains(&scheme_type(scheme)))
+    }
+}
+
+fn scheme_type(scheme: &str) -> String {
+    format!("x-scheme-handler/{}", scheme.to_ascii_lowercase())
+}
+
+// Split `url` into the lowercase scheme and the host if the URL has an authority component
+fn parse_url(url: &str) -> Option<(String, Option<&str>)> {
+    let (scheme, rest) = url.split_once(':')?;
+    if scheme.is_empty() || !scheme.chars().all(|c| c.is_ascii_alphanumeric() || "+-.".contains(c)) {
+        return None;
+    }
+    let host = rest.strip_prefix("//").map(|authority| {
+        let authority = authority.split(['/', '?', '#']).next().unwrap_or("");
+        let host = authority.rsplit('@').next().unwrap_or("");
+        host.split(':').next().unwrap_or("")
+    }).filter(|host| !host.is_empty());
+    Some((scheme.to_ascii_lowercase(), host))
+}
+
+#[test]
+fn test_route_matching() {
+    let routes = OpenRoutes::parse(r#"
+        default-realm = "main"
+
+        [[route]]
+        domain = "github.com"
+        realm = "dev"
+
+        [[route]]
+        scheme = "https"
+        realm = "browser"
+
+        [[route]]
+        mime-type = "image/*"
+        realm = "media"
+    "#).unwrap();
+
+    assert_eq!(routes.realm_for_url("https://gist.github.com/foo"), Some("dev"));
+    assert_eq!(routes.realm_for_url("https://user@github.com:443/"), Some("dev"));
+    assert_eq!(routes.realm_for_url("https://notgithub.com/"), Some("browser"));
+    assert_eq!(routes.realm_for_url("mailto:someone@github.com"), None);
+    assert_eq!(routes.realm_for_mime_type("image/png"), Some("media"));
+    assert_eq!(routes.realm_for_mime_type("x-scheme-handler/https"), Some("browser"));
+    assert_eq!(routes.realm_for_mime_type("text/plain"), None);
+    assert_eq!(routes.default_realm(), Some("main"));
+    assert_eq!(routes.types_for_realm("dev"), vec!["x-scheme-handler/http", "x-scheme-handler/https"]);
+    assert!(routes.has_domain_rules("http"));
+
+    assert!(OpenRoutes::parse("[[route]]\nrealm = \"main\"\n").is_err());
+}
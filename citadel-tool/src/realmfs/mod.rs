@@ -1,7 +1,7 @@
 use clap::App;
 use clap::ArgMatches;
 
-use libcitadel::{Result,RealmFS,Logger,LogLevel};
+use libcitadel::{Result,RealmFS,RealmManager,Logger,LogLevel};
 use libcitadel::util::is_euid_root;
 use clap::SubCommand;
 use clap::AppSettings::*;
@@ -57,6 +57,32 @@ is the final absolute size of the image.")
                 .help("Path or name of RealmFS image")
                 .required(true)))
 
+        .subcommand(SubCommand::with_name("generations")
+            .about("List saved previous generations of a RealmFS image")
+            .arg(Arg::with_name("image")
+                .help("Path or name of RealmFS image")
+                .required(true)))
+
+        .subcommand(SubCommand::with_name("diff")
+            .about("Show metainfo fields which differ between a saved generation and the current image or another generation")
+            .arg(Arg::with_name("image")
+                .help("Path or name of RealmFS image")
+                .required(true))
+            .arg(Arg::with_name("generation")
+                .help("Generation number to compare")
+                .required(true))
+            .arg(Arg::with_name("other")
+                .help("Generation number to compare against instead of the current image")))
+
+        .subcommand(SubCommand::with_name("rollback")
+            .about("Replace a RealmFS image with a saved previous generation")
+            .arg(Arg::with_name("image")
+                .help("Name of RealmFS image to roll back")
+                .required(true))
+            .arg(Arg::with_name("generation")
+                .help("Generation number to roll back to")
+                .default_value("0")))
+
         .subcommand(SubCommand::with_name("activate")
             .about("Activate a RealmFS by creating a block device for the image and mounting it.")
             .arg(Arg::with_name("image")
@@ -80,6 +106,9 @@ is the final absolute size of the image.")
         ("autoresize", Some(m)) => autoresize(m),
         ("fork", Some(m)) => fork(m),
         ("update", Some(m)) => update(m),
+        ("generations", Some(m)) => generations(m),
+        ("diff", Some(m)) => diff(m),
+        ("rollback", Some(m)) => rollback(m),
         ("activate", Some(m)) => activate(m),
         ("deactivate", Some(m)) => deactivate(m),
         _ => image_info(&matches),
@@ -188,6 +217,73 @@ fn update(arg_matches: &ArgMatches) -> Result<()> {
     Ok(())
 }
 
+fn generation_arg(arg_matches: &ArgMatches, name: &str) -> Result<usize> {
+    let arg = arg_matches.value_of(name).unwrap_or("0");
+    arg.parse::<usize>()
+        .map_err(|_| format_err!("Unable to parse generation number '{}'", arg))
+}
+
+fn generations(arg_matches: &ArgMatches) -> Result<()> {
+    let img = realmfs_image(arg_matches)?;
+    let generations = img.generations()?;
+    if generations.is_empty() {
+        info!("RealmFS image {} has no saved generations", img.name());
+    }
+    let current = img.metainfo();
+    println!("current  {}  {} blocks  {}", current.verity_tag(), current.nblocks(), img.path().display());
+    for generation in generations {
+        let metainfo = generation.metainfo();
+        println!("{:<7}  {}  {} blocks  {}", generation.index(), metainfo.verity_tag(), metainfo.nblocks(), generation.path().display());
+    }
+    Ok(())
+}
+
+fn diff(arg_matches: &ArgMatches) -> Result<()> {
+    let img = realmfs_image(arg_matches)?;
+    let generation = img.generation(generation_arg(arg_matches, "generation")?)?;
+
+    let (other_label, changes) = if arg_matches.is_present("other") {
+        let other = img.generation(generation_arg(arg_matches, "other")?)?;
+        (format!("generation {}", other.index()), generation.diff_metainfo(other.header()))
+    } else {
+        ("current".to_string(), generation.diff_metainfo(img.header()))
+    };
+
+    if changes.is_empty() {
+        println!("No differences in metainfo");
+        return Ok(());
+    }
+    println!("--- generation {}", generation.index());
+    println!("+++ {}", other_label);
+    for (field, ours, theirs) in changes {
+        if let Some(v) = ours {
+            println!("-{} = {}", field, v);
+        }
+        if let Some(v) = theirs {
+            println!("+{} = {}", field, v);
+        }
+    }
+    Ok(())
+}
+
+fn rollback(arg_matches: &ArgMatches) -> Result<()> {
+    if !is_euid_root() {
+        bail!("RealmFS rollback must be run as root");
+    }
+    let name = arg_matches.value_of("image").unwrap();
+    let index = generation_arg(arg_matches, "generation")?;
+
+    // Load through the manager so that running realms can be checked for use of the image
+    let manager = RealmManager::load()?;
+    let img = match manager.realmfs_by_name(name) {
+        Some(img) => img,
+        None => bail!("No RealmFS image named '{}' found", name),
+    };
+    img.rollback(index)?;
+    info!("RealmFS image {} rolled back to generation {}", name, index);
+    Ok(())
+}
+
 fn activate(arg_matches: &ArgMatches) -> Result<()> {
     let img = realmfs_image(arg_matches)?;
     let img_arg = arg_matches.value_of("image").unwrap();
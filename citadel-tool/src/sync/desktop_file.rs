@@ -74,6 +74,13 @@ impl DesktopFile {
         self.get_key_val("Icon")
     }
 
+    pub fn mime_types(&self) -> Vec<&str> {
+        match self.get_key_val("MimeType") {
+            Some(val) => val.split_terminator(';').map(|s| s.trim()).filter(|s| !s.is_empty()).collect(),
+            None => Vec::new(),
+        }
+    }
+
     fn show_in_gnome(&self) -> bool {
         if self.key_exists("NotShowIn") && self.key_value_contains("NotShowIn", "GNOME") {
             return false;
@@ -7,8 +7,12 @@ use libcitadel::{Realm, Realms, Result, util};
 use crate::sync::parser::DesktopFileParser;
 use std::fs::DirEntry;
 use crate::sync::icons::IconSync;
+use crate::sync::mime_handlers::MimeHandlers;
 
-/// Synchronize dot-desktop files from active realm to a target directory in Citadel.
+/// Synchronize dot-desktop files from an active realm to a target directory in Citadel.
+///
+/// Synchronized files are named with a `realm-NAME.` prefix and launch the application
+/// in realm `NAME` through realmsd.
 pub struct DesktopFileSync {
     realm: Realm,
     items: HashSet<DesktopItem>,
@@ -44,15 +48,70 @@ impl DesktopItem {
 impl DesktopFileSync {
     pub const CITADEL_APPLICATIONS: &'static str = "/home/citadel/.local/share/applications";
 
+    /// Directories of a realm containing desktop files, relative to the realm run path.
+    pub const APPLICATION_DIRS: &'static [&'static str] = &["rootfs/usr/share/applications", "home/.local/share/applications"];
+
+    /// Synchronize the desktop files of every active realm and remove the files of
+    /// realms which are no longer running.
     pub fn sync_active_realms() -> Result<()> {
         let realms = Realms::load()?;
-        for realm in realms.active(true) {
+        let active = realms.active(true);
+        let names = active.iter()
+            .map(|r| r.name().to_string())
+            .collect::<HashSet<_>>();
+
+        Self::remove_target_files_except(&names)?;
+
+        for realm in active {
+            let name = realm.name().to_string();
             let mut sync = DesktopFileSync::new(realm);
-            sync.run_sync(false)?;
+            if let Err(e) = sync.run_sync(false) {
+                warn!("Error synchronizing desktop files from realm-{}: {}", name, e);
+            }
+        }
+        Ok(())
+    }
+
+    /// Remove all synchronized desktop files of the realm `name`.
+    pub fn remove_realm_files(name: &str) -> Result<()> {
+        let target = Path::new(Self::CITADEL_APPLICATIONS);
+        if !target.exists() {
+            return Ok(());
         }
+        util::read_directory(target, |dent| {
+            if Self::target_file_realm(dent).as_deref() == Some(name) {
+                util::remove_file(dent.path())?;
+            }
+            Ok(())
+        })?;
+        Self::update_mime_handlers();
         Ok(())
     }
 
+    // Remove the synchronized desktop files of all realms not listed in `names`
+    fn remove_target_files_except(names: &HashSet<String>) -> Result<()> {
+        let target = Path::new(Self::CITADEL_APPLICATIONS);
+        if !target.exists() {
+            return Ok(());
+        }
+        util::read_directory(target, |dent| {
+            if let Some(realm) = Self::target_file_realm(dent) {
+                if !names.contains(&realm) {
+                    verbose!("Removing desktop entry of realm which is not running: {:?}", dent.path());
+                    util::remove_file(dent.path())?;
+                }
+            }
+            Ok(())
+        })
+    }
+
+    /// Return the realm name from the `realm-NAME.` prefix of a target filename
+    pub fn target_file_realm(dent: &DirEntry) -> Option<String> {
+        let filename = dent.file_name();
+        let name = filename.to_str()?.strip_prefix("realm-")?.split('.').next()?;
+        Some(name.to_string())
+    }
+
     pub fn new_current() -> Option<Self> {
         Realms::load_current_realm()
             .filter(|r| r.is_active())
@@ -72,20 +131,87 @@ impl DesktopFileSync {
 
     pub fn run_sync(&mut self, clear: bool) -> Result<()> {
 
-        self.collect_source_files("rootfs/usr/share/applications")?;
-        self.collect_source_files("home/.local/share/applications")?;
+        for dir in Self::APPLICATION_DIRS {
+            self.collect_source_files(dir)?;
+        }
 
         let target = Path::new(Self::CITADEL_APPLICATIONS);
 
         util::create_dir(&target)?;
 
         if clear {
-            Self::clear_target_files()?;
+            Self::remove_realm_files(self.realm.name())?;
         } else {
             self.remove_missing_target_files()?;
         }
 
         self.synchronize_items()?;
+        Self::update_mime_handlers();
+        self.write_icon_cache()
+    }
+
+    // Handlers are regenerated after every change to the synchronized desktop files.
+    // Failing to write them does not fail the sync itself.
+    fn update_mime_handlers() {
+        if let Err(e) = MimeHandlers::update() {
+            warn!("Error updating MIME type handlers: {}", e);
+        }
+    }
+
+    pub fn realm(&self) -> &Realm {
+        &self.realm
+    }
+
+    /// Synchronize desktop file `path` of the realm after it was created, changed or removed.
+    pub fn sync_changed_file(&mut self, path: &Path) -> Result<()> {
+        if path.extension() != Some(OsStr::new("desktop")) {
+            return Ok(());
+        }
+        self.items.retain(|item| item.path != path);
+
+        let filename = path.file_name().map(|s| s.to_string_lossy().to_string()).unwrap_or_default();
+        let target = Path::new(Self::CITADEL_APPLICATIONS)
+            .join(format!("realm-{}.{}", self.realm.name(), filename));
+        if target.exists() {
+            util::remove_file(&target)?;
+        }
+
+        // A file with the same name may remain in the other applications directory
+        let item = match Self::mtime(path) {
+            Some(mtime) => Some(DesktopItem::new(path.to_path_buf(), mtime)),
+            None => self.items.iter()
+                .find(|item| item.filename() == OsStr::new(&filename))
+                .map(|item| DesktopItem::new(item.path.clone(), item.mtime)),
+        };
+
+        if let Some(item) = item {
+            util::create_dir(Self::CITADEL_APPLICATIONS)?;
+            self.sync_item(&item)?;
+            self.items.insert(item);
+        }
+        Self::update_mime_handlers();
+        self.write_icon_cache()
+    }
+
+    /// Synchronize icon `icon_name` after it changed in the realm if any desktop file
+    /// of the realm uses it.
+    pub fn sync_changed_icon(&self, icon_name: &str) -> Result<()> {
+        let icons = match self.icons {
+            Some(ref icons) => icons,
+            None => return Ok(()),
+        };
+        let used = self.items.iter().any(|item| {
+            DesktopFileParser::parse_from_path(&item.path, "")
+                .map(|dfp| dfp.icon() == Some(icon_name))
+                .unwrap_or(false)
+        });
+        if used {
+            icons.resync_icon(icon_name)?;
+        }
+        self.write_icon_cache()
+    }
+
+    fn write_icon_cache(&self) -> Result<()> {
         if let Some(ref icons) = self.icons {
             icons.write_known_cache()?;
         }
@@ -93,7 +219,7 @@ impl DesktopFileSync {
     }
 
     fn collect_source_files(&mut self,  directory: impl AsRef<Path>) -> Result<()> {
-        let directory = Realms::current_realm_symlink().join(directory.as_ref());
+        let directory = self.realm.run_path().join(directory.as_ref());
         if directory.exists() {
             util::read_directory(&directory, |dent| {
                 self.process_source_entry(dent);
@@ -112,12 +238,6 @@ impl DesktopFileSync {
         }
     }
 
-    pub fn clear_target_files() -> Result<()> {
-        util::read_directory(Self::CITADEL_APPLICATIONS, |dent| {
-            util::remove_file(dent.path())
-        })
-    }
-
     fn remove_missing_target_files(&mut self) -> Result<()> {
         let sources = self.source_filenames();
         let prefix = format!("realm-{}.", self.realm.name());
@@ -151,7 +271,10 @@ impl DesktopFileSync {
 
     fn synchronize_items(&self) -> Result<()> {
         for item in &self.items {
-            let target = Path::new(Self::CITADEL_APPLICATIONS).join(item.filename());
+            let target = match self.item_realm_filename(item) {
+                Some(filename) => Path::new(Self::CITADEL_APPLICATIONS).join(filename),
+                None => continue,
+            };
             if item.is_newer_than(&target) {
                 if let Err(e) = self.sync_item(item) {
                     warn!("Error synchronizing desktop file {:?} from realm-{}: {}", item.filename(), self.realm.name(), e);
@@ -162,7 +285,8 @@ impl DesktopFileSync {
     }
 
     fn sync_item(&self, item: &DesktopItem) -> Result<()> {
-        let dfp = DesktopFileParser::parse_from_path(&item.path, "/usr/libexec/citadel-run ")?;
+        let exec_prefix = format!("/usr/libexec/citadel-run --realm {} ", self.realm.name());
+        let dfp = DesktopFileParser::parse_from_path(&item.path, &exec_prefix)?;
         if dfp.is_showable() {
             dfp.write_to_dir(Self::CITADEL_APPLICATIONS, Some(&self.realm))?;
             if let Some(icon_name)= dfp.icon() {
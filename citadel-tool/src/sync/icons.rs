@@ -17,8 +17,11 @@ impl IconSync {
     const KNOWN_ICONS_FILE: &'static str = "/home/citadel/.local/share/icons/known.cache";
     const PAPER_ICON_CACHE: &'static str = "/usr/share/icons/Paper/icon-theme.cache";
 
+    /// Icon theme directories of a realm searched for icons, relative to the realm run path.
+    pub const ICON_DIRS: &'static [&'static str] = &["rootfs/usr/share/icons/hicolor", "home/.local/share/icons/hicolor"];
+
     pub fn new(realm: &Realm) -> Result<Self> {
-        let realm_base= realm.base_path();
+        let realm_base= realm.run_path();
         let cache = IconCache::open(Self::PAPER_ICON_CACHE)?;
         let known = Self::read_known_cache()?;
         let known = RefCell::new(known);
@@ -36,8 +39,20 @@ impl IconSync {
             return Ok(());
         }
 
-        if !self.search("rootfs/usr/share/icons/hicolor", icon_name)? {
-            self.search("home/.local/share/icons/hicolor", icon_name)?;
+        self.search_realm(icon_name)
+    }
+
+    /// Copy icon `icon_name` from the realm again after it has changed, even if it was
+    /// synchronized before.
+    pub fn resync_icon(&self, icon_name: &str) -> Result<()> {
+        self.search_realm(icon_name)
+    }
+
+    fn search_realm(&self, icon_name: &str) -> Result<()> {
+        for dir in Self::ICON_DIRS {
+            if self.search(dir, icon_name)? {
+                break;
+            }
         }
         Ok(())
     }
@@ -0,0 +1,300 @@
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt::Write;
+use std::path::Path;
+
+use libcitadel::{Result, util};
+
+use crate::open::routes::OpenRoutes;
+use crate::sync::desktop_file::{DesktopFile, Line};
+use crate::sync::desktop_sync::DesktopFileSync;
+use crate::sync::parser::DesktopFileParser;
+
+const CITADEL_OPEN: &str = "/usr/libexec/citadel-open";
+
+/// Host side handlers which open files and URLs in realms with `citadel-open`.
+///
+/// A `citadel-open-NAME.desktop` handler is written for each realm which has synchronized
+/// desktop files declaring a `MimeType` or which is the target of a routing rule, so that
+/// files can be opened in that realm from the "Open With" menu. MIME types and URL
+/// schemes matched by a rule in `OpenRoutes` are assigned to `citadel-open.desktop`
+/// in the user's `mimeapps.list` which chooses the realm with the rules. Entries naming
+/// any other handler are left as they are in `mimeapps.list`.
+pub struct MimeHandlers {
+    routes: OpenRoutes,
+    // MIME types of desktop files synchronized from each realm and types routed to the realm
+    realm_types: BTreeMap<String, BTreeSet<String>>,
+}
+
+impl MimeHandlers {
+    const HANDLER_NAME: &'static str = "citadel-open.desktop";
+    const HANDLER_PREFIX: &'static str = "citadel-open-";
+    const MIMEAPPS_LIST: &'static str = "/home/citadel/.config/mimeapps.list";
+    const DEFAULT_APPLICATIONS: &'static str = "[Default Applications]";
+    const ADDED_ASSOCIATIONS: &'static str = "[Added Associations]";
+
+    /// Regenerate all handlers and the generated `mimeapps.list` entries from the synchronized desktop files.
+    pub fn update() -> Result<()> {
+        let mut handlers = MimeHandlers {
+            routes: OpenRoutes::load()?,
+            realm_types: BTreeMap::new(),
+        };
+        handlers.collect_types()?;
+        handlers.remove_handlers()?;
+        handlers.write_handlers()?;
+        handlers.write_mimeapps_list()
+    }
+
+    fn collect_types(&mut self) -> Result<()> {
+        let target = Path::new(DesktopFileSync::CITADEL_APPLICATIONS);
+        if target.exists() {
+            util::read_directory(target, |dent| {
+                if let Some(realm) = DesktopFileSync::target_file_realm(dent) {
+                    match DesktopFileParser::parse_from_path(dent.path(), "") {
+                        Ok(dfp) => self.add_types(&realm, dfp.mime_types()),
+                        Err(e) => warn!("Error reading synchronized desktop file {:?}: {}", dent.path(), e),
+                    }
+                }
+                Ok(())
+            })?;
+        }
+        for realm in self.routes.realms() {
+            let types = self.routes.types_for_realm(realm);
+            self.realm_types.entry(realm.to_string()).or_default().extend(types);
+        }
+        Ok(())
+    }
+
+    fn add_types<S: AsRef<str>>(&mut self, realm: &str, types: Vec<S>) {
+        let entry = self.realm_types.entry(realm.to_string()).or_default();
+        entry.extend(types.iter().map(|t| t.as_ref().to_string()));
+    }
+
+    fn handler_name(realm: &str) -> String {
+        format!("{}{}.desktop", Self::HANDLER_PREFIX, realm)
+    }
+
+    fn is_handler_name(filename: &str) -> bool {
+        filename == Self::HANDLER_NAME ||
+            (filename.starts_with(Self::HANDLER_PREFIX) && filename.ends_with(".desktop"))
+    }
+
+    // Types which are opened with citadel-open by default
+    fn routed_types(&self) -> BTreeSet<&str> {
+        self.realm_types.values()
+            .flatten()
+            .map(|t| t.as_str())
+            .filter(|t| self.is_routed(t))
+            .collect()
+    }
+
+    fn is_routed(&self, mime_type: &str) -> bool {
+        if let Some(scheme) = mime_type.strip_prefix("x-scheme-handler/") {
+            if self.routes.has_domain_rules(scheme) {
+                return true;
+            }
+        }
+        self.routes.realm_for_mime_type(mime_type).is_some()
+    }
+
+    fn remove_handlers(&self) -> Result<()> {
+        let target = Path::new(DesktopFileSync::CITADEL_APPLICATIONS);
+        if !target.exists() {
+            return Ok(());
+        }
+        util::read_directory(target, |dent| {
+            if let Some(filename) = dent.file_name().to_str() {
+                if Self::is_handler_name(filename) {
+                    util::remove_file(dent.path())?;
+                }
+            }
+            Ok(())
+        })
+    }
+
+    fn write_handlers(&self) -> Result<()> {
+        util::create_dir(DesktopFileSync::CITADEL_APPLICATIONS)?;
+
+        for (realm, types) in &self.realm_types {
+            if types.is_empty() {
+                continue;
+            }
+            let handler = Self::handler_file(
+                &Self::handler_name(realm),
+                &format!("Open in realm-{}", realm),
+                &format!("{} --realm {} %U", CITADEL_OPEN, realm),
+                types.iter().map(|t| t.as_str()));
+            handler.write_to_dir(DesktopFileSync::CITADEL_APPLICATIONS, None)?;
+        }
+
+        let routed = self.routed_types();
+        if !routed.is_empty() {
+            let handler = Self::handler_file(
+                Self::HANDLER_NAME,
+                "Open in Realm",
+                &format!("{} %U", CITADEL_OPEN),
+                routed.into_iter());
+            handler.write_to_dir(DesktopFileSync::CITADEL_APPLICATIONS, None)?;
+        }
+        Ok(())
+    }
+
+    fn handler_file<'a>(filename: &str, name: &str, exec: &str, types: impl Iterator<Item=&'a str>) -> DesktopFile {
+        let mime_types = types.fold(String::new(), |s, t| s + t + ";");
+        let mut dfp = DesktopFile::new(filename);
+        dfp.add_line(Line::DesktopHeader);
+        dfp.add_line(Line::KeyValue("Type".into(), "Application".into()));
+        dfp.add_line(Line::KeyValue("Name".into(), name.into()));
+        dfp.add_line(Line::KeyValue("NoDisplay".into(), "true".into()));
+        dfp.add_line(Line::ExecLine(exec.into()));
+        dfp.add_line(Line::KeyValue("MimeType".into(), mime_types));
+        dfp
+    }
+
+    fn write_mimeapps_list(&self) -> Result<()> {
+        let mut defaults = BTreeMap::new();
+        for t in self.routed_types() {
+            defaults.insert(t, vec![Self::HANDLER_NAME.to_string()]);
+        }
+
+        // every realm handler which can open a type is offered in "Open With"
+        let mut associations = BTreeMap::<&str, Vec<String>>::new();
+        for (realm, types) in &self.realm_types {
+            for t in types {
+                associations.entry(t.as_str()).or_default().push(Self::handler_name(realm));
+            }
+        }
+
+        let path = Path::new(Self::MIMEAPPS_LIST);
+        let exists = path.exists();
+        let existing = if exists { util::read_to_string(path)? } else { String::new() };
+        let content = Self::merge_mimeapps_list(&existing, &defaults, &associations);
+        if content == existing {
+            return Ok(());
+        }
+        if let Some(parent) = path.parent() {
+            if !parent.exists() {
+                util::create_dir(parent)?;
+                util::chown_user(parent)?;
+            }
+        }
+        util::write_file(path, content)?;
+        if !exists {
+            util::chown_user(path)?;
+        }
+        Ok(())
+    }
+
+    // Remove every handler written by a previous update from `existing` and add the
+    // generated handlers. Default handlers are placed ahead of any handler chosen by
+    // the user and associations are added after them.
+    fn merge_mimeapps_list(existing: &str, defaults: &BTreeMap<&str, Vec<String>>, associations: &BTreeMap<&str, Vec<String>>) -> String {
+        let mut pending = BTreeMap::new();
+        pending.insert(Self::DEFAULT_APPLICATIONS, defaults.clone());
+        pending.insert(Self::ADDED_ASSOCIATIONS, associations.clone());
+
+        let mut out = String::new();
+        let mut group = "";
+        for line in existing.lines() {
+            let trimmed = line.trim();
+            if trimmed.starts_with('[') {
+                Self::append_pending(&mut out, pending.get_mut(group));
+                group = trimmed;
+                let _ = writeln!(out, "{}", line);
+                continue;
+            }
+            let generated = match pending.get_mut(group) {
+                Some(generated) => generated,
+                None => {
+                    let _ = writeln!(out, "{}", line);
+                    continue;
+                }
+            };
+            let (key, value) = match trimmed.split_once('=') {
+                Some(kv) if !trimmed.starts_with('#') => kv,
+                _ => {
+                    let _ = writeln!(out, "{}", line);
+                    continue;
+                }
+            };
+            let key = key.trim();
+            let mut handlers = value.split(';')
+                .map(|h| h.trim())
+                .filter(|h| !h.is_empty() && !Self::is_handler_name(h))
+                .map(|h| h.to_string())
+                .collect::<Vec<_>>();
+            if let Some(generated) = generated.remove(key) {
+                if group == Self::DEFAULT_APPLICATIONS {
+                    handlers.splice(0..0, generated);
+                } else {
+                    handlers.extend(generated);
+                }
+            }
+            if !handlers.is_empty() {
+                let _ = writeln!(out, "{}={};", key, handlers.join(";"));
+            }
+        }
+        Self::append_pending(&mut out, pending.get_mut(group));
+
+        for (group, mut entries) in pending {
+            if !entries.is_empty() {
+                if !out.is_empty() && !out.ends_with("\n\n") {
+                    out.push('\n');
+                }
+                let _ = writeln!(out, "{}", group);
+                Self::append_pending(&mut out, Some(&mut entries));
+            }
+        }
+        out
+    }
+
+    fn append_pending(out: &mut String, entries: Option<&mut BTreeMap<&str, Vec<String>>>) {
+        if let Some(entries) = entries.filter(|e| !e.is_empty()) {
+            // keep the blank line which separates a group from the next one
+            let blank = out.ends_with("\n\n");
+            if blank {
+                out.pop();
+            }
+            for (t, handlers) in std::mem::take(entries) {
+                let _ = writeln!(out, "{}={};", t, handlers.join(";"));
+            }
+            if blank {
+                out.push('\n');
+            }
+        }
+    }
+}
+
+#[test]
+fn test_merge_mimeapps_list() {
+    let existing = "[Default Applications]\n\
+        text/plain=citadel-open.desktop;gedit.desktop;\n\
+        image/png=citadel-open.desktop;\n\
+        \n\
+        [Removed Associations]\n\
+        text/html=citadel-open-main.desktop;\n\
+        \n\
+        [Added Associations]\n\
+        text/plain=citadel-open-work.desktop;\n";
+
+    let mut defaults = BTreeMap::new();
+    defaults.insert("text/plain", vec!["citadel-open.desktop".to_string()]);
+    defaults.insert("text/html", vec!["citadel-open.desktop".to_string()]);
+    let mut associations = BTreeMap::new();
+    associations.insert("text/html", vec!["citadel-open-main.desktop".to_string()]);
+
+    assert_eq!(MimeHandlers::merge_mimeapps_list(existing, &defaults, &associations),
+        "[Default Applications]\n\
+        text/plain=citadel-open.desktop;gedit.desktop;\n\
+        text/html=citadel-open.desktop;\n\
+        \n\
+        [Removed Associations]\n\
+        text/html=citadel-open-main.desktop;\n\
+        \n\
+        [Added Associations]\n\
+        text/html=citadel-open-main.desktop;\n");
+
+    let empty = BTreeMap::new();
+    assert_eq!(MimeHandlers::merge_mimeapps_list("", &empty, &empty), "");
+    assert_eq!(MimeHandlers::merge_mimeapps_list("", &defaults, &empty),
+        "[Default Applications]\ntext/html=citadel-open.desktop;\ntext/plain=citadel-open.desktop;\n");
+}
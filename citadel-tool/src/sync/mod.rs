@@ -5,8 +5,11 @@ mod parser;
 mod desktop_sync;
 mod icons;
 mod icon_cache;
+mod mime_handlers;
+mod watcher;
 
 use self::desktop_sync::DesktopFileSync;
+use self::watcher::DesktopSyncWatcher;
 
 fn has_first_arg(args: &[String], arg: &str) -> bool {
     args.len() > 1 && args[1].as_str() == arg
@@ -16,10 +19,21 @@ pub fn main(args: Vec<String>) {
 
     Logger::set_log_level(LogLevel::Debug);
 
-    if has_first_arg(&args, "--all") {
+    if has_first_arg(&args, "--watch") {
+        if let Err(e) = DesktopSyncWatcher::run() {
+            println!("Desktop file watcher failed: {}", e);
+        }
+    } else if has_first_arg(&args, "--all") {
         if let Err(e) = DesktopFileSync::sync_active_realms() {
             println!("Sync all active realms failed: {}", e);
         }
+    } else if has_first_arg(&args, "--remove") {
+        match args.get(2) {
+            Some(name) => if let Err(e) = DesktopFileSync::remove_realm_files(name) {
+                println!("Removing desktop files of realm-{} failed: {}", name, e);
+            },
+            None => println!("--remove requires a realm name"),
+        }
     } else {
         let clear = has_first_arg(&args, "--clear");
         if let Err(e) = sync(clear) {
@@ -32,6 +46,6 @@ fn sync(clear: bool) -> Result<()> {
     if let Some(mut sync) = DesktopFileSync::new_current() {
         sync.run_sync(clear)
     } else {
-        DesktopFileSync::clear_target_files()
+        DesktopFileSync::sync_active_realms()
     }
 }
\ No newline at end of file
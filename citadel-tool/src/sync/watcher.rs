@@ -0,0 +1,370 @@
+use std::collections::{HashMap, HashSet};
+use std::ffi::OsStr;
+use std::os::unix::io::AsRawFd;
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use inotify::{Event, EventMask, Inotify, WatchDescriptor, WatchMask};
+use libcitadel::{Realm, RealmEvent, RealmManager, Result, util};
+
+use crate::sync::desktop_sync::DesktopFileSync;
+use crate::sync::icons::IconSync;
+
+// Changes are processed once no further change has arrived for this long
+const DEBOUNCE_INTERVAL: Duration = Duration::from_millis(500);
+
+// Longest time to wait for inotify events before checking for realms starting or stopping
+const REALM_POLL_INTERVAL: Duration = Duration::from_secs(1);
+
+enum RealmChange {
+    Started(Realm),
+    Stopped(String),
+}
+
+// Events which are watched on every directory
+const WATCH_MASK: WatchMask = WatchMask::CLOSE_WRITE.union(WatchMask::CREATE)
+    .union(WatchMask::DELETE).union(WatchMask::MOVED_FROM).union(WatchMask::MOVED_TO);
+
+#[derive(Clone,Copy,PartialEq)]
+enum WatchKind {
+    Applications,
+    Icons,
+    // An icon theme directory containing SIZE/apps subdirectories
+    IconTheme,
+}
+
+// A directory to watch which does not exist yet
+struct MissingDir {
+    realm: String,
+    path: PathBuf,
+    kind: WatchKind,
+}
+
+// Changes in a realm collected until the debounce interval expires
+#[derive(Default)]
+struct PendingChanges {
+    files: HashSet<PathBuf>,
+    icons: HashSet<String>,
+}
+
+struct WatchedRealm {
+    sync: DesktopFileSync,
+    watches: Vec<WatchDescriptor>,
+}
+
+/// Long running desktop file synchronization.
+///
+/// Watches the application and icon directories of every active realm with inotify
+/// and synchronizes only the desktop files and icons which changed. Realms are added
+/// and removed as they start and stop. Directories which do not exist yet are
+/// watched for once they are created.
+pub struct DesktopSyncWatcher {
+    inotify: Inotify,
+    realms: HashMap<String, WatchedRealm>,
+    watches: HashMap<WatchDescriptor, (String, PathBuf, WatchKind)>,
+    // Directories which do not exist yet, keyed by the watch on their nearest existing parent
+    missing: HashMap<WatchDescriptor, Vec<MissingDir>>,
+    pending: HashMap<String, PendingChanges>,
+    last_change: Option<Instant>,
+    changes: Receiver<RealmChange>,
+}
+
+impl DesktopSyncWatcher {
+
+    pub fn run() -> Result<()> {
+        let (sender, changes) = mpsc::channel();
+        let manager = RealmManager::load()?;
+        Self::forward_realm_events(&manager, sender);
+
+        let inotify = Inotify::init()
+            .map_err(context!("inotify initialization failed"))?;
+
+        let mut watcher = DesktopSyncWatcher {
+            inotify,
+            realms: HashMap::new(),
+            watches: HashMap::new(),
+            missing: HashMap::new(),
+            pending: HashMap::new(),
+            last_change: None,
+            changes,
+        };
+
+        DesktopFileSync::sync_active_realms()?;
+        for realm in manager.active_realms(true) {
+            watcher.add_realm(realm, false);
+        }
+        watcher.event_loop()
+    }
+
+    fn forward_realm_events(manager: &RealmManager, sender: Sender<RealmChange>) {
+        // Handlers must be Sync, but a Sender can only be shared between threads behind a lock
+        let sender = Mutex::new(sender);
+        manager.add_event_handler(move |ev| {
+            let change = match ev {
+                RealmEvent::Started(realm) => RealmChange::Started(realm.clone()),
+                RealmEvent::Stopped(realm) | RealmEvent::Removed(realm) => RealmChange::Stopped(realm.name().to_string()),
+                _ => return,
+            };
+            if let Err(e) = sender.lock().unwrap().send(change) {
+                warn!("Error forwarding realm event to desktop sync: {}", e);
+            }
+        });
+        if let Err(e) = manager.start_event_task() {
+            warn!("Error starting realm manager event task: {}", e);
+        }
+    }
+
+    fn event_loop(&mut self) -> Result<()> {
+        let mut buffer = [0; 4096];
+        loop {
+            self.process_realm_changes();
+
+            if self.wait_for_events(self.poll_timeout())? {
+                let events = self.inotify.read_events(&mut buffer)
+                    .map_err(context!("error reading inotify events"))?;
+                for event in events {
+                    self.handle_event(event);
+                }
+            }
+
+            if self.last_change.is_some_and(|t| t.elapsed() >= DEBOUNCE_INTERVAL) {
+                self.process_pending();
+            }
+        }
+    }
+
+    fn poll_timeout(&self) -> Duration {
+        match self.last_change {
+            Some(t) => DEBOUNCE_INTERVAL.saturating_sub(t.elapsed()),
+            None => REALM_POLL_INTERVAL,
+        }
+    }
+
+    // Return true if inotify events are ready to be read before `timeout` expires
+    fn wait_for_events(&self, timeout: Duration) -> Result<bool> {
+        let mut pollfd = libc::pollfd {
+            fd: self.inotify.as_raw_fd(),
+            events: libc::POLLIN,
+            revents: 0,
+        };
+        let n = unsafe { libc::poll(&mut pollfd, 1, timeout.as_millis() as libc::c_int) };
+        if n < 0 {
+            let err = std::io::Error::last_os_error();
+            if err.kind() == std::io::ErrorKind::Interrupted {
+                return Ok(false);
+            }
+            bail!("error polling inotify descriptor: {}", err);
+        }
+        Ok(n > 0)
+    }
+
+    fn process_realm_changes(&mut self) {
+        while let Ok(change) = self.changes.try_recv() {
+            match change {
+                RealmChange::Started(realm) => self.add_realm(realm, true),
+                RealmChange::Stopped(name) => self.remove_realm(&name),
+            }
+        }
+    }
+
+    fn add_realm(&mut self, realm: Realm, sync: bool) {
+        if realm.is_system() {
+            return;
+        }
+        let name = realm.name().to_string();
+        self.remove_realm(&name);
+
+        let mut desktop_sync = DesktopFileSync::new(realm.clone());
+        if sync {
+            if let Err(e) = desktop_sync.run_sync(false) {
+                warn!("Error synchronizing desktop files from realm-{}: {}", name, e);
+            }
+        }
+
+        self.realms.insert(name.clone(), WatchedRealm { sync: desktop_sync, watches: Vec::new() });
+        for dir in DesktopFileSync::APPLICATION_DIRS {
+            self.add_watch(&name, realm.run_path().join(dir), WatchKind::Applications, false);
+        }
+        for dir in IconSync::ICON_DIRS {
+            self.add_watch(&name, realm.run_path().join(dir), WatchKind::IconTheme, false);
+        }
+        info!("Watching desktop files of realm-{}", name);
+    }
+
+    // Watch directory `path` of `realm`, or the nearest parent of `path` which exists
+    // until it is created. If `created` is true the directory has just appeared and
+    // any files already in it are synchronized.
+    fn add_watch(&mut self, realm: &str, path: PathBuf, kind: WatchKind, created: bool) {
+        let base = match self.realms.get(realm) {
+            Some(watched) => watched.sync.realm().run_path(),
+            None => return,
+        };
+        if !path.is_dir() {
+            self.watch_missing(realm, &base, path, kind);
+            return;
+        }
+        let wd = match self.watch_directory(realm, &path) {
+            Some(wd) => wd,
+            None => return,
+        };
+        self.watches.insert(wd, (realm.to_string(), path.clone(), kind));
+
+        if kind == WatchKind::IconTheme {
+            // icons are stored in SIZE/apps subdirectories of the theme directory
+            let mut subdirs = Vec::new();
+            let result = util::read_directory(&path, |dent| {
+                if dent.path().is_dir() {
+                    subdirs.push(dent.path().join("apps"));
+                }
+                Ok(())
+            });
+            if let Err(e) = result {
+                warn!("Error adding watches for icons of realm-{}: {}", realm, e);
+            }
+            for dir in subdirs {
+                self.add_watch(realm, dir, WatchKind::Icons, created);
+            }
+        } else if created {
+            let result = util::read_directory(&path, |dent| {
+                self.add_pending(realm, &path, kind, Path::new(&dent.file_name()));
+                Ok(())
+            });
+            if let Err(e) = result {
+                warn!("Error reading new directory {:?} of realm-{}: {}", path, realm, e);
+            }
+        }
+    }
+
+    fn watch_missing(&mut self, realm: &str, base: &Path, path: PathBuf, kind: WatchKind) {
+        let parent = match path.ancestors().skip(1).find(|p| p.is_dir()) {
+            Some(parent) if parent.starts_with(base) => parent.to_path_buf(),
+            _ => return,
+        };
+        if let Some(wd) = self.watch_directory(realm, &parent) {
+            let realm = realm.to_string();
+            self.missing.entry(wd).or_default().push(MissingDir { realm, path, kind });
+        }
+    }
+
+    fn watch_directory(&mut self, realm: &str, path: &Path) -> Option<WatchDescriptor> {
+        match self.inotify.add_watch(path, WATCH_MASK) {
+            Ok(wd) => {
+                if let Some(watched) = self.realms.get_mut(realm) {
+                    if !watched.watches.contains(&wd) {
+                        watched.watches.push(wd.clone());
+                    }
+                }
+                Some(wd)
+            },
+            Err(e) => {
+                warn!("Error adding inotify watch for {:?}: {}", path, e);
+                None
+            },
+        }
+    }
+
+    fn remove_realm(&mut self, name: &str) {
+        let watched = match self.realms.remove(name) {
+            Some(watched) => watched,
+            None => return,
+        };
+        for wd in watched.watches {
+            self.watches.remove(&wd);
+            self.missing.remove(&wd);
+            // fails if the directory is already gone, which also removes the watch
+            let _ = self.inotify.rm_watch(wd);
+        }
+        self.pending.remove(name);
+        if let Err(e) = DesktopFileSync::remove_realm_files(name) {
+            warn!("Error removing desktop files of realm-{}: {}", name, e);
+        }
+        info!("Stopped watching desktop files of realm-{}", name);
+    }
+
+    fn handle_event(&mut self, event: Event<&OsStr>) {
+        if event.mask.contains(EventMask::Q_OVERFLOW) {
+            // events were lost so resynchronize everything
+            let realms = self.realms.values().map(|w| w.sync.realm().clone()).collect::<Vec<_>>();
+            for realm in realms {
+                self.add_realm(realm, true);
+            }
+            return;
+        }
+        if event.mask.contains(EventMask::IGNORED) {
+            // The watched directory was removed, so wait for it to be created again
+            if let Some((realm, path, kind)) = self.watches.remove(&event.wd) {
+                self.add_watch(&realm, path, kind, false);
+            }
+            for dir in self.missing.remove(&event.wd).unwrap_or_default() {
+                self.add_watch(&dir.realm, dir.path, dir.kind, false);
+            }
+            return;
+        }
+
+        let is_new_dir = event.mask.contains(EventMask::ISDIR) &&
+            event.mask.intersects(EventMask::CREATE | EventMask::MOVED_TO);
+
+        if is_new_dir {
+            // Retry each missing directory waiting on this parent, which either adds
+            // the watch or waits again on a parent closer to the missing directory.
+            if let Some(missing) = self.missing.remove(&event.wd) {
+                for dir in missing {
+                    self.add_watch(&dir.realm, dir.path, dir.kind, true);
+                }
+            }
+        }
+
+        let (realm, dir, kind) = match self.watches.get(&event.wd) {
+            Some(watch) => watch.clone(),
+            None => return,
+        };
+        let name = match event.name {
+            Some(name) => Path::new(name),
+            None => return,
+        };
+        if kind == WatchKind::IconTheme {
+            if is_new_dir {
+                self.add_watch(&realm, dir.join(name).join("apps"), WatchKind::Icons, true);
+            }
+        } else {
+            self.add_pending(&realm, &dir, kind, name);
+        }
+    }
+
+    fn add_pending(&mut self, realm: &str, dir: &Path, kind: WatchKind, name: &Path) {
+        let pending = self.pending.entry(realm.to_string()).or_default();
+        match kind {
+            WatchKind::Applications => {
+                pending.files.insert(dir.join(name));
+            },
+            WatchKind::Icons => if let Some(stem) = name.file_stem().and_then(|s| s.to_str()) {
+                pending.icons.insert(stem.to_string());
+            },
+            WatchKind::IconTheme => return,
+        }
+        self.last_change = Some(Instant::now());
+    }
+
+    fn process_pending(&mut self) {
+        self.last_change = None;
+        for (name, pending) in self.pending.drain() {
+            let watched = match self.realms.get_mut(&name) {
+                Some(watched) => watched,
+                None => continue,
+            };
+            for path in &pending.files {
+                verbose!("Desktop file changed in realm-{}: {:?}", name, path);
+                if let Err(e) = watched.sync.sync_changed_file(path) {
+                    warn!("Error synchronizing desktop file {:?} from realm-{}: {}", path, name, e);
+                }
+            }
+            for icon in &pending.icons {
+                if let Err(e) = watched.sync.sync_changed_icon(icon) {
+                    warn!("Error synchronizing icon {} from realm-{}: {}", icon, name, e);
+                }
+            }
+        }
+    }
+}
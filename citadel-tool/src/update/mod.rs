@@ -1,6 +1,6 @@
 use std::path::{Path, PathBuf};
 
-use libcitadel::{Result, Partition, ResourceImage, ImageHeader, LogLevel, Logger, util};
+use libcitadel::{Result, Partition, ResourceImage, ImageHeader, LogLevel, Logger, RealmFS, util};
 use crate::update::kernel::{KernelInstaller, KernelVersion};
 use std::collections::HashSet;
 use std::fs::{DirEntry, File};
@@ -9,9 +9,9 @@ use tempfile::Builder;
 
 mod kernel;
 
-const FLAG_SKIP_SHA: u32 = 0x01;
-const FLAG_NO_PREFER: u32 = 0x02;
-const FLAG_QUIET: u32 = 0x04;
+pub const FLAG_SKIP_SHA: u32 = 0x01;
+pub const FLAG_NO_PREFER: u32 = 0x02;
+pub const FLAG_QUIET: u32 = 0x04;
 
 const RESOURCES_DIRECTORY: &str = "/storage/resources";
 const TEMP_DIRECTORY: &str = "/storage/resources/tmp";
@@ -93,7 +93,9 @@ fn create_tmp_copy(path: &Path) -> Result<PathBuf> {
     Ok(path)
 }
 
-fn install_image(path: &Path, flags: u32) -> Result<()> {
+/// Validate and install an image file of any type. This is the installation
+/// path used by both `citadel-update` and `citadel-image install`.
+pub fn install_image(path: &Path, flags: u32) -> Result<()> {
     if !path.exists() || path.file_name().is_none() {
         bail!("file path {} does not exist", path.display());
     }
@@ -106,7 +108,14 @@ fn install_image(path: &Path, flags: u32) -> Result<()> {
 
     let tmpfile = create_tmp_copy(path)?;
 
-    let mut image = ResourceImage::from_header(header, tmpfile)?;
+    let image = ResourceImage::from_header(header, tmpfile)?;
+
+    let (mut image, flags) = if image.is_delta() {
+        // shasum is always verified when applying a delta
+        (apply_delta_image(image)?, flags | FLAG_SKIP_SHA)
+    } else {
+        (image, flags)
+    };
 
     prepare_image(&image, flags)?;
 
@@ -114,10 +123,80 @@ fn install_image(path: &Path, flags: u32) -> Result<()> {
         "kernel" => install_kernel_image(&mut image),
         "extra" => install_extra_image(&image),
         "rootfs" =>  install_rootfs_image(&image, flags),
+        "realmfs" => install_realmfs_image(&image),
         image_type => bail!("Unknown image type: {}", image_type),
     }
 }
 
+// Reconstruct a full image from a delta image and the currently installed image
+// which the delta was generated against. The reconstructed image is verified to have
+// the shasum and dm-verity root hash from the metainfo of the delta image.
+fn apply_delta_image(delta: ResourceImage) -> Result<ResourceImage> {
+    let metainfo = delta.metainfo();
+    let base_shasum = match metainfo.delta_base_shasum() {
+        Some(shasum) => shasum,
+        None => bail!("delta image does not have a delta-base-shasum field"),
+    };
+
+    let target = delta.path().with_extension("full");
+    let result = match metainfo.image_type() {
+        "rootfs" => {
+            let partition = find_delta_base_partition(base_shasum)?;
+            delta.apply_delta(partition.path(), 0, &target)
+        },
+        "realmfs" => {
+            let realmfs = find_delta_base_realmfs(metainfo.realmfs_name(), base_shasum)?;
+            delta.apply_delta(realmfs.path(), ImageHeader::HEADER_SIZE as u64, &target)
+        },
+        image_type => bail!("delta images are not supported for image type {}", image_type),
+    };
+    util::remove_file(delta.path())?;
+
+    let image = result.and_then(|image| {
+        verify_delta_image(&image)?;
+        Ok(image)
+    });
+    if image.is_err() {
+        util::remove_file(&target)?;
+    }
+    image
+}
+
+fn verify_delta_image(image: &ResourceImage) -> Result<()> {
+    info!("Verifying sha256 hash of image reconstructed from delta");
+    let shasum = image.generate_shasum()?;
+    if shasum != image.metainfo().shasum() {
+        bail!("image reconstructed from delta does not have expected sha256 value");
+    }
+    image.generate_verity_hashtree()?;
+    if !image.verify_verity()? {
+        bail!("image reconstructed from delta does not match dm-verity root hash in metainfo");
+    }
+    Ok(())
+}
+
+fn find_delta_base_partition(shasum: &str) -> Result<Partition> {
+    for p in Partition::rootfs_partitions()? {
+        if p.is_initialized() && p.metainfo().shasum() == shasum {
+            info!("Found delta base image on partition {}", p.path().display());
+            return Ok(p);
+        }
+    }
+    bail!("no rootfs partition contains the base image ({}) for this delta", shasum)
+}
+
+fn find_delta_base_realmfs(name: Option<&str>, shasum: &str) -> Result<RealmFS> {
+    let name = match name {
+        Some(name) => name,
+        None => bail!("realmfs delta image does not have a realmfs-name field"),
+    };
+    let realmfs = RealmFS::load_by_name(name)?;
+    if realmfs.metainfo().shasum() != shasum {
+        bail!("installed realmfs image '{}' is not the base image ({}) for this delta", name, shasum);
+    }
+    Ok(realmfs)
+}
+
 // Prepare the image file for installation by decompressing and generating
 // dmverity hash tree.
 fn prepare_image(image: &ResourceImage, flags: u32) -> Result<()> {
@@ -172,6 +251,20 @@ fn maybe_remove_old_extra_image(path: &Path, shasum: &str) -> Result<()> {
     Ok(())
 }
 
+fn install_realmfs_image(image: &ResourceImage) -> Result<()> {
+    let name = match image.metainfo().realmfs_name() {
+        Some(name) if RealmFS::is_valid_name(name) => name.to_string(),
+        Some(name) => bail!("realmfs image has invalid realmfs-name '{}'", name),
+        None => bail!("realmfs image does not have a realmfs-name field"),
+    };
+    let image_dest = Path::new(RealmFS::BASE_PATH).join(format!("{}-realmfs.img", name));
+    if image_dest.exists() {
+        rotate(&image_dest)?;
+    }
+    info!("installing realmfs image by moving from {} to {}", image.path().display(), image_dest.display());
+    util::rename(image.path(), image_dest)
+}
+
 fn install_kernel_image(image: &mut ResourceImage) -> Result<()> {
     if !Path::new("/boot/loader/loader.conf").exists() {
         bail!("failed to automount /boot partition. Please manually mount correct partition.");
@@ -34,6 +34,12 @@ impl CommandLine {
         CMDLINE._get_value(name)
     }
 
+    /// Return `true` if `condition` is the name of a variable present on the kernel command
+    /// line, or has the form `name=value` and the variable `name` has the value `value`.
+    pub fn matches(condition: &str) -> bool {
+        CMDLINE._matches(condition)
+    }
+
     /// Return `true` if variable citadel.noverity is present on kernel command line.
     pub fn noverity() -> bool {
         Self::var_exists("citadel.noverity")
@@ -100,6 +106,13 @@ impl CommandLine {
         None
     }
 
+    /// Return the number of channel keys which must have signed an image, from
+    /// the variable citadel.sig-threshold
+    pub fn sig_threshold() -> Option<usize> {
+        Self::get_value("citadel.sig-threshold")
+            .and_then(|s| s.parse::<usize>().ok())
+    }
+
     pub fn verbose() -> bool {
         Self::var_exists("citadel.verbose")
     }
@@ -124,6 +137,13 @@ impl CommandLine {
         self.varmap.contains_key(name)
     }
 
+    fn _matches(&self, condition: &str) -> bool {
+        match condition.split_once('=') {
+            Some((name, value)) => self._get_value(name) == Some(value),
+            None => self._var_exists(condition),
+        }
+    }
+
     fn _get_value(&self, name: &str) -> Option<&str> {
         if let Some(val) = self.varmap.get(name) {
             // 'name' exists
@@ -288,3 +308,15 @@ fn foo() {
     println!("hello");
     println!("cline: {:?}", cline.varmap);
 }
+
+#[test]
+fn test_cmdline_matches() {
+    let cline = CommandLine {
+        varmap: CommandLineParser::new("quiet citadel.live citadel.channel=dev".to_string()).parse(),
+    };
+    assert!(cline._matches("citadel.live"));
+    assert!(cline._matches("citadel.channel"));
+    assert!(cline._matches("citadel.channel=dev"));
+    assert!(!cline._matches("citadel.channel=prod"));
+    assert!(!cline._matches("citadel.install"));
+}
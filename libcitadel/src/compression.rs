@@ -0,0 +1,163 @@
+use std::fs::File;
+use std::io::{self, BufReader, BufWriter, Read, Write};
+use std::path::Path;
+use std::thread;
+
+use xz2::stream::{Check, MtStreamBuilder};
+
+use crate::{ImageHeader, Result, util};
+
+// Images are compressed once when they are built and decompressed on every system
+// which installs them, so the slower high compression settings are used.
+const XZ_PRESET: u32 = 6;
+const ZSTD_LEVEL: i32 = 19;
+
+// Number of compressed bytes read between calls to a progress callback
+const PROGRESS_INTERVAL: u64 = 16 * 1024 * 1024;
+
+/// Algorithm used to compress the data of a resource image.
+///
+/// The header of a compressed image has `FLAG_DATA_COMPRESSED` set, and also
+/// `FLAG_COMPRESSED_ZSTD` if the data is compressed with zstd rather than xz. Setting
+/// both flags for zstd means that tools which only know about xz fail to decompress
+/// the image rather than treating it as uncompressed.
+#[derive(Clone,Copy,Debug,PartialEq,Eq)]
+pub enum Compression {
+    Xz,
+    Zstd,
+}
+
+impl Compression {
+    pub fn from_name(s: &str) -> Option<Self> {
+        match s {
+            "xz" => Some(Compression::Xz),
+            "zstd" => Some(Compression::Zstd),
+            _ => None,
+        }
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Compression::Xz => "xz",
+            Compression::Zstd => "zstd",
+        }
+    }
+
+    /// Return the algorithm used to compress the data of the image with `header` or
+    /// `None` if the data is not compressed.
+    pub fn from_header(header: &ImageHeader) -> Option<Self> {
+        if !header.has_flag(ImageHeader::FLAG_DATA_COMPRESSED) {
+            None
+        } else if header.has_flag(ImageHeader::FLAG_COMPRESSED_ZSTD) {
+            Some(Compression::Zstd)
+        } else {
+            Some(Compression::Xz)
+        }
+    }
+
+    /// Set the flags in `header` which record that image data is compressed with this algorithm.
+    pub fn set_header_flags(&self, header: &ImageHeader) {
+        header.set_flag(ImageHeader::FLAG_DATA_COMPRESSED);
+        if *self == Compression::Zstd {
+            header.set_flag(ImageHeader::FLAG_COMPRESSED_ZSTD);
+        } else {
+            header.clear_flag(ImageHeader::FLAG_COMPRESSED_ZSTD);
+        }
+    }
+
+    /// Clear the flags in `header` which record that image data is compressed.
+    pub fn clear_header_flags(header: &ImageHeader) {
+        header.clear_flag(ImageHeader::FLAG_DATA_COMPRESSED);
+        header.clear_flag(ImageHeader::FLAG_COMPRESSED_ZSTD);
+    }
+
+    /// Replace the file at `path` with a compressed copy, using one thread for each
+    /// available cpu.
+    pub fn compress_file(&self, path: &Path) -> Result<()> {
+        let tmpfile = path.with_extension(format!("tmp.{}", self.as_str()));
+        let mut input = BufReader::new(File::open(path)
+            .map_err(context!("error opening file {:?} to compress", path))?);
+        let out = BufWriter::new(File::create(&tmpfile)
+            .map_err(context!("error creating temporary file {:?}", tmpfile))?);
+        self.compress(&mut input, out)
+            .map_err(context!("error compressing {:?} with {}", path, self.as_str()))?;
+        util::rename(&tmpfile, path)
+    }
+
+    fn compress<R: Read, W: Write>(&self, input: &mut R, out: W) -> io::Result<()> {
+        let threads = thread::available_parallelism().map(|n| n.get() as u32).unwrap_or(1);
+        match self {
+            Compression::Xz => {
+                let stream = MtStreamBuilder::new()
+                    .preset(XZ_PRESET)
+                    .threads(threads)
+                    .check(Check::Crc64)
+                    .encoder()?;
+                let mut encoder = xz2::write::XzEncoder::new_stream(out, stream);
+                io::copy(input, &mut encoder)?;
+                encoder.finish()?.flush()
+            }
+            Compression::Zstd => {
+                let mut encoder = zstd::stream::write::Encoder::new(out, ZSTD_LEVEL)?;
+                encoder.multithread(threads)?;
+                io::copy(input, &mut encoder)?;
+                encoder.finish()?.flush()
+            }
+        }
+    }
+
+    /// Decompress all of `input` and write the result to `out`, returning the number
+    /// of bytes written. `progress` is called every few megabytes with the number of
+    /// bytes of `input` consumed so far and `total`, the size of `input`.
+    pub fn decompress<R: Read, W: Write>(&self, input: R, out: &mut W, total: u64, progress: &dyn Fn(u64, u64)) -> Result<u64> {
+        let input = ProgressReader { inner: input, total, read: 0, reported: 0, progress };
+        let result = match self {
+            Compression::Xz => io::copy(&mut xz2::read::XzDecoder::new_multi_decoder(input), out),
+            Compression::Zstd => zstd::stream::read::Decoder::new(input)
+                .and_then(|mut decoder| io::copy(&mut decoder, out)),
+        };
+        result.map_err(context!("error decompressing {} data", self.as_str()))
+    }
+}
+
+struct ProgressReader<'a, R: Read> {
+    inner: R,
+    total: u64,
+    read: u64,
+    reported: u64,
+    progress: &'a dyn Fn(u64, u64),
+}
+
+impl <'a, R: Read> Read for ProgressReader<'a, R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let n = self.inner.read(buf)?;
+        self.read += n as u64;
+        if self.read >= self.reported + PROGRESS_INTERVAL || (n == 0 && self.read != self.reported) {
+            self.reported = self.read;
+            (self.progress)(self.read, self.total);
+        }
+        Ok(n)
+    }
+}
+
+#[test]
+fn test_compression_round_trip() {
+    let data = (0..200_000u32).flat_map(|i| (i % 251).to_le_bytes()).collect::<Vec<_>>();
+    for compression in [Compression::Xz, Compression::Zstd] {
+        let mut compressed = Vec::new();
+        compression.compress(&mut data.as_slice(), &mut compressed).unwrap();
+        assert!(compressed.len() < data.len());
+
+        let calls = std::cell::Cell::new(0);
+        let mut out = Vec::new();
+        let total = compressed.len() as u64;
+        let n = compression.decompress(compressed.as_slice(), &mut out, total, &|read, t| {
+            assert_eq!(t, total);
+            assert!(read <= total);
+            calls.set(calls.get() + 1);
+        }).unwrap();
+        assert_eq!(n, data.len() as u64);
+        assert_eq!(out, data);
+        assert!(calls.get() > 0);
+    }
+}
@@ -84,6 +84,14 @@ impl OsRelease {
         OsRelease::get_value("CITADEL_IMAGE_PUBKEY")
     }
 
+    pub fn citadel_image_pubkeys() -> Option<&'static str> {
+        OsRelease::get_value("CITADEL_IMAGE_PUBKEYS")
+    }
+
+    pub fn citadel_image_sig_threshold() -> Option<usize> {
+        OsRelease::get_int_value("CITADEL_IMAGE_SIG_THRESHOLD")
+    }
+
     pub fn citadel_rootfs_version() -> Option<usize> {
         OsRelease::get_int_value("CITADEL_ROOTFS_VERSION")
     }
@@ -0,0 +1,317 @@
+use std::collections::HashMap;
+use std::collections::hash_map::DefaultHasher;
+use std::hash::Hasher;
+use std::io::{self, Read, Seek, SeekFrom, Write};
+
+use byteorder::{BigEndian, ReadBytesExt, WriteBytesExt};
+
+use crate::{Result, BLOCK_SIZE};
+
+/// Expected magic value at the start of delta data
+const DELTA_MAGIC: &[u8] = b"SGDELTA1";
+
+const OP_END: u8 = 0;
+const OP_COPY: u8 = 1;
+const OP_DATA: u8 = 2;
+const OP_ZERO: u8 = 3;
+
+// Maximum number of blocks described by a single delta operation
+const MAX_RUN: u32 = 1024;
+
+///
+/// A block level binary delta between two versions of an image.
+///
+/// The image data of a delta update image contains a list of operations
+/// which reconstruct the data of a target image from the data of a base
+/// image which is already present on the system. The layout is:
+///
+/// ```text
+///     magic         8 bytes   ('SGDELTA1')
+///     nblocks       4 bytes   (number of blocks in target image as 32-bit Big Endian)
+///     operations    ...
+/// ```
+///
+/// Each operation is a single op byte followed by a 32-bit Big Endian block count.
+///
+/// ```text
+///     OP_COPY   followed by a 32-bit base block index. Copy `count` blocks from the base
+///               image starting at this index.
+///
+///     OP_DATA   followed by `count` blocks of literal data.
+///
+///     OP_ZERO   Write `count` blocks of zeros.
+///
+///     OP_END    Marks the end of the operation list. Count field is zero.
+/// ```
+///
+pub struct ImageDelta;
+
+impl ImageDelta {
+
+    /// Generate a delta which transforms the first `base_nblocks` blocks of `base` (starting
+    /// at byte offset `base_offset`) into the first `target_nblocks` blocks read from `target`.
+    ///
+    /// Returns the number of blocks which must be stored as literal data in the delta.
+    pub fn generate<B,T,W>(base: &mut B, base_offset: u64, base_nblocks: usize, target: &mut T, target_nblocks: usize, out: &mut W) -> Result<usize>
+        where B: Read + Seek, T: Read, W: Write
+    {
+        let index = BaseIndex::build(base, base_offset, base_nblocks)?;
+        let mut encoder = DeltaEncoder::new(out, target_nblocks)?;
+        let mut block = vec![0u8; BLOCK_SIZE];
+        let mut candidate = vec![0u8; BLOCK_SIZE];
+
+        for idx in 0..target_nblocks {
+            target.read_exact(&mut block)
+                .map_err(context!("error reading block {} of target image", idx))?;
+
+            if block.iter().all(|b| *b == 0) {
+                encoder.zero()?;
+            } else if let Some(base_idx) = index.find(base, base_offset, &block, encoder.next_copy().unwrap_or(idx as u32), &mut candidate)? {
+                encoder.copy(base_idx)?;
+            } else {
+                encoder.data(&block)?;
+            }
+        }
+        encoder.finish()
+    }
+
+    /// Apply delta data read from `delta` to `base` (starting at byte offset `base_offset`)
+    /// and write the reconstructed image data to `out`.
+    ///
+    /// Returns the number of blocks written.
+    pub fn apply<B,D,W>(base: &mut B, base_offset: u64, delta: &mut D, out: &mut W) -> Result<usize>
+        where B: Read + Seek, D: Read, W: Write
+    {
+        let mut magic = [0u8; 8];
+        delta.read_exact(&mut magic)
+            .map_err(context!("error reading delta header"))?;
+        if magic != DELTA_MAGIC {
+            bail!("delta data does not have expected magic value");
+        }
+        let nblocks = read_u32(delta)? as usize;
+
+        let mut block = vec![0u8; BLOCK_SIZE];
+        let mut written = 0;
+        loop {
+            let op = delta.read_u8()
+                .map_err(context!("error reading delta operation"))?;
+            let count = read_u32(delta)? as usize;
+            if op == OP_END {
+                break;
+            }
+            if written + count > nblocks {
+                bail!("delta operations exceed target image size of {} blocks", nblocks);
+            }
+            match op {
+                OP_COPY => {
+                    let start = read_u32(delta)? as u64;
+                    base.seek(SeekFrom::Start(base_offset + start * BLOCK_SIZE as u64))
+                        .map_err(context!("error seeking to block {} of base image", start))?;
+                    for _ in 0..count {
+                        base.read_exact(&mut block)
+                            .map_err(context!("error reading base image block"))?;
+                        write_block(out, &block)?;
+                    }
+                },
+                OP_DATA => {
+                    for _ in 0..count {
+                        delta.read_exact(&mut block)
+                            .map_err(context!("error reading literal block from delta"))?;
+                        write_block(out, &block)?;
+                    }
+                },
+                OP_ZERO => {
+                    let zeros = vec![0u8; BLOCK_SIZE];
+                    for _ in 0..count {
+                        write_block(out, &zeros)?;
+                    }
+                },
+                op => bail!("unknown delta operation {}", op),
+            }
+            written += count;
+        }
+
+        if written != nblocks {
+            bail!("delta produced {} blocks but target image has {} blocks", written, nblocks);
+        }
+        Ok(written)
+    }
+}
+
+fn read_u32<R: Read>(r: &mut R) -> Result<u32> {
+    r.read_u32::<BigEndian>()
+        .map_err(context!("error reading delta data"))
+}
+
+fn write_block<W: Write>(out: &mut W, block: &[u8]) -> Result<()> {
+    out.write_all(block)
+        .map_err(context!("error writing reconstructed image data"))
+}
+
+fn block_hash(block: &[u8]) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    hasher.write(block);
+    hasher.finish()
+}
+
+// Maps the hash of each block in the base image to the index of the first
+// block with that hash.
+struct BaseIndex {
+    nblocks: usize,
+    map: HashMap<u64, u32>,
+}
+
+impl BaseIndex {
+    fn build<B: Read + Seek>(base: &mut B, offset: u64, nblocks: usize) -> Result<Self> {
+        base.seek(SeekFrom::Start(offset))
+            .map_err(context!("error seeking to start of base image data"))?;
+        let mut map = HashMap::new();
+        let mut block = vec![0u8; BLOCK_SIZE];
+        for idx in 0..nblocks {
+            base.read_exact(&mut block)
+                .map_err(context!("error reading block {} of base image", idx))?;
+            map.entry(block_hash(&block)).or_insert(idx as u32);
+        }
+        Ok(BaseIndex { nblocks, map })
+    }
+
+    // Find a block in the base image identical to `block`. The block at index `hint`
+    // (which would extend the current copy run, or otherwise is at the same position in
+    // the base image) is preferred over the hash lookup.
+    fn find<B: Read + Seek>(&self, base: &mut B, offset: u64, block: &[u8], hint: u32, buffer: &mut [u8]) -> Result<Option<u32>> {
+        if (hint as usize) < self.nblocks && Self::matches(base, offset, hint, block, buffer)? {
+            return Ok(Some(hint));
+        }
+        match self.map.get(&block_hash(block)) {
+            Some(&idx) if Self::matches(base, offset, idx, block, buffer)? => Ok(Some(idx)),
+            _ => Ok(None),
+        }
+    }
+
+    fn matches<B: Read + Seek>(base: &mut B, offset: u64, idx: u32, block: &[u8], buffer: &mut [u8]) -> Result<bool> {
+        base.seek(SeekFrom::Start(offset + idx as u64 * BLOCK_SIZE as u64))
+            .and_then(|_| base.read_exact(buffer))
+            .map_err(context!("error reading block {} of base image", idx))?;
+        Ok(buffer == block)
+    }
+}
+
+enum Run {
+    None,
+    Copy { start: u32, count: u32 },
+    Data(Vec<u8>),
+    Zero(u32),
+}
+
+// Collects runs of identical operations and writes them to the output.
+struct DeltaEncoder<'a, W: Write> {
+    out: &'a mut W,
+    run: Run,
+    data_blocks: usize,
+}
+
+impl <'a, W: Write> DeltaEncoder<'a, W> {
+    fn new(out: &'a mut W, nblocks: usize) -> Result<Self> {
+        out.write_all(DELTA_MAGIC)
+            .and_then(|_| out.write_u32::<BigEndian>(nblocks as u32))
+            .map_err(context!("error writing delta header"))?;
+        Ok(DeltaEncoder { out, run: Run::None, data_blocks: 0 })
+    }
+
+    // If a copy run is in progress, return the base index which would extend it.
+    fn next_copy(&self) -> Option<u32> {
+        match self.run {
+            Run::Copy { start, count } => Some(start + count),
+            _ => None,
+        }
+    }
+
+    fn copy(&mut self, idx: u32) -> Result<()> {
+        if let Run::Copy { start, ref mut count } = self.run {
+            if start + *count == idx && *count < MAX_RUN {
+                *count += 1;
+                return Ok(());
+            }
+        }
+        self.flush()?;
+        self.run = Run::Copy { start: idx, count: 1 };
+        Ok(())
+    }
+
+    fn data(&mut self, block: &[u8]) -> Result<()> {
+        self.data_blocks += 1;
+        if let Run::Data(ref mut v) = self.run {
+            if v.len() < MAX_RUN as usize * BLOCK_SIZE {
+                v.extend_from_slice(block);
+                return Ok(());
+            }
+        }
+        self.flush()?;
+        self.run = Run::Data(block.to_vec());
+        Ok(())
+    }
+
+    fn zero(&mut self) -> Result<()> {
+        if let Run::Zero(ref mut count) = self.run {
+            if *count < MAX_RUN {
+                *count += 1;
+                return Ok(());
+            }
+        }
+        self.flush()?;
+        self.run = Run::Zero(1);
+        Ok(())
+    }
+
+    fn flush(&mut self) -> Result<()> {
+        let run = std::mem::replace(&mut self.run, Run::None);
+        self.write_run(run)
+            .map_err(context!("error writing delta data"))
+    }
+
+    fn write_run(&mut self, run: Run) -> io::Result<()> {
+        match run {
+            Run::None => Ok(()),
+            Run::Copy { start, count } => {
+                self.out.write_u8(OP_COPY)?;
+                self.out.write_u32::<BigEndian>(count)?;
+                self.out.write_u32::<BigEndian>(start)
+            },
+            Run::Data(data) => {
+                self.out.write_u8(OP_DATA)?;
+                self.out.write_u32::<BigEndian>((data.len() / BLOCK_SIZE) as u32)?;
+                self.out.write_all(&data)
+            },
+            Run::Zero(count) => {
+                self.out.write_u8(OP_ZERO)?;
+                self.out.write_u32::<BigEndian>(count)
+            },
+        }
+    }
+
+    fn finish(mut self) -> Result<usize> {
+        self.flush()?;
+        self.out.write_u8(OP_END)
+            .and_then(|_| self.out.write_u32::<BigEndian>(0))
+            .map_err(context!("error writing delta data"))?;
+        Ok(self.data_blocks)
+    }
+}
+
+#[test]
+fn test_delta_roundtrip() {
+    use std::io::Cursor;
+
+    let block = |b: u8| vec![b; BLOCK_SIZE];
+    let base: Vec<u8> = [block(1), block(2), block(3), block(4), block(5)].concat();
+    let target: Vec<u8> = [block(1), block(2), block(9), block(4), block(0), block(3), block(5)].concat();
+
+    let mut delta = Vec::new();
+    let ndata = ImageDelta::generate(&mut Cursor::new(&base), 0, 5, &mut Cursor::new(&target), 7, &mut delta).unwrap();
+    assert_eq!(ndata, 1);
+
+    let mut out = Vec::new();
+    let n = ImageDelta::apply(&mut Cursor::new(&base), 0, &mut Cursor::new(&delta), &mut out).unwrap();
+    assert_eq!(n, 7);
+    assert!(out == target);
+}
@@ -5,7 +5,7 @@ use std::path::Path;
 use toml;
 
 use crate::blockdev::AlignedBuffer;
-use crate::{Result, BlockDev, public_key_for_channel, PublicKey};
+use crate::{Result, BlockDev, public_key_for_channel, trust_policy_for_channel, PublicKey, TrustPolicy};
 use std::sync::{Arc, Mutex, RwLock, RwLockReadGuard, RwLockWriteGuard};
 use std::sync::atomic::{Ordering,AtomicIsize};
 use std::os::unix::fs::MetadataExt;
@@ -20,8 +20,26 @@ const METAINFO_OFFSET: usize = 8;
 /// Signature is 64 bytes long
 const SIGNATURE_LENGTH: usize = 64;
 
+/// Expected magic value at start of boot attempt counter
+const BOOT_TRIES_MAGIC: &[u8] = b"SGBT";
+
+/// Boot attempt counter is 4 byte magic followed by 1 byte count stored at the end of the block
+const BOOT_TRIES_OFFSET: usize = ImageHeader::HEADER_SIZE - (BOOT_TRIES_MAGIC.len() + 1);
+
 /// Maximum amount of space in block for metainfo document
-const MAX_METAINFO_LEN: usize = ImageHeader::HEADER_SIZE - (METAINFO_OFFSET + SIGNATURE_LENGTH);
+const MAX_METAINFO_LEN: usize = BOOT_TRIES_OFFSET - (METAINFO_OFFSET + SIGNATURE_LENGTH);
+
+/// Expected magic value at start of signature block
+const SIGBLOCK_MAGIC: &[u8] = b"SGSB";
+
+/// Signature block header is 4 byte magic followed by 1 byte count of entries
+const SIGBLOCK_HEADER_LEN: usize = 5;
+
+/// Key id is the first 8 bytes of the public key
+const KEY_ID_LENGTH: usize = 8;
+
+/// Each signature block entry is a key id followed by a signature
+const SIGBLOCK_ENTRY_LEN: usize = KEY_ID_LENGTH + SIGNATURE_LENGTH;
 
 fn is_valid_status_code(code: u8) -> bool {
     code <= ImageHeader::STATUS_BAD_META
@@ -46,6 +64,10 @@ fn is_valid_status_code(code: u8) -> bool {
 ///
 ///    signature    64              8 + length
 ///
+///    sigblock  <variable>         72 + length     (optional)
+///
+///    boot tries   5                 4091           (optional)
+///
 /// magic     : Must match ascii bytes 'SGOS' for the header to be considered valid
 ///
 /// status    : One of the `STATUS` constants defined below
@@ -58,6 +80,15 @@ fn is_valid_status_code(code: u8) -> bool {
 ///
 /// signature : ed25519 signature over the bytes of the metainfo field
 ///
+/// sigblock  : Additional signatures over the metainfo field so that images can be
+///             signed by more than one key. Begins with the ascii bytes 'SGSB' followed
+///             by a 1 byte count of entries. Each entry is an 8 byte key id (the first
+///             8 bytes of the public key) followed by a 64 byte ed25519 signature.
+///
+/// boot tries: Number of remaining attempts to boot a rootfs partition in `STATUS_TRY_BOOT`
+///             before it is marked `STATUS_FAILED`. Begins with the ascii bytes 'SGBT'
+///             followed by a 1 byte count.
+///
 
 pub struct ImageHeader {
     buffer: RwLock<HeaderBytes>,
@@ -141,6 +172,8 @@ impl ImageHeader {
     pub const FLAG_PREFER_BOOT: u8 = 0x01; // Set to override usual strategy for choosing a partition to boot and force this one.
     pub const FLAG_HASH_TREE: u8 = 0x02; // dm-verity hash tree data is appended to the image
     pub const FLAG_DATA_COMPRESSED: u8 = 0x04; // The image data is compressed and needs to be uncompressed before use.
+    pub const FLAG_DELTA: u8 = 0x08; // The image data is a delta which must be applied to the image named by 'delta-base-shasum'
+    pub const FLAG_COMPRESSED_ZSTD: u8 = 0x10; // Set along with FLAG_DATA_COMPRESSED when the image data is compressed with zstd instead of xz
 
     pub const STATUS_INVALID: u8 = 0; // Set on partition before writing a new rootfs disk image
     pub const STATUS_NEW: u8 = 1; // Set on partition after write of new rootfs disk image completes successfully
@@ -311,6 +344,24 @@ impl ImageHeader {
         self.read_u8(5)
     }
 
+    /// Return the number of remaining boot attempts or `None` if the header does not
+    /// contain a boot attempt counter.
+    pub fn boot_tries(&self) -> Option<u8> {
+        if self.read_bytes(BOOT_TRIES_OFFSET, BOOT_TRIES_MAGIC.len()) != BOOT_TRIES_MAGIC {
+            return None;
+        }
+        Some(self.read_u8(BOOT_TRIES_OFFSET + BOOT_TRIES_MAGIC.len()))
+    }
+
+    pub fn set_boot_tries(&self, tries: u8) {
+        self.write_bytes(BOOT_TRIES_OFFSET, BOOT_TRIES_MAGIC);
+        self.write_u8(BOOT_TRIES_OFFSET + BOOT_TRIES_MAGIC.len(), tries);
+    }
+
+    pub fn clear_boot_tries(&self) {
+        self.write_bytes(BOOT_TRIES_OFFSET, &[0u8; BOOT_TRIES_MAGIC.len() + 1]);
+    }
+
     pub fn has_flag(&self, flag: u8) -> bool {
         (self.flags() & flag) == flag
     }
@@ -380,16 +431,97 @@ impl ImageHeader {
     pub fn clear_signature(&self) {
         let zeros = vec![0u8; SIGNATURE_LENGTH];
         self.set_signature(&zeros);
+        let offset = self.sigblock_offset();
+        self.write_bytes(offset, &vec![0u8; BOOT_TRIES_OFFSET - offset]);
+    }
+
+    fn sigblock_offset(&self) -> usize {
+        METAINFO_OFFSET + self.metainfo_len() + SIGNATURE_LENGTH
+    }
+
+    /// Return the entries of the signature block as a list of (key id, signature) pairs
+    /// where the key id is hex encoded.
+    pub fn signature_block(&self) -> Vec<(String, Vec<u8>)> {
+        let offset = self.sigblock_offset();
+        if offset + SIGBLOCK_HEADER_LEN > BOOT_TRIES_OFFSET || self.read_bytes(offset, SIGBLOCK_MAGIC.len()) != SIGBLOCK_MAGIC {
+            return Vec::new();
+        }
+        let count = self.read_u8(offset + SIGBLOCK_MAGIC.len()) as usize;
+        let max = (BOOT_TRIES_OFFSET - (offset + SIGBLOCK_HEADER_LEN)) / SIGBLOCK_ENTRY_LEN;
+        (0..count.min(max)).map(|i| {
+            let entry = offset + SIGBLOCK_HEADER_LEN + (i * SIGBLOCK_ENTRY_LEN);
+            let key_id = hex::encode(self.read_bytes(entry, KEY_ID_LENGTH));
+            let signature = self.read_bytes(entry + KEY_ID_LENGTH, SIGNATURE_LENGTH);
+            (key_id, signature)
+        }).collect()
+    }
+
+    /// Add a signature made with the key `pubkey` to this header.
+    ///
+    /// If the header does not yet have a signature, it is also stored as the primary
+    /// signature so that it can be verified by systems which only know a single channel key.
+    /// A signature already present in the signature block for the same key is replaced.
+    pub fn add_signature(&self, pubkey: &PublicKey, signature: &[u8]) -> Result<()> {
+        if signature.len() != SIGNATURE_LENGTH {
+            bail!("signature has invalid length: {}", signature.len());
+        }
+        if !self.has_signature() {
+            self.set_signature(signature);
+        }
+        let key_id = pubkey.key_id();
+        let mut entries = self.signature_block();
+        entries.retain(|(id,_)| *id != key_id);
+        entries.push((key_id, signature.to_vec()));
+        self.write_signature_block(&entries)
+    }
+
+    fn write_signature_block(&self, entries: &[(String, Vec<u8>)]) -> Result<()> {
+        let offset = self.sigblock_offset();
+        let len = SIGBLOCK_HEADER_LEN + (entries.len() * SIGBLOCK_ENTRY_LEN);
+        if offset + len > BOOT_TRIES_OFFSET || entries.len() > u8::MAX as usize {
+            bail!("not enough space in image header for {} signatures", entries.len());
+        }
+        let mut block = Vec::with_capacity(len);
+        block.extend_from_slice(SIGBLOCK_MAGIC);
+        block.push(entries.len() as u8);
+        for (key_id, signature) in entries {
+            let id = hex::decode(key_id)
+                .map_err(context!("error hex decoding key id {}", key_id))?;
+            if id.len() != KEY_ID_LENGTH {
+                bail!("key id {} has invalid length", key_id);
+            }
+            block.extend_from_slice(&id);
+            block.extend_from_slice(signature);
+        }
+        self.write_bytes(offset, &block);
+        Ok(())
     }
 
     pub fn public_key(&self) -> Result<Option<PublicKey>> {
         public_key_for_channel(self.metainfo().channel())
     }
 
+    /// Return the signature trust policy for the channel of this image.
+    pub fn trust_policy(&self) -> Result<Option<TrustPolicy>> {
+        trust_policy_for_channel(self.metainfo().channel())
+    }
+
     pub fn verify_signature(&self, pubkey: PublicKey) -> bool {
         pubkey.verify(&self.metainfo_bytes(), &self.signature())
     }
 
+    /// Return `true` if the signature made by `pubkey` is present in this header and is valid,
+    /// either as the primary signature or as an entry in the signature block.
+    pub fn has_valid_signature_from(&self, pubkey: &PublicKey) -> bool {
+        let metainfo = self.metainfo_bytes();
+        if self.has_signature() && pubkey.verify(&metainfo, &self.signature()) {
+            return true;
+        }
+        let key_id = pubkey.key_id();
+        self.signature_block().iter()
+            .any(|(id, sig)| *id == key_id && pubkey.verify(&metainfo, sig))
+    }
+
     pub fn write_header<W: Write>(&self, mut writer: W) -> io::Result<()> {
         self.with_bytes(|bs| writer.write_all(&bs.0))
     }
@@ -469,6 +601,12 @@ pub struct MetaInfo {
 
     #[serde(default, rename = "verity-root")]
     verity_root: String,
+
+    #[serde(rename = "delta-base-shasum")]
+    delta_base_shasum: Option<String>,
+
+    #[serde(rename = "delta-base-version")]
+    delta_base_version: Option<u32>,
 }
 
 impl MetaInfo {
@@ -532,8 +670,30 @@ impl MetaInfo {
         &self.verity_salt
     }
 
+    /// For delta images, the shasum of the image the delta must be applied to. The
+    /// `shasum` field is the shasum of the target image produced by applying the delta.
+    pub fn delta_base_shasum(&self) -> Option<&str> {
+        Self::str_ref(&self.delta_base_shasum)
+    }
+
+    pub fn delta_base_version(&self) -> Option<u32> {
+        self.delta_base_version
+    }
+
     pub fn verity_tag(&self) -> &str {
         &self.verity_root()[..8]
     }
 }
 
+
+#[test]
+fn test_boot_tries() {
+    let header = ImageHeader::new();
+    assert_eq!(header.boot_tries(), None);
+    header.set_boot_tries(3);
+    header.set_status(ImageHeader::STATUS_TRY_BOOT);
+    assert_eq!(header.boot_tries(), Some(3));
+    assert_eq!(header.status(), ImageHeader::STATUS_TRY_BOOT);
+    header.clear_boot_tries();
+    assert_eq!(header.boot_tries(), None);
+}
@@ -87,6 +87,14 @@ impl KeyRing {
         Ok(())
     }
 
+    /// Return the keypair stored in this keyring under `name`.
+    pub fn get_keypair(&self, name: &str) -> Result<KeyPair> {
+        match self.keypairs.get(name) {
+            Some(hex) => KeyPair::from_hex(hex),
+            None => bail!("no key named '{}' found in keyring", name),
+        }
+    }
+
     pub fn get_kernel_keypair(name: &str) -> Result<KeyPair> {
         let key = Self::get_key(name)?;
         let data = key.read()?;
@@ -34,6 +34,12 @@ impl PublicKey {
         hex::encode(&(self.0).0)
     }
 
+    /// Hex encoded first 8 bytes of the public key, used to identify which
+    /// key made each signature in an image header signature block.
+    pub fn key_id(&self) -> String {
+        hex::encode(&(self.0).0[..8])
+    }
+
     pub fn verify(&self, data: &[u8], signature: &[u8]) -> bool {
         let sig = sign::Signature::try_from(signature)
             .expect("Signature::from_slice() failed");
@@ -12,10 +12,14 @@ mod cmdline;
 mod header;
 mod partition;
 mod resource;
+mod delta;
+mod compression;
+mod manifest;
 pub mod util;
 pub mod verity;
 mod realmfs;
 mod keyring;
+mod trust;
 pub mod symlink;
 mod realm;
 pub mod terminal;
@@ -27,15 +31,28 @@ pub use crate::cmdline::CommandLine;
 pub use crate::header::{ImageHeader,MetaInfo};
 pub use crate::partition::Partition;
 pub use crate::resource::ResourceImage;
+pub use crate::delta::ImageDelta;
+pub use crate::compression::Compression;
 pub use crate::keys::{KeyPair,PublicKey,Signature};
-pub use crate::realmfs::{RealmFS,Mountpoint};
+pub use crate::trust::TrustPolicy;
+pub use crate::realmfs::{RealmFS,RealmFSGeneration,Mountpoint};
 pub use crate::keyring::{KeyRing,KernelKey};
 pub use crate::exec::{Exec,FileRange};
 pub use crate::realmfs::resizer::ResizeSize;
 pub use crate::realm::overlay::RealmOverlay;
 pub use crate::realm::realm::Realm;
-pub use crate::realm::config::{RealmConfig,OverlayType,GLOBAL_CONFIG};
+pub use crate::realm::snapshot::RealmSnapshot;
+pub use crate::realm::archive::{RealmArchive,RealmFSReference};
+pub use crate::realm::config::{RealmConfig,FirewallPolicy,NetworkZone,OverlayType,GLOBAL_CONFIG};
+pub use crate::realm::config_value::{ConfigKind,ConfigValue,ConfigError,CONFIG_VARIABLES,config_kind};
+pub use crate::realm::firewall::RealmFirewall;
+pub use crate::realm::limits::{ResourceLimits,LIMIT_VARIABLES};
+pub use crate::realm::stats::RealmStats;
+pub use crate::realm::stage::RealmStage;
+pub use crate::realm::health::RestartPolicy;
+pub use crate::realm::depends::{RealmDependencies,StopDependents};
 pub use crate::realm::events::RealmEvent;
+pub use crate::realm::journal::{EventJournal,JournalEntry,JournalEventKind};
 pub use crate::realm::realms::Realms;
 pub use crate::realm::manager::RealmManager;
 pub use crate::log::{LogLevel,Logger,DefaultLogOutput,LogOutput};
@@ -49,28 +66,14 @@ pub fn devkeys() -> KeyPair {
         .expect("Error parsing built in dev channel keys")
 }
 
+/// Return the first public key of the trust policy for `channel`.
 pub fn public_key_for_channel(channel: &str) -> Result<Option<PublicKey>> {
-    if channel == "dev" {
-        return Ok(Some(devkeys().public_key()));
-    }
-
-    // Look in /etc/os-release
-    if Some(channel) == OsRelease::citadel_channel() {
-        if let Some(hex) = OsRelease::citadel_image_pubkey() {
-            let pubkey = PublicKey::from_hex(hex)?;
-            return Ok(Some(pubkey));
-        }
-    }
-
-    // Does kernel command line have citadel.channel=name:[hex encoded pubkey]
-    if Some(channel) == CommandLine::channel_name() {
-        if let Some(hex) = CommandLine::channel_pubkey() {
-            let pubkey = PublicKey::from_hex(hex)?;
-            return Ok(Some(pubkey))
-        }
-    }
+    let policy = trust_policy_for_channel(channel)?;
+    Ok(policy.map(|p| p.keys()[0].clone()))
+}
 
-    Ok(None)
+pub fn trust_policy_for_channel(channel: &str) -> Result<Option<TrustPolicy>> {
+    TrustPolicy::for_channel(channel)
 }
 
 pub use error::{Result,Error};
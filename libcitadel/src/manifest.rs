@@ -0,0 +1,242 @@
+use std::fs;
+use std::path::{Component, Path, PathBuf};
+
+use crate::{CommandLine, Result, symlink, util};
+
+/// The `manifest.toml` file in the root directory of a resource image, which lists
+/// changes to make to the system rootfs after the image is mounted.
+///
+/// Entries are applied in the order in which they appear in the file:
+///
+///```toml
+/// [[entry]]
+/// type = "bind"                # "bind", "tmpfs" or "symlink"
+/// source = "/usr/share/foo"    # bind: path in the image, or on the rootfs if it
+///                              # begins with /sysroot/. symlink: contents of the link
+/// target = "/usr/share/foo"    # path on the rootfs, for bind defaults to source
+/// read-only = true             # bind: remount the target read-only
+/// create = true                # create the target (or the parent directory of a
+///                              # symlink) if it does not exist
+/// options = "size=64M"         # tmpfs: mount options
+/// required = true              # fail to mount the image if the entry fails
+/// if-cmdline = "citadel.live"  # only apply if the kernel command line has this
+///                              # variable, or `name=value` for a variable with a value
+/// unless-cmdline = "citadel.install"
+///```
+///
+/// Images without a `manifest.toml` may have a `manifest` file in the older format with
+/// one bind mount on each line.
+#[derive(Deserialize)]
+pub struct ResourceManifest {
+    #[serde(default)]
+    entry: Vec<ManifestEntry>,
+}
+
+#[derive(Deserialize)]
+struct ManifestEntry {
+    #[serde(rename = "type")]
+    kind: String,
+    source: Option<String>,
+    target: Option<String>,
+    #[serde(default, rename = "read-only")]
+    read_only: bool,
+    #[serde(default)]
+    create: bool,
+    options: Option<String>,
+    #[serde(default)]
+    required: bool,
+    #[serde(rename = "if-cmdline")]
+    if_cmdline: Option<String>,
+    #[serde(rename = "unless-cmdline")]
+    unless_cmdline: Option<String>,
+}
+
+impl ResourceManifest {
+    pub fn load(path: impl AsRef<Path>) -> Result<Self> {
+        let path = path.as_ref();
+        let s = util::read_to_string(path)?;
+        Self::parse(&s).map_err(context!("invalid manifest file {:?}", path))
+    }
+
+    fn parse(s: &str) -> Result<Self> {
+        let manifest = toml::from_str::<ResourceManifest>(s)
+            .map_err(context!("failed to parse manifest"))?;
+        for entry in &manifest.entry {
+            entry.validate()?;
+        }
+        Ok(manifest)
+    }
+
+    /// Apply the entries for a resource image mounted at `mount_path` to the rootfs
+    /// mounted at `/sysroot`. An entry which fails is skipped with a warning unless
+    /// it is marked `required`, in which case the error is returned and no further
+    /// entries are applied.
+    pub fn apply(&self, mount_path: &Path) -> Result<()> {
+        for entry in &self.entry {
+            if !entry.is_enabled() {
+                info!("Skipping manifest {} entry for {} because of kernel command line condition", entry.kind, entry.target());
+                continue;
+            }
+            if let Err(err) = entry.apply(mount_path) {
+                if entry.required {
+                    bail!("required manifest {} entry for {} failed: {}", entry.kind, entry.target(), err);
+                }
+                warn!("Skipping manifest {} entry for {}: {}", entry.kind, entry.target(), err);
+            }
+        }
+        Ok(())
+    }
+}
+
+impl ManifestEntry {
+    fn validate(&self) -> Result<()> {
+        match self.kind.as_str() {
+            "bind" => if self.source.is_none() {
+                bail!("bind entry requires source field");
+            },
+            "tmpfs" => if self.target.is_none() {
+                bail!("tmpfs entry requires target field");
+            },
+            "symlink" => if self.source.is_none() || self.target.is_none() {
+                bail!("symlink entry requires source and target fields");
+            },
+            kind => bail!("unknown manifest entry type '{}'", kind),
+        }
+        if self.options.is_some() && self.kind != "tmpfs" {
+            bail!("options field is only valid for tmpfs entries");
+        }
+        if self.read_only && self.kind != "bind" {
+            bail!("read-only field is only valid for bind entries");
+        }
+        Ok(())
+    }
+
+    fn source(&self) -> &str {
+        self.source.as_deref().unwrap_or_default()
+    }
+
+    fn target(&self) -> &str {
+        self.target.as_deref().unwrap_or_else(|| self.source())
+    }
+
+    // Option::is_none_or() requires Rust 1.82
+    #[allow(clippy::unnecessary_map_or)]
+    fn is_enabled(&self) -> bool {
+        self.if_cmdline.as_ref().map_or(true, |c| CommandLine::matches(c)) &&
+            self.unless_cmdline.as_ref().map_or(true, |c| !CommandLine::matches(c))
+    }
+
+    fn apply(&self, mount_path: &Path) -> Result<()> {
+        if Path::new(self.target()).components().any(|c| c == Component::ParentDir) {
+            bail!("target path {} contains '..'", self.target());
+        }
+        let target = Path::new("/sysroot").join(self.target().trim_start_matches('/'));
+        match self.kind.as_str() {
+            "bind" => self.apply_bind(mount_path, &target),
+            "tmpfs" => self.apply_tmpfs(&target),
+            "symlink" => self.apply_symlink(&target),
+            kind => bail!("unknown manifest entry type '{}'", kind),
+        }
+    }
+
+    fn apply_bind(&self, mount_path: &Path, target: &Path) -> Result<()> {
+        let source = if self.source().starts_with("/sysroot/") {
+            PathBuf::from(self.source())
+        } else {
+            mount_path.join(self.source().trim_start_matches('/'))
+        };
+        if !source.exists() {
+            bail!("source path {} does not exist", source.display());
+        }
+        self.ensure_target(target, source.is_dir())?;
+        info!("Bind mounting {} to {} from manifest", source.display(), target.display());
+        util::mount(source.to_string_lossy(), target, Some("--bind"))?;
+        if self.read_only {
+            if let Err(err) = cmd!("/usr/bin/mount", "-o remount,bind,ro {}", target.display()) {
+                // don't leave a writable bind mount behind
+                if let Err(e) = util::umount(target) {
+                    warn!("Failed to unmount {}: {}", target.display(), e);
+                }
+                bail!("failed to remount {:?} read-only: {}", target, err);
+            }
+        }
+        Ok(())
+    }
+
+    fn apply_tmpfs(&self, target: &Path) -> Result<()> {
+        self.ensure_target(target, true)?;
+        let options = match self.options {
+            Some(ref options) => format!("-t tmpfs -o {}", options),
+            None => "-t tmpfs".to_string(),
+        };
+        info!("Mounting tmpfs on {} from manifest", target.display());
+        util::mount("tmpfs", target, Some(&options))
+    }
+
+    fn apply_symlink(&self, target: &Path) -> Result<()> {
+        if let Ok(meta) = fs::symlink_metadata(target) {
+            if !meta.file_type().is_symlink() {
+                bail!("{} exists and is not a symlink", target.display());
+            }
+        }
+        if let Some(parent) = target.parent() {
+            if !parent.exists() && !self.create {
+                bail!("parent directory {} does not exist", parent.display());
+            }
+        }
+        info!("Creating symlink {} -> {} from manifest", target.display(), self.source());
+        symlink::write(self.source(), target, false)
+    }
+
+    fn ensure_target(&self, target: &Path, directory: bool) -> Result<()> {
+        if target.exists() {
+            return Ok(());
+        }
+        if !self.create {
+            bail!("target path {} does not exist", target.display());
+        }
+        if directory {
+            util::create_dir(target)
+        } else {
+            if let Some(parent) = target.parent() {
+                util::create_dir(parent)?;
+            }
+            util::write_file(target, "")
+        }
+    }
+}
+
+#[test]
+fn test_parse_manifest() {
+    let manifest = ResourceManifest::parse(r#"
+        [[entry]]
+        type = "tmpfs"
+        target = "/var/cache/foo"
+        options = "size=64M,mode=0755"
+
+        [[entry]]
+        type = "bind"
+        source = "/usr/share/foo"
+        read-only = true
+        required = true
+        if-cmdline = "citadel.live"
+
+        [[entry]]
+        type = "symlink"
+        source = "/usr/share/foo/bin/foo"
+        target = "/usr/bin/foo"
+        create = true
+    "#).unwrap();
+
+    assert_eq!(manifest.entry.len(), 3);
+    assert_eq!(manifest.entry[1].target(), "/usr/share/foo");
+    assert!(manifest.entry[1].read_only && manifest.entry[1].required);
+    assert_eq!(manifest.entry[2].target(), "/usr/bin/foo");
+
+    assert!(ResourceManifest::parse("[[entry]]\ntype = \"overlay\"\ntarget = \"/\"").is_err());
+    assert!(ResourceManifest::parse("[[entry]]\ntype = \"symlink\"\ntarget = \"/usr/bin/foo\"").is_err());
+    assert!(ResourceManifest::parse("[[entry]]\ntype = \"bind\"\nsource = \"/a\"\noptions = \"ro\"").is_err());
+
+    let escape = ResourceManifest::parse("[[entry]]\ntype = \"tmpfs\"\ntarget = \"/var/../../etc\"").unwrap();
+    assert!(escape.entry[0].apply(Path::new("/nonexistent")).is_err());
+}
@@ -2,7 +2,7 @@ use std::fs;
 use std::path::{Path,PathBuf};
 use std::sync::Arc;
 
-use crate::{Result, ImageHeader, MetaInfo, Mounts, PublicKey, public_key_for_channel,util};
+use crate::{Result, ImageHeader, MetaInfo, Mounts, TrustPolicy, trust_policy_for_channel, util};
 
 
 #[derive(Clone)]
@@ -16,10 +16,14 @@ pub struct Partition {
 struct HeaderInfo {
     header: Arc<ImageHeader>,
     // None if no public key available for channel named in metainfo
-    pubkey: Option<PublicKey>,
+    policy: Option<TrustPolicy>,
 }
 
 impl Partition {
+    /// Number of times a new rootfs image is booted without being blessed before it
+    /// is marked `STATUS_FAILED`.
+    pub const BOOT_TRIES: u8 = 3;
+
     pub fn rootfs_partitions() -> Result<Vec<Self>> {
         let mut v = Vec::new();
         for path in rootfs_partition_paths()? {
@@ -43,17 +47,17 @@ impl Partition {
         }
 
         let metainfo = header.metainfo();
-        let pubkey = match public_key_for_channel(metainfo.channel()) {
+        let policy = match trust_policy_for_channel(metainfo.channel()) {
             Ok(result) => result,
             Err(err) => {
-                warn!("Error parsing pubkey for channel '{}': {}", metainfo.channel(), err);
+                warn!("Error parsing pubkeys for channel '{}': {}", metainfo.channel(), err);
                 None
             }
         };
 
         let header = Arc::new(header);
         Ok(Some(HeaderInfo {
-            header, pubkey,
+            header, policy,
         }))
     }
 
@@ -90,6 +94,10 @@ impl Partition {
         self.header().status() == ImageHeader::STATUS_NEW
     }
 
+    pub fn is_trying_boot(&self) -> bool {
+        self.header().status() == ImageHeader::STATUS_TRY_BOOT
+    }
+
     pub fn is_good(&self) -> bool {
         self.header().status() == ImageHeader::STATUS_GOOD
     }
@@ -102,23 +110,21 @@ impl Partition {
         self.header().status() == ImageHeader::STATUS_BAD_SIG
     }
 
+    /// Return `true` if the header carries enough valid signatures to satisfy
+    /// the trust policy of the channel.
     pub fn is_signature_valid(&self) -> bool {
-        if let Some(ref hinfo) = self.hinfo {
-            if let Some(ref pubkey) = hinfo.pubkey {
-                return pubkey.verify(
-                    &self.header().metainfo_bytes(),
-                    &self.header().signature())
-            }
+        match self.trust_policy() {
+            Some(policy) => policy.verify(self.header()),
+            None => false,
         }
-        false
     }
 
     pub fn has_public_key(&self) -> bool {
-        if let Some(ref h) = self.hinfo {
-            h.pubkey.is_some()
-        } else {
-            false
-        }
+        self.trust_policy().is_some()
+    }
+
+    pub fn trust_policy(&self) -> Option<&TrustPolicy> {
+        self.hinfo.as_ref().and_then(|h| h.policy.as_ref())
     }
 
     pub fn write_status(&mut self, status: u8) -> Result<()> {
@@ -143,7 +149,9 @@ impl Partition {
     /// Called at boot to perform various checks and possibly
     /// update the status field to an error state.
     ///
-    /// Mark `STATUS_TRY_BOOT` partition as `STATUS_FAILED`.
+    /// A `STATUS_TRY_BOOT` partition was booted without being blessed,
+    /// so decrement the boot attempt counter and mark the partition
+    /// as `STATUS_FAILED` when no attempts remain.
     ///
     /// If a partition that had prior signature failure now
     /// has a valid signature set to STATUS_NEW
@@ -152,9 +160,14 @@ impl Partition {
         if !self.is_initialized() {
             return Ok(())
         }
-        if self.header().status() == ImageHeader::STATUS_TRY_BOOT {
-            warn!("Partition {} has STATUS_TRY_BOOT, assuming it failed boot attempt and marking STATUS_FAILED", self.path().display());
-            self.write_status(ImageHeader::STATUS_FAILED)?;
+        if self.is_trying_boot() {
+            let tries = Self::count_boot_attempt(self.header());
+            if tries == 0 {
+                warn!("Partition {} has STATUS_TRY_BOOT and no boot attempts remaining, marking STATUS_FAILED", self.path().display());
+            } else {
+                warn!("Partition {} has STATUS_TRY_BOOT and was not blessed, {} boot attempts remaining", self.path().display(), tries);
+            }
+            self.write_header()?;
         }
         if self.is_sig_failed() && self.is_signature_valid() {
             self.write_status(ImageHeader::STATUS_NEW)?;
@@ -162,12 +175,42 @@ impl Partition {
         Ok(())
     }
 
+    /// Called at boot on the partition chosen to boot. A `STATUS_NEW` partition
+    /// is set to `STATUS_TRY_BOOT` with `BOOT_TRIES` boot attempts remaining.
+    pub fn start_boot_attempt(&mut self) -> Result<()> {
+        if self.is_new() {
+            info!("Trying boot of new rootfs partition {}", self.path().display());
+            self.header().set_boot_tries(Self::BOOT_TRIES);
+            self.write_status(ImageHeader::STATUS_TRY_BOOT)?;
+        }
+        Ok(())
+    }
+
     pub fn bless(&mut self) -> Result<()> {
-        if self.header().status() == ImageHeader::STATUS_TRY_BOOT {
-            self.write_status(ImageHeader::STATUS_GOOD)?;
+        if self.is_trying_boot() {
+            Self::bless_header(self.header());
+            self.write_header()?;
         }
         Ok(())
     }
+
+    // Decrement the boot attempts remaining in a `STATUS_TRY_BOOT` header and set the
+    // status to `STATUS_FAILED` if no attempts remain. Returns the attempts remaining.
+    fn count_boot_attempt(header: &ImageHeader) -> u8 {
+        let tries = header.boot_tries().unwrap_or(0).saturating_sub(1);
+        if tries == 0 {
+            header.clear_boot_tries();
+            header.set_status(ImageHeader::STATUS_FAILED);
+        } else {
+            header.set_boot_tries(tries);
+        }
+        tries
+    }
+
+    fn bless_header(header: &ImageHeader) {
+        header.clear_boot_tries();
+        header.set_status(ImageHeader::STATUS_GOOD);
+    }
 }
 
 fn is_in_use(path: &Path) -> Result<bool> {
@@ -232,3 +275,23 @@ fn path_filename(path: &Path) -> &str {
     ""
 }
 
+#[test]
+fn test_boot_attempts() {
+    let header = ImageHeader::new();
+    header.set_status(ImageHeader::STATUS_TRY_BOOT);
+    header.set_boot_tries(Partition::BOOT_TRIES);
+
+    assert_eq!(Partition::count_boot_attempt(&header), 2);
+    assert_eq!(Partition::count_boot_attempt(&header), 1);
+    assert_eq!(header.status(), ImageHeader::STATUS_TRY_BOOT);
+    assert_eq!(Partition::count_boot_attempt(&header), 0);
+    assert_eq!(header.status(), ImageHeader::STATUS_FAILED);
+    assert_eq!(header.boot_tries(), None);
+
+    header.set_status(ImageHeader::STATUS_TRY_BOOT);
+    header.set_boot_tries(Partition::BOOT_TRIES);
+    assert_eq!(Partition::count_boot_attempt(&header), 2);
+    Partition::bless_header(&header);
+    assert_eq!(header.status(), ImageHeader::STATUS_GOOD);
+    assert_eq!(header.boot_tries(), None);
+}
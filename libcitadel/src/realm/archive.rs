@@ -0,0 +1,212 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use sodiumoxide::randombytes::randombytes;
+
+use crate::{KeyPair, PublicKey, Realm, RealmManager, Realms, Result, util};
+
+const TAR: &str = "/usr/bin/tar";
+
+// Version of the archive format written by `export()`
+const ARCHIVE_VERSION: u32 = 1;
+
+const MANIFEST: &str = "manifest";
+const MANIFEST_SIGNATURE: &str = "manifest.sig";
+const PAYLOAD: &str = "realm.tar";
+
+/// Files and directories of a realm directory which are included in an archive.
+pub(crate) const ARCHIVE_FILES: &[&str] = &["config", "notes", "home", "skel"];
+
+///
+/// A RealmFS which an exported realm was using.
+///
+/// The `shasum` is the shasum from the metainfo of the image, which identifies the
+/// exact content of the RealmFS.
+///
+#[derive(Serialize,Deserialize,Clone)]
+pub struct RealmFSReference {
+    pub name: String,
+    pub shasum: String,
+}
+
+#[derive(Serialize,Deserialize)]
+struct ArchiveManifest {
+    version: u32,
+
+    #[serde(rename="realm-name")]
+    realm_name: String,
+
+    #[serde(rename="payload-shasum")]
+    payload_shasum: String,
+
+    #[serde(rename="public-key")]
+    public_key: String,
+
+    realmfs: Option<RealmFSReference>,
+}
+
+///
+/// An exported realm stored as a single archive file which can be imported on another system.
+///
+/// The archive is a tar file with three members:
+///
+///```text
+///     manifest        TOML description of the archive
+///     manifest.sig    hex encoded signature of the manifest
+///     realm.tar       config, notes, home and skel from the realm directory
+///```
+///
+/// The manifest contains the sha256 of `realm.tar` and the public key which signed the
+/// manifest.
+///
+pub struct RealmArchive {
+    manifest: ArchiveManifest,
+    workdir: PathBuf,
+}
+
+impl RealmArchive {
+
+    fn create_workdir(prefix: &str) -> Result<PathBuf> {
+        let tmpdir = Path::new(Realms::BASE_PATH).join(".tmp");
+        let workdir = tmpdir.join(format!("{}-{}", prefix, hex::encode(randombytes(8))));
+        util::create_dir(&workdir)?;
+        Ok(workdir)
+    }
+
+    /// Extract the archive file at `path` and verify that the manifest is signed by
+    /// `public_key` and that the payload matches the manifest.
+    pub fn open(path: &Path, public_key: &PublicKey) -> Result<Self> {
+        let workdir = Self::create_workdir("import")?;
+        match Self::extract(path, &workdir, public_key) {
+            Ok(manifest) => Ok(RealmArchive { manifest, workdir }),
+            Err(err) => {
+                let _ = fs::remove_dir_all(&workdir);
+                Err(err)
+            }
+        }
+    }
+
+    fn extract(path: &Path, workdir: &Path, public_key: &PublicKey) -> Result<ArchiveManifest> {
+        cmd!(TAR, "-C {} -xf {} {} {} {}", workdir.display(), path.display(), MANIFEST, MANIFEST_SIGNATURE, PAYLOAD)
+            .map_err(context!("failed to extract realm archive {:?}", path))?;
+
+        let manifest_bytes = fs::read(workdir.join(MANIFEST))
+            .map_err(context!("failed to read manifest from realm archive {:?}", path))?;
+        let signature = util::read_to_string(workdir.join(MANIFEST_SIGNATURE))?;
+        let signature = hex::decode(signature.trim())
+            .map_err(context!("failed to decode manifest signature of realm archive {:?}", path))?;
+
+        if !public_key.verify(&manifest_bytes, &signature) {
+            bail!("realm archive {:?} is not signed by key {}", path, public_key.key_id());
+        }
+
+        let manifest = toml::from_slice::<ArchiveManifest>(&manifest_bytes)
+            .map_err(context!("failed to parse manifest of realm archive {:?}", path))?;
+
+        if manifest.version != ARCHIVE_VERSION {
+            bail!("realm archive {:?} has unsupported version {}", path, manifest.version);
+        }
+        if manifest.public_key != public_key.to_hex() {
+            bail!("public key in manifest of realm archive {:?} does not match signing key", path);
+        }
+        if !Realm::is_valid_name(&manifest.realm_name) {
+            bail!("realm archive {:?} contains invalid realm name '{}'", path, manifest.realm_name);
+        }
+
+        let shasum = util::sha256(workdir.join(PAYLOAD))?;
+        if shasum != manifest.payload_shasum {
+            bail!("realm archive {:?} payload does not match shasum in manifest", path);
+        }
+        Ok(manifest)
+    }
+
+    /// Name of the realm when it was exported.
+    pub fn realm_name(&self) -> &str {
+        &self.manifest.realm_name
+    }
+
+    /// The RealmFS the exported realm was using, if any.
+    pub fn realmfs(&self) -> Option<&RealmFSReference> {
+        self.manifest.realmfs.as_ref()
+    }
+
+    /// Path to the verified tar file containing the realm directory files.
+    pub fn payload(&self) -> PathBuf {
+        self.workdir.join(PAYLOAD)
+    }
+
+    /// Log a warning if the RealmFS this archive references is missing or has
+    /// different content on this system.
+    pub fn check_realmfs(&self, manager: &RealmManager) {
+        let reference = match self.realmfs() {
+            Some(reference) => reference,
+            None => return,
+        };
+        match manager.realmfs_by_name(&reference.name) {
+            None => warn!("Imported realm requires RealmFS '{}' which does not exist on this system", reference.name),
+            Some(realmfs) if realmfs.metainfo().shasum() != reference.shasum => {
+                warn!("RealmFS '{}' on this system differs from the image used by the exported realm", reference.name);
+            },
+            Some(_) => {},
+        }
+    }
+}
+
+impl Drop for RealmArchive {
+    fn drop(&mut self) {
+        if let Err(err) = fs::remove_dir_all(&self.workdir) {
+            warn!("Failed to remove realm archive work directory {:?}: {}", self.workdir, err);
+        }
+    }
+}
+
+/// Write the files of `realm` to a new archive file at `target` and sign the
+/// archive with `keys`.
+pub fn export(manager: &RealmManager, realm: &Realm, target: &Path, keys: &KeyPair) -> Result<()> {
+    if target.exists() {
+        bail!("Cannot export realm to {:?} because file already exists", target);
+    }
+    let workdir = RealmArchive::create_workdir("export")?;
+    let result = write_archive(manager, realm, target, keys, &workdir);
+    if let Err(err) = fs::remove_dir_all(&workdir) {
+        warn!("Failed to remove realm export work directory {:?}: {}", workdir, err);
+    }
+    result
+}
+
+fn write_archive(manager: &RealmManager, realm: &Realm, target: &Path, keys: &KeyPair, workdir: &Path) -> Result<()> {
+    let members = ARCHIVE_FILES.iter()
+        .filter(|name| realm.base_path_file(name).exists())
+        .cloned()
+        .collect::<Vec<_>>()
+        .join(" ");
+
+    let payload = workdir.join(PAYLOAD);
+    cmd!(TAR, "-C {} --numeric-owner -cpf {} {}", realm.base_path().display(), payload.display(), members)
+        .map_err(context!("failed to archive directory of realm-{}", realm.name()))?;
+
+    let realmfs = manager.realmfs_by_name(realm.config().realmfs())
+        .map(|realmfs| RealmFSReference {
+            name: realmfs.name().to_string(),
+            shasum: realmfs.metainfo().shasum().to_string(),
+        });
+
+    let manifest = ArchiveManifest {
+        version: ARCHIVE_VERSION,
+        realm_name: realm.name().to_string(),
+        payload_shasum: util::sha256(&payload)?,
+        public_key: keys.public_key().to_hex(),
+        realmfs,
+    };
+    let manifest_bytes = toml::to_string(&manifest)
+        .map_err(context!("failed to serialize realm archive manifest"))?;
+    let signature = keys.sign(manifest_bytes.as_bytes());
+
+    util::write_file(workdir.join(MANIFEST), &manifest_bytes)?;
+    util::write_file(workdir.join(MANIFEST_SIGNATURE), hex::encode(signature.to_bytes()))?;
+
+    cmd!(TAR, "-C {} -cf {} {} {} {}", workdir.display(), target.display(), MANIFEST, MANIFEST_SIGNATURE, PAYLOAD)
+        .map_err(context!("failed to write realm archive {:?}", target))?;
+    info!("Exported realm-{} to {}", realm.name(), target.display());
+    Ok(())
+}
@@ -1,16 +1,20 @@
+use std::collections::BTreeMap;
 use std::path::{Path, PathBuf};
 use std::fs;
 use std::os::unix::fs::MetadataExt;
 use toml;
-use crate::{Result, Realms, util};
+use crate::{Result, Realms, RestartPolicy, util};
 
 lazy_static! {
     pub static ref GLOBAL_CONFIG: RealmConfig = RealmConfig::load_global_config();
 }
 
-const DEFAULT_ZONE: &str = "clear";
+pub(crate) const DEFAULT_ZONE: &str = "clear";
+pub(crate) const DEFAULT_ZONE_NETWORK: &str = "172.17.0.0/24";
 const DEFAULT_REALMFS: &str = "base";
 const DEFAULT_OVERLAY: &str = "storage";
+const DEFAULT_REALMFS_GENERATIONS: usize = 2;
+const DEFAULT_HEALTH_CHECK_INTERVAL: u32 = 60;
 
 /// Type of rootfs overlay a Realm is configured to use
 #[derive(PartialEq,Debug,Copy,Clone)]
@@ -44,6 +48,116 @@ impl OverlayType {
     }
 }
 
+// Bridge interface names created by systemd-nspawn for a zone are 'vz-' followed
+// by the zone name and interface names are limited to 15 characters.
+const MAX_ZONE_NAME_LEN: usize = 12;
+
+/// A network zone defined in the global realm configuration.
+///
+/// Realms placed in the same zone share a bridge interface `vz-$ZONE` created by
+/// systemd-nspawn and are assigned addresses from the zone network:
+///
+///```text
+///     [network-zones.work]
+///     network = "172.18.0.0/24"
+///     gateway = "172.18.0.1"
+///     network6 = "fd8a:1c3e:77f0:1::/64"
+///```
+///
+/// If no gateway is configured, the first address of the network is used.
+///
+/// If the zone has a /64 ULA prefix `network6`, realms are also assigned an IPv6
+/// address from it. Zones without `network6` are IPv4 only.
+#[derive (Serialize,Deserialize,Clone,PartialEq)]
+pub struct NetworkZone {
+    pub network: String,
+    pub gateway: Option<String>,
+    pub network6: Option<String>,
+}
+
+impl NetworkZone {
+
+    /// Return `true` if `name` is a valid name for a network zone.
+    pub fn is_valid_name(name: &str) -> bool {
+        !name.is_empty() && name.len() <= MAX_ZONE_NAME_LEN &&
+            name.chars().all(|c| c.is_ascii_alphanumeric() || c == '-')
+    }
+
+    /// The zone which realms are placed in when `network-zone` is not configured.
+    pub fn default_zone() -> Self {
+        NetworkZone {
+            network: DEFAULT_ZONE_NETWORK.to_string(),
+            gateway: None,
+            network6: None,
+        }
+    }
+}
+
+/// Firewall policy for traffic leaving a realm on its network zone.
+///
+/// Stored as a `[firewall]` table in the realm configuration file:
+///
+///```text
+///     [firewall]
+///     default-outbound = "deny"
+///     allow-outbound = [ "example.com:443", "10.1.2.0/24:53:udp" ]
+///     isolate-lan = true
+///     allow-realms = [ "apt-cacher" ]
+///```
+///
+/// Entries in `allow-outbound` have the form `HOST[:PORTS][:PROTO]` where `HOST` is an
+/// address, network or hostname, `PORTS` is a port, a list of ports separated by
+/// commas or a range such as `6000-6010`, and `PROTO` is either `tcp` or `udp`. IPv6
+/// addresses and networks must be enclosed in brackets such as `[2001:db8::/32]:443`.
+#[derive (Serialize,Deserialize,Clone,Default)]
+pub struct FirewallPolicy {
+    #[serde(rename="default-outbound")]
+    pub default_outbound: Option<String>,
+
+    #[serde(rename="allow-outbound")]
+    pub allow_outbound: Option<Vec<String>>,
+
+    #[serde(rename="isolate-lan")]
+    pub isolate_lan: Option<bool>,
+
+    #[serde(rename="allow-realms")]
+    pub allow_realms: Option<Vec<String>>,
+}
+
+impl FirewallPolicy {
+
+    /// If `true` outbound traffic which does not match an `allow-outbound` entry is permitted.
+    pub fn default_outbound_allowed(&self) -> bool {
+        match self.default_outbound.as_deref() {
+            None | Some("allow") => true,
+            Some("deny") => false,
+            Some(value) => {
+                warn!("Invalid firewall default-outbound value: '{}'", value);
+                false
+            },
+        }
+    }
+
+    /// A list of hosts and ports which the realm is permitted to connect to.
+    pub fn allow_outbound(&self) -> Vec<&str> {
+        self.allow_outbound.as_ref()
+            .map(|v| v.iter().map(|s| s.as_str()).collect())
+            .unwrap_or_default()
+    }
+
+    /// If `true` traffic to private networks other than the realm zone is dropped.
+    pub fn isolate_lan(&self) -> bool {
+        self.isolate_lan.unwrap_or(false)
+    }
+
+    /// If set, only the realms named in this list may be reached from this realm
+    /// on the zone bridge.
+    pub fn allow_realms(&self) -> Option<Vec<&str>> {
+        self.allow_realms.as_ref()
+            .map(|v| v.iter().map(|s| s.as_str()).collect())
+    }
+}
+
 /// Content of a Realm configuration file
 #[derive (Serialize,Deserialize,Clone)]
 pub struct RealmConfig {
@@ -103,6 +217,15 @@ pub struct RealmConfig {
     #[serde(rename="realm-depends")]
     pub realm_depends: Option<Vec<String>>,
 
+    #[serde(rename="restart-policy")]
+    pub restart_policy: Option<String>,
+
+    #[serde(rename="health-check")]
+    pub health_check: Option<String>,
+
+    #[serde(rename="health-check-interval")]
+    pub health_check_interval: Option<u32>,
+
     pub realmfs: Option<String>,
 
     #[serde(rename="terminal-scheme")]
@@ -112,6 +235,34 @@ pub struct RealmConfig {
 
     pub netns: Option<String>,
 
+    #[serde(rename="realmfs-generations")]
+    pub realmfs_generations: Option<usize>,
+
+    #[serde(rename="memory-max")]
+    pub memory_max: Option<String>,
+
+    #[serde(rename="memory-high")]
+    pub memory_high: Option<String>,
+
+    #[serde(rename="cpu-weight")]
+    pub cpu_weight: Option<u32>,
+
+    #[serde(rename="cpu-quota")]
+    pub cpu_quota: Option<String>,
+
+    #[serde(rename="io-weight")]
+    pub io_weight: Option<u32>,
+
+    #[serde(rename="tasks-max")]
+    pub tasks_max: Option<u32>,
+
+    // Fields which serialize as TOML tables must follow all plain values
+
+    #[serde(rename="network-zones")]
+    pub network_zones: Option<BTreeMap<String, NetworkZone>>,
+
+    pub firewall: Option<FirewallPolicy>,
+
     #[serde(skip)]
     pub parent: Option<Box<RealmConfig>>,
 
@@ -159,8 +310,12 @@ impl RealmConfig {
         util::write_file(path, serialized)
     }
 
-    pub fn write(&self) -> Result<()> {
-        self.write_to(&self.path)
+    /// Write the config to the config file it was loaded from and remember the new
+    /// modification time so that the written file is not considered stale.
+    pub fn write(&mut self) -> Result<()> {
+        self.write_to(&self.path)?;
+        self.loaded = Some(self.read_mtime());
+        Ok(())
     }
 
     fn read_mtime(&self) -> i64 {
@@ -171,6 +326,11 @@ impl RealmConfig {
         Some(self.read_mtime()) != self.loaded
     }
 
+    /// Return `true` if the config has been read from the config file at least once.
+    pub fn is_loaded(&self) -> bool {
+        self.loaded.is_some()
+    }
+
     pub fn reload(&mut self) -> Result<()> {
         let path = self.path.clone();
 
@@ -208,10 +368,22 @@ impl RealmConfig {
             extra_bindmounts: None,
             extra_bindmounts_ro: None,
             realm_depends: None,
+            restart_policy: None,
+            health_check: None,
+            health_check_interval: None,
             realmfs: Some(DEFAULT_REALMFS.into()),
             overlay: Some(DEFAULT_OVERLAY.into()),
             terminal_scheme: None,
             netns: None,
+            realmfs_generations: Some(DEFAULT_REALMFS_GENERATIONS),
+            memory_max: None,
+            memory_high: None,
+            cpu_weight: None,
+            cpu_quota: None,
+            io_weight: None,
+            tasks_max: None,
+            network_zones: None,
+            firewall: None,
             parent: None,
             loaded: None,
             path: PathBuf::new(),
@@ -238,11 +410,23 @@ impl RealmConfig {
             extra_bindmounts: None,
             extra_bindmounts_ro: None,
             realm_depends: None,
+            restart_policy: None,
+            health_check: None,
+            health_check_interval: None,
             ephemeral_persistent_dirs: None,
             realmfs: None,
             overlay: None,
             terminal_scheme: None,
             netns: None,
+            realmfs_generations: None,
+            memory_max: None,
+            memory_high: None,
+            cpu_weight: None,
+            cpu_quota: None,
+            io_weight: None,
+            tasks_max: None,
+            network_zones: None,
+            firewall: None,
             parent: None,
             loaded: None,
             path: PathBuf::new(),
@@ -390,6 +574,26 @@ impl RealmConfig {
         self.str_vec_value(|c| c.realm_depends.as_ref())
     }
 
+    /// When `realmsd` restarts this realm after it stops unexpectedly or its health
+    /// check fails. Defaults to `RestartPolicy::Never`.
+    pub fn restart_policy(&self) -> RestartPolicy {
+        self.str_value(|c| c.restart_policy.as_ref())
+            .and_then(RestartPolicy::from_name)
+            .unwrap_or(RestartPolicy::Never)
+    }
+
+    /// A shell command run as root inside the running realm to check that it is
+    /// working. The realm is considered unhealthy if the command fails repeatedly.
+    pub fn health_check(&self) -> Option<&str> {
+        self.str_value(|c| c.health_check.as_ref())
+    }
+
+    /// Seconds between runs of the health check command.
+    pub fn health_check_interval(&self) -> u32 {
+        self.u32_value(|c| c.health_check_interval)
+            .unwrap_or(DEFAULT_HEALTH_CHECK_INTERVAL)
+    }
+
     /// The name of a RealmFS to use as the root filesystem for this realm.
     pub fn realmfs(&self) -> &str {
         self.str_value(|c| c.realmfs.as_ref()).unwrap_or(DEFAULT_REALMFS)
@@ -420,6 +624,76 @@ impl RealmConfig {
         self.netns().is_some()
     }
 
+    /// The number of previous generations of each RealmFS image to keep when an
+    /// image is updated. Only meaningful in the global realm configuration.
+    pub fn realmfs_generations(&self) -> usize {
+        if let Some(n) = self.realmfs_generations {
+            n
+        } else if let Some(ref parent) = self.parent {
+            parent.realmfs_generations()
+        } else {
+            DEFAULT_REALMFS_GENERATIONS
+        }
+    }
+
+    /// Hard limit on memory use of the realm, such as `4G`. Becomes `MemoryMax=`
+    /// of the realm service unit.
+    pub fn memory_max(&self) -> Option<&str> {
+        self.str_value(|c| c.memory_max.as_ref())
+    }
+
+    /// Memory use above which processes of the realm are throttled and reclaimed
+    /// aggressively. Becomes `MemoryHigh=` of the realm service unit.
+    pub fn memory_high(&self) -> Option<&str> {
+        self.str_value(|c| c.memory_high.as_ref())
+    }
+
+    /// Relative share of CPU time (1 - 10000) given to the realm.
+    pub fn cpu_weight(&self) -> Option<u32> {
+        self.u32_value(|c| c.cpu_weight)
+    }
+
+    /// Absolute limit on CPU time of the realm as a percentage of one CPU, such as `50%`.
+    pub fn cpu_quota(&self) -> Option<&str> {
+        self.str_value(|c| c.cpu_quota.as_ref())
+    }
+
+    /// Relative share of block IO bandwidth (1 - 10000) given to the realm.
+    pub fn io_weight(&self) -> Option<u32> {
+        self.u32_value(|c| c.io_weight)
+    }
+
+    /// Maximum number of tasks (processes and threads) which may run in the realm.
+    pub fn tasks_max(&self) -> Option<u32> {
+        self.u32_value(|c| c.tasks_max)
+    }
+
+    /// The network zones defined in the configuration. The default `clear` zone is
+    /// always included. Only meaningful in the global realm configuration.
+    pub fn network_zones(&self) -> BTreeMap<String, NetworkZone> {
+        let mut zones = if let Some(ref zones) = self.network_zones {
+            zones.clone()
+        } else if let Some(ref parent) = self.parent {
+            parent.network_zones()
+        } else {
+            BTreeMap::new()
+        };
+        zones.entry(DEFAULT_ZONE.to_string())
+            .or_insert_with(NetworkZone::default_zone);
+        zones
+    }
+
+    /// Firewall policy to install for this realm when it is started, if any.
+    pub fn firewall(&self) -> Option<&FirewallPolicy> {
+        if let Some(ref policy) = self.firewall {
+            Some(policy)
+        } else if let Some(ref parent) = self.parent {
+            parent.firewall()
+        } else {
+            None
+        }
+    }
+
     fn str_vec_value<F>(&self, get: F) -> Vec<&str>
         where F: Fn(&RealmConfig) -> Option<&Vec<String>>
     {
@@ -444,6 +718,18 @@ impl RealmConfig {
         None
     }
 
+    fn u32_value<F>(&self, get: F) -> Option<u32>
+        where F: Fn(&RealmConfig) -> Option<u32>
+    {
+        if let Some(val) = get(self) {
+            return Some(val)
+        }
+        if let Some(ref parent) = self.parent {
+            return parent.u32_value(get);
+        }
+        None
+    }
+
     fn bool_value<F>(&self, get: F) -> bool
         where F: Fn(&RealmConfig) -> Option<bool>
     {
@@ -0,0 +1,429 @@
+use std::fmt;
+
+use crate::{FirewallPolicy, NetworkZone, OverlayType, Realm, RealmConfig, RealmFS, ResourceLimits, RestartPolicy, GLOBAL_CONFIG};
+
+// Lowest value of the last address octet which can be used for `reserved-ip`
+const RESERVED_IP_START: i64 = 200;
+
+/// The type of value stored in a realm configuration variable.
+#[derive(Clone,Copy,PartialEq,Debug)]
+pub enum ConfigKind {
+    Bool,
+    Integer,
+    String,
+    StringList,
+}
+
+impl ConfigKind {
+    pub fn name(self) -> &'static str {
+        match self {
+            ConfigKind::Bool => "boolean",
+            ConfigKind::Integer => "integer",
+            ConfigKind::String => "string",
+            ConfigKind::StringList => "string list",
+        }
+    }
+}
+
+/// A typed value of a realm configuration variable.
+#[derive(Clone,PartialEq,Debug)]
+pub enum ConfigValue {
+    Bool(bool),
+    Integer(i64),
+    String(String),
+    StringList(Vec<String>),
+}
+
+impl ConfigValue {
+
+    pub fn kind(&self) -> ConfigKind {
+        match self {
+            ConfigValue::Bool(_) => ConfigKind::Bool,
+            ConfigValue::Integer(_) => ConfigKind::Integer,
+            ConfigValue::String(_) => ConfigKind::String,
+            ConfigValue::StringList(_) => ConfigKind::StringList,
+        }
+    }
+
+    /// Parse the string form of a value of type `kind`. Lists are separated by commas.
+    pub fn parse(kind: ConfigKind, s: &str) -> Option<Self> {
+        match kind {
+            ConfigKind::Bool => match s {
+                "true" => Some(ConfigValue::Bool(true)),
+                "false" => Some(ConfigValue::Bool(false)),
+                _ => None,
+            },
+            ConfigKind::Integer => s.trim().parse().ok().map(ConfigValue::Integer),
+            ConfigKind::String => Some(ConfigValue::String(s.to_string())),
+            ConfigKind::StringList => {
+                let v = s.split(',')
+                    .map(|item| item.trim())
+                    .filter(|item| !item.is_empty())
+                    .map(String::from)
+                    .collect();
+                Some(ConfigValue::StringList(v))
+            },
+        }
+    }
+}
+
+impl fmt::Display for ConfigValue {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ConfigValue::Bool(b) => write!(f, "{}", b),
+            ConfigValue::Integer(n) => write!(f, "{}", n),
+            ConfigValue::String(s) => write!(f, "{}", s),
+            ConfigValue::StringList(v) => write!(f, "{}", v.join(",")),
+        }
+    }
+}
+
+/// A value which was rejected when setting a realm configuration variable.
+#[derive(Clone,Debug)]
+pub struct ConfigError {
+    variable: String,
+    message: String,
+}
+
+impl ConfigError {
+    fn new(variable: &str, message: impl Into<String>) -> Self {
+        ConfigError { variable: variable.to_string(), message: message.into() }
+    }
+
+    /// Name of the configuration variable the error applies to.
+    pub fn variable(&self) -> &str {
+        &self.variable
+    }
+
+    pub fn message(&self) -> &str {
+        &self.message
+    }
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}: {}", self.variable, self.message)
+    }
+}
+
+/// Names and types of all realm configuration variables which can be read and
+/// changed with `RealmConfig::get_value()` and `RealmConfig::set_value()`.
+///
+/// Variables of the firewall policy are named with a `firewall.` prefix.
+pub const CONFIG_VARIABLES: &[(&str, ConfigKind)] = &[
+    ("use-shared-dir", ConfigKind::Bool),
+    ("use-media-dir", ConfigKind::Bool),
+    ("use-ephemeral-home", ConfigKind::Bool),
+    ("ephemeral-persistent-dirs", ConfigKind::StringList),
+    ("use-sound", ConfigKind::Bool),
+    ("use-x11", ConfigKind::Bool),
+    ("use-wayland", ConfigKind::Bool),
+    ("wayland-socket", ConfigKind::String),
+    ("use-kvm", ConfigKind::Bool),
+    ("use-gpu", ConfigKind::Bool),
+    ("use-gpu-card0", ConfigKind::Bool),
+    ("use-network", ConfigKind::Bool),
+    ("network-zone", ConfigKind::String),
+    ("reserved-ip", ConfigKind::Integer),
+    ("system-realm", ConfigKind::Bool),
+    ("autostart", ConfigKind::Bool),
+    ("extra-bindmounts", ConfigKind::StringList),
+    ("extra-bindmounts-ro", ConfigKind::StringList),
+    ("realm-depends", ConfigKind::StringList),
+    ("restart-policy", ConfigKind::String),
+    ("health-check", ConfigKind::String),
+    ("health-check-interval", ConfigKind::Integer),
+    ("realmfs", ConfigKind::String),
+    ("terminal-scheme", ConfigKind::String),
+    ("overlay", ConfigKind::String),
+    ("netns", ConfigKind::String),
+    ("memory-max", ConfigKind::String),
+    ("memory-high", ConfigKind::String),
+    ("cpu-weight", ConfigKind::Integer),
+    ("cpu-quota", ConfigKind::String),
+    ("io-weight", ConfigKind::Integer),
+    ("tasks-max", ConfigKind::Integer),
+    ("firewall.default-outbound", ConfigKind::String),
+    ("firewall.allow-outbound", ConfigKind::StringList),
+    ("firewall.isolate-lan", ConfigKind::Bool),
+    ("firewall.allow-realms", ConfigKind::StringList),
+];
+
+/// Return the type of configuration variable `name` or `None` if no such variable exists.
+pub fn config_kind(name: &str) -> Option<ConfigKind> {
+    CONFIG_VARIABLES.iter()
+        .find(|(n, _)| *n == name)
+        .map(|(_, kind)| *kind)
+}
+
+fn strings(v: Vec<&str>) -> ConfigValue {
+    ConfigValue::StringList(v.into_iter().map(String::from).collect())
+}
+
+fn opt_string(s: Option<&str>) -> ConfigValue {
+    ConfigValue::String(s.unwrap_or("").to_string())
+}
+
+// A bind mount item has the form SOURCE[:DEST[:OPTIONS]] with absolute paths
+fn is_valid_bind_item(item: &str) -> bool {
+    let mut parts = item.split(':');
+    let source = parts.next().unwrap_or("");
+    let dest = parts.next();
+    let dest_valid = match dest {
+        Some(dest) => dest.starts_with('/'),
+        None => true,
+    };
+    !item.contains('\n') && source.starts_with('/') && dest_valid
+}
+
+fn is_valid_relative_path(path: &str) -> bool {
+    !path.is_empty() && !path.starts_with('/') && !path.contains('\n') &&
+        path.split('/').all(|c| c != "..")
+}
+
+impl RealmConfig {
+
+    /// Return the effective value of configuration variable `name` including any value
+    /// inherited from the global configuration.
+    pub fn get_value(&self, name: &str) -> Option<ConfigValue> {
+        let value = match name {
+            "use-shared-dir" => ConfigValue::Bool(self.shared_dir()),
+            "use-media-dir" => ConfigValue::Bool(self.media_dir()),
+            "use-ephemeral-home" => ConfigValue::Bool(self.ephemeral_home()),
+            "ephemeral-persistent-dirs" => ConfigValue::StringList(self.ephemeral_persistent_dirs()),
+            "use-sound" => ConfigValue::Bool(self.sound()),
+            "use-x11" => ConfigValue::Bool(self.x11()),
+            "use-wayland" => ConfigValue::Bool(self.wayland()),
+            "wayland-socket" => ConfigValue::String(self.wayland_socket().to_string()),
+            "use-kvm" => ConfigValue::Bool(self.kvm()),
+            "use-gpu" => ConfigValue::Bool(self.gpu()),
+            "use-gpu-card0" => ConfigValue::Bool(self.gpu_card0()),
+            "use-network" => ConfigValue::Bool(self.network()),
+            "network-zone" => ConfigValue::String(self.network_zone().to_string()),
+            "reserved-ip" => ConfigValue::Integer(self.reserved_ip().map_or(0, i64::from)),
+            "system-realm" => ConfigValue::Bool(self.system_realm()),
+            "autostart" => ConfigValue::Bool(self.autostart()),
+            "extra-bindmounts" => strings(self.extra_bindmounts()),
+            "extra-bindmounts-ro" => strings(self.extra_bindmounts_ro()),
+            "realm-depends" => strings(self.realm_depends()),
+            "restart-policy" => ConfigValue::String(self.restart_policy().as_str().to_string()),
+            "health-check" => opt_string(self.health_check()),
+            "health-check-interval" => ConfigValue::Integer(i64::from(self.health_check_interval())),
+            "realmfs" => ConfigValue::String(self.realmfs().to_string()),
+            "terminal-scheme" => opt_string(self.terminal_scheme()),
+            "overlay" => opt_string(self.overlay().to_str_value().or(Some("none"))),
+            "netns" => opt_string(self.netns()),
+            "memory-max" => opt_string(self.memory_max()),
+            "memory-high" => opt_string(self.memory_high()),
+            "cpu-weight" => ConfigValue::Integer(self.cpu_weight().map_or(0, i64::from)),
+            "cpu-quota" => opt_string(self.cpu_quota()),
+            "io-weight" => ConfigValue::Integer(self.io_weight().map_or(0, i64::from)),
+            "tasks-max" => ConfigValue::Integer(self.tasks_max().map_or(0, i64::from)),
+            "firewall.default-outbound" => {
+                let allowed = match self.firewall() {
+                    Some(policy) => policy.default_outbound_allowed(),
+                    None => true,
+                };
+                ConfigValue::String(if allowed { "allow" } else { "deny" }.to_string())
+            },
+            "firewall.allow-outbound" => strings(self.firewall().map(|p| p.allow_outbound()).unwrap_or_default()),
+            "firewall.isolate-lan" => ConfigValue::Bool(self.firewall().is_some_and(|p| p.isolate_lan())),
+            "firewall.allow-realms" => strings(self.firewall().and_then(|p| p.allow_realms()).unwrap_or_default()),
+            _ => return None,
+        };
+        Some(value)
+    }
+
+    /// Validate `value` and store it as the value of configuration variable `name`.
+    ///
+    /// Returns `true` if the effective value of the variable changed. The configuration
+    /// is not written to disk.
+    pub fn set_value(&mut self, name: &str, value: ConfigValue) -> std::result::Result<bool, ConfigError> {
+        let kind = match config_kind(name) {
+            Some(kind) => kind,
+            None => return Err(ConfigError::new(name, "unknown configuration variable")),
+        };
+        if value.kind() != kind {
+            return Err(ConfigError::new(name, format!("expected a {} value but got a {} value", kind.name(), value.kind().name())));
+        }
+        validate_value(name, &value)?;
+
+        if self.get_value(name).as_ref() == Some(&value) {
+            return Ok(false);
+        }
+
+        match value {
+            ConfigValue::Bool(b) => self.set_bool(name, b),
+            ConfigValue::Integer(n) => self.set_integer(name, n),
+            ConfigValue::String(s) => self.set_string(name, s),
+            ConfigValue::StringList(v) => self.set_string_list(name, v),
+        }
+        Ok(true)
+    }
+
+    fn set_bool(&mut self, name: &str, b: bool) {
+        let b = Some(b);
+        match name {
+            "use-shared-dir" => self.use_shared_dir = b,
+            "use-media-dir" => self.use_media_dir = b,
+            "use-ephemeral-home" => self.use_ephemeral_home = b,
+            "use-sound" => self.use_sound = b,
+            "use-x11" => self.use_x11 = b,
+            "use-wayland" => self.use_wayland = b,
+            "use-kvm" => self.use_kvm = b,
+            "use-gpu" => self.use_gpu = b,
+            "use-gpu-card0" => self.use_gpu_card0 = b,
+            "use-network" => self.use_network = b,
+            "system-realm" => self.system_realm = b,
+            "autostart" => self.autostart = b,
+            "firewall.isolate-lan" => self.firewall_policy_mut().isolate_lan = b,
+            _ => {},
+        }
+    }
+
+    fn set_integer(&mut self, name: &str, n: i64) {
+        // zero removes the value
+        let value = if n == 0 { None } else { Some(n as u32) };
+        match name {
+            "reserved-ip" => self.reserved_ip = value,
+            "cpu-weight" => self.cpu_weight = value,
+            "io-weight" => self.io_weight = value,
+            "tasks-max" => self.tasks_max = value,
+            "health-check-interval" => self.health_check_interval = value,
+            _ => {},
+        }
+    }
+
+    fn set_string(&mut self, name: &str, s: String) {
+        let value = if s.is_empty() { None } else { Some(s) };
+        match name {
+            "wayland-socket" => self.wayland_socket = value,
+            "network-zone" => self.network_zone = value,
+            "realmfs" => self.realmfs = value,
+            "terminal-scheme" => self.terminal_scheme = value,
+            "overlay" => self.overlay = value,
+            "netns" => self.netns = value,
+            "memory-max" => self.memory_max = value,
+            "memory-high" => self.memory_high = value,
+            "cpu-quota" => self.cpu_quota = value,
+            "restart-policy" => self.restart_policy = value,
+            "health-check" => self.health_check = value,
+            "firewall.default-outbound" => self.firewall_policy_mut().default_outbound = value,
+            _ => {},
+        }
+    }
+
+    fn set_string_list(&mut self, name: &str, v: Vec<String>) {
+        let v = Some(v);
+        match name {
+            "ephemeral-persistent-dirs" => self.ephemeral_persistent_dirs = v,
+            "extra-bindmounts" => self.extra_bindmounts = v,
+            "extra-bindmounts-ro" => self.extra_bindmounts_ro = v,
+            "realm-depends" => self.realm_depends = v,
+            "firewall.allow-outbound" => self.firewall_policy_mut().allow_outbound = v,
+            "firewall.allow-realms" => self.firewall_policy_mut().allow_realms = v,
+            _ => {},
+        }
+    }
+
+    // A realm policy replaces an inherited policy, so start from a copy of it.
+    fn firewall_policy_mut(&mut self) -> &mut FirewallPolicy {
+        if self.firewall.is_none() {
+            let inherited = self.parent.as_ref()
+                .and_then(|p| p.firewall())
+                .cloned()
+                .unwrap_or_default();
+            self.firewall = Some(inherited);
+        }
+        self.firewall.get_or_insert_with(FirewallPolicy::default)
+    }
+}
+
+fn validate_value(name: &str, value: &ConfigValue) -> std::result::Result<(), ConfigError> {
+    let message = match (name, value) {
+        ("reserved-ip", ConfigValue::Integer(n)) if *n != 0 && !(RESERVED_IP_START..=254).contains(n) => {
+            format!("reserved address octet must be between {} and 254", RESERVED_IP_START)
+        },
+        ("network-zone", ConfigValue::String(zone)) if !NetworkZone::is_valid_name(zone) => {
+            format!("'{}' is not a valid network zone name", zone)
+        },
+        ("network-zone", ConfigValue::String(zone)) if !GLOBAL_CONFIG.network_zones().contains_key(zone) => {
+            format!("network zone '{}' is not defined", zone)
+        },
+        ("overlay", ConfigValue::String(overlay)) if overlay != "none" && OverlayType::from_str_value(overlay) == OverlayType::None => {
+            format!("'{}' is not a valid overlay type", overlay)
+        },
+        ("realmfs", ConfigValue::String(realmfs)) if !RealmFS::is_valid_name(realmfs) => {
+            format!("'{}' is not a valid RealmFS name", realmfs)
+        },
+        ("wayland-socket", ConfigValue::String(s)) | ("netns", ConfigValue::String(s)) if s.contains('/') || s.contains('\n') => {
+            format!("'{}' is not a valid name", s)
+        },
+        ("memory-max", ConfigValue::String(s)) | ("memory-high", ConfigValue::String(s)) if !s.is_empty() && !ResourceLimits::is_valid_memory_size(s) => {
+            format!("'{}' is not a valid memory size", s)
+        },
+        ("cpu-quota", ConfigValue::String(s)) if !s.is_empty() && !ResourceLimits::is_valid_cpu_quota(s) => {
+            format!("'{}' is not a valid CPU quota percentage", s)
+        },
+        ("cpu-weight", ConfigValue::Integer(n)) | ("io-weight", ConfigValue::Integer(n)) if *n != 0 && !(1..=10000).contains(n) => {
+            "weight must be between 1 and 10000".to_string()
+        },
+        ("tasks-max", ConfigValue::Integer(n)) if !(0..=i64::from(u32::MAX)).contains(n) => {
+            "task limit must be a positive number".to_string()
+        },
+        ("restart-policy", ConfigValue::String(s)) if RestartPolicy::from_name(s).is_none() => {
+            "value must be one of 'never', 'on-failure' or 'always'".to_string()
+        },
+        ("health-check", ConfigValue::String(s)) if s.contains('\n') => {
+            "health check must be a single command line".to_string()
+        },
+        ("health-check-interval", ConfigValue::Integer(n)) if *n != 0 && !(5..=86400).contains(n) => {
+            "interval must be between 5 and 86400 seconds".to_string()
+        },
+        ("firewall.default-outbound", ConfigValue::String(s)) if s != "allow" && s != "deny" => {
+            "value must be either 'allow' or 'deny'".to_string()
+        },
+        ("ephemeral-persistent-dirs", ConfigValue::StringList(v)) if !v.iter().all(|d| is_valid_relative_path(d)) => {
+            "paths must be relative paths inside the home directory".to_string()
+        },
+        ("extra-bindmounts", ConfigValue::StringList(v)) | ("extra-bindmounts-ro", ConfigValue::StringList(v)) if !v.iter().all(|b| is_valid_bind_item(b)) => {
+            "bind mounts must have the form SOURCE[:DEST[:OPTIONS]] with absolute paths".to_string()
+        },
+        ("realm-depends", ConfigValue::StringList(v)) | ("firewall.allow-realms", ConfigValue::StringList(v)) if !v.iter().all(|r| Realm::is_valid_name(r)) => {
+            "list contains an invalid realm name".to_string()
+        },
+        _ => return Ok(()),
+    };
+    Err(ConfigError::new(name, message))
+}
+
+#[test]
+fn test_set_config_value() {
+    let mut config = RealmConfig::empty();
+    config.parent = Some(Box::new(RealmConfig::default()));
+
+    assert!(config.set_value("use-kvm", ConfigValue::Bool(true)).unwrap());
+    assert!(!config.set_value("use-kvm", ConfigValue::Bool(true)).unwrap());
+    assert_eq!(config.get_value("use-kvm"), Some(ConfigValue::Bool(true)));
+
+    assert!(config.set_value("use-kvm", ConfigValue::String("yes".into())).is_err());
+    assert!(config.set_value("no-such-variable", ConfigValue::Bool(true)).is_err());
+    assert!(config.set_value("reserved-ip", ConfigValue::Integer(12)).is_err());
+    assert!(config.set_value("extra-bindmounts", ConfigValue::StringList(vec!["relative:/x".into()])).is_err());
+    assert!(config.set_value("ephemeral-persistent-dirs", ConfigValue::StringList(vec!["../etc".into()])).is_err());
+
+    config.set_value("reserved-ip", ConfigValue::Integer(210)).unwrap();
+    assert_eq!(config.reserved_ip(), Some(210));
+
+    assert!(config.set_value("memory-max", ConfigValue::String("lots".into())).is_err());
+    assert!(config.set_value("cpu-weight", ConfigValue::Integer(20000)).is_err());
+    config.set_value("memory-max", ConfigValue::String("2G".into())).unwrap();
+    assert_eq!(config.memory_max(), Some("2G"));
+
+    config.set_value("firewall.isolate-lan", ConfigValue::Bool(true)).unwrap();
+    assert!(config.firewall().unwrap().isolate_lan());
+    assert!(config.firewall().unwrap().default_outbound_allowed());
+
+    let parsed = ConfigValue::parse(ConfigKind::StringList, "a, b,,c").unwrap();
+    assert_eq!(parsed, ConfigValue::StringList(vec!["a".into(), "b".into(), "c".into()]));
+}
@@ -1,5 +1,7 @@
 use std::path::{PathBuf, Path};
 use crate::{Realms, Result, util};
+use crate::realm::archive::ARCHIVE_FILES;
+use crate::realm::snapshot;
 use std::fs;
 
 /// Creation and removal of a Realm
@@ -47,6 +49,62 @@ impl RealmCreateDestroy {
         Ok(())
     }
 
+    /// Create a new realm with the name `self.name` from the files in the tar
+    /// archive `payload` of an exported realm.
+    pub fn import(&self, payload: &Path) -> Result<()> {
+        if self.basepath().exists() {
+            bail!("realm directory {} already exists", self.basepath().display());
+        }
+
+        if let Err(e) = self.import_realm_directory(payload) {
+            let tmpdir = self.temp_basepath();
+            if tmpdir.exists() {
+                let _ = fs::remove_dir_all(tmpdir);
+            }
+            return Err(e);
+        }
+        Ok(())
+    }
+
+    fn import_realm_directory(&self, payload: &Path) -> Result<()> {
+        let base = self.temp_basepath();
+        if base.exists() {
+            fs::remove_dir_all(&base)
+                .map_err(context!("error removing stale temporary directory {:?}", base))?;
+        }
+        util::create_dir(&base)?;
+        cmd!("/usr/bin/tar", "-C {} --numeric-owner -xpf {}", base.display(), payload.display())
+            .map_err(context!("failed to extract realm files from {:?}", payload))?;
+
+        // Only keep the expected files and directories
+        util::read_directory(&base, |dent| {
+            let name = dent.file_name();
+            if !ARCHIVE_FILES.iter().any(|&f| name == f) {
+                warn!("Ignoring unexpected file {:?} in realm archive", name);
+                let path = dent.path();
+                if path.is_dir() {
+                    fs::remove_dir_all(&path)
+                        .map_err(context!("error removing directory {:?}", path))?;
+                } else {
+                    util::remove_file(&path)?;
+                }
+            }
+            Ok(())
+        })?;
+
+        let home = base.join("home");
+        if home.exists() {
+            util::chown_tree(&home, (1000, 1000), true)?;
+        } else {
+            self.create_home()?;
+        }
+        let skel = base.join("skel");
+        if skel.exists() {
+            util::chown_tree(&skel, (1000, 1000), true)?;
+        }
+        self.move_from_temp()
+    }
+
     fn create_realm_directory(&self) -> Result<()> {
         self.create_home()?;
         self.move_from_temp()
@@ -98,7 +156,14 @@ impl RealmCreateDestroy {
 
         let realmdir = self.temp_basepath();
         info!("removing realm directory {:?}", realmdir);
-        fs::remove_dir_all(&realmdir)
+        Self::remove_realm_directory(&realmdir)
+    }
+
+    // Snapshots may contain read-only btrfs subvolumes which must be deleted
+    // before the rest of the directory can be removed.
+    fn remove_realm_directory(realmdir: &Path) -> Result<()> {
+        snapshot::remove_all(realmdir)?;
+        fs::remove_dir_all(realmdir)
             .map_err(context!("error removing realm directory {:?}", realmdir))
     }
 
@@ -123,4 +188,21 @@ impl RealmCreateDestroy {
         save_dir
     }
 
-}
\ No newline at end of file
+}
+
+#[test]
+fn test_remove_realm_with_snapshots() {
+    let realmdir = std::env::temp_dir().join(format!("realm-test-{}", std::process::id()));
+    let snapshots = realmdir.join("snapshots");
+    fs::create_dir_all(realmdir.join("home/.config")).unwrap();
+    fs::create_dir_all(snapshots.join("first/home/.config")).unwrap();
+    fs::create_dir_all(snapshots.join("second/overlay/usr")).unwrap();
+    fs::write(snapshots.join("second/home.tar"), b"").unwrap();
+
+    snapshot::remove_all(&realmdir).unwrap();
+    assert_eq!(fs::read_dir(&snapshots).unwrap().count(), 0);
+    fs::create_dir_all(snapshots.join("third/home")).unwrap();
+
+    RealmCreateDestroy::remove_realm_directory(&realmdir).unwrap();
+    assert!(!realmdir.exists());
+}
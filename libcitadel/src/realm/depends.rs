@@ -0,0 +1,198 @@
+use std::collections::{BTreeMap, BTreeSet};
+
+use crate::Realm;
+
+/// What stopping a realm does with running realms which depend on it.
+#[derive(Clone,Copy,Debug,PartialEq,Eq)]
+pub enum StopDependents {
+    /// Fail without stopping anything.
+    Refuse,
+    /// Stop the dependent realms first.
+    Cascade,
+    /// Stop only the realm, as when it is about to be started again or every realm
+    /// is being stopped in order.
+    Ignore,
+}
+
+/// The graph of `realm-depends` relationships between realms.
+///
+/// Dependencies on realms which do not exist are ignored.
+pub struct RealmDependencies {
+    // realm name -> names of the realms it depends on
+    depends: BTreeMap<String, BTreeSet<String>>,
+}
+
+impl RealmDependencies {
+    pub fn new(realms: &[Realm]) -> Self {
+        let pairs = realms.iter()
+            .map(|r| {
+                let config = r.config();
+                let depends = config.realm_depends().iter().map(|s| s.to_string()).collect();
+                (r.name().to_string(), depends)
+            })
+            .collect::<Vec<_>>();
+        Self::from_pairs(pairs)
+    }
+
+    fn from_pairs(pairs: Vec<(String, Vec<String>)>) -> Self {
+        let names = pairs.iter().map(|(name,_)| name.clone()).collect::<BTreeSet<_>>();
+        let depends = pairs.into_iter()
+            .map(|(name, deps)| {
+                let deps = deps.into_iter()
+                    .filter(|d| names.contains(d) && *d != name)
+                    .collect();
+                (name, deps)
+            })
+            .collect();
+        RealmDependencies { depends }
+    }
+
+    /// Names of the realms which `name` depends on directly.
+    pub fn depends_on(&self, name: &str) -> Vec<&str> {
+        self.depends.get(name)
+            .map(|deps| deps.iter().map(|s| s.as_str()).collect())
+            .unwrap_or_default()
+    }
+
+    /// Names of the realms which depend on `name` directly.
+    pub fn dependents(&self, name: &str) -> Vec<&str> {
+        self.depends.iter()
+            .filter(|(_, deps)| deps.contains(name))
+            .map(|(realm, _)| realm.as_str())
+            .collect()
+    }
+
+    /// Names of all realms which depend on `name` directly or indirectly, ordered
+    /// so that every realm comes before the realms it depends on. This is the order
+    /// in which they can be stopped before `name` is stopped.
+    pub fn all_dependents(&self, name: &str) -> Vec<&str> {
+        let mut found = BTreeSet::new();
+        let mut pending = vec![name];
+        while let Some(n) = pending.pop() {
+            for dependent in self.dependents(n) {
+                if dependent != name && found.insert(dependent) {
+                    pending.push(dependent);
+                }
+            }
+        }
+        self.stop_order()
+            .into_iter()
+            .filter(|n| found.contains(n))
+            .collect()
+    }
+
+    /// Names of all realms ordered so that every realm comes before the realms it
+    /// depends on. Realms which are part of a dependency cycle are placed after all
+    /// other realms which depend on them.
+    pub fn stop_order(&self) -> Vec<&str> {
+        // number of not yet ordered realms which depend on each realm
+        let mut remaining = self.depends.keys()
+            .map(|name| (name.as_str(), self.dependents(name).len()))
+            .collect::<BTreeMap<_,_>>();
+        let mut order = Vec::new();
+
+        while !remaining.is_empty() {
+            let ready = remaining.iter()
+                .filter(|(_, count)| **count == 0)
+                .map(|(name, _)| *name)
+                .collect::<Vec<_>>();
+            // break a cycle by taking the realm with the fewest remaining dependents
+            let ready = if ready.is_empty() {
+                remaining.iter()
+                    .min_by_key(|(_, count)| **count)
+                    .map(|(name, _)| vec![*name])
+                    .unwrap_or_default()
+            } else {
+                ready
+            };
+            for name in ready {
+                remaining.remove(name);
+                for dep in self.depends_on(name) {
+                    if let Some(count) = remaining.get_mut(dep) {
+                        *count = count.saturating_sub(1);
+                    }
+                }
+                order.push(name);
+            }
+        }
+        order
+    }
+
+    /// Find a cycle in the dependency graph and return the names of the realms which
+    /// form it, with the first realm repeated at the end.
+    pub fn find_cycle(&self) -> Option<Vec<String>> {
+        let mut done = BTreeSet::new();
+        for name in self.depends.keys() {
+            let mut path = Vec::new();
+            if let Some(cycle) = self.visit(name, &mut path, &mut done) {
+                return Some(cycle);
+            }
+        }
+        None
+    }
+
+    fn visit<'a>(&'a self, name: &'a str, path: &mut Vec<&'a str>, done: &mut BTreeSet<&'a str>) -> Option<Vec<String>> {
+        if let Some(idx) = path.iter().position(|n| *n == name) {
+            let mut cycle = path[idx..].iter().map(|s| s.to_string()).collect::<Vec<_>>();
+            cycle.push(name.to_string());
+            return Some(cycle);
+        }
+        if done.contains(name) {
+            return None;
+        }
+        path.push(name);
+        for dep in self.depends_on(name) {
+            if let Some(cycle) = self.visit(dep, path, done) {
+                return Some(cycle);
+            }
+        }
+        path.pop();
+        done.insert(name);
+        None
+    }
+
+    /// Return the cycle which would be created if realm `name` depended on the realms
+    /// named in `depends` instead of its current dependencies.
+    pub fn cycle_with(&self, name: &str, depends: &[String]) -> Option<Vec<String>> {
+        let pairs = self.depends.iter()
+            .map(|(realm, deps)| {
+                let deps = if realm == name {
+                    depends.to_vec()
+                } else {
+                    deps.iter().cloned().collect()
+                };
+                (realm.clone(), deps)
+            })
+            .collect();
+        Self::from_pairs(pairs).find_cycle()
+    }
+}
+
+#[test]
+fn test_realm_dependencies() {
+    let pair = |name: &str, deps: &[&str]| (name.to_string(), deps.iter().map(|s| s.to_string()).collect());
+    let graph = RealmDependencies::from_pairs(vec![
+        pair("apt-cacher", &[]),
+        pair("dev", &["apt-cacher", "missing"]),
+        pair("build", &["dev"]),
+        pair("web", &[]),
+    ]);
+
+    assert_eq!(graph.depends_on("dev"), vec!["apt-cacher"]);
+    assert_eq!(graph.dependents("apt-cacher"), vec!["dev"]);
+    assert_eq!(graph.all_dependents("apt-cacher"), vec!["build", "dev"]);
+    assert!(graph.find_cycle().is_none());
+
+    let order = graph.stop_order();
+    let pos = |n| order.iter().position(|o| *o == n).unwrap();
+    assert!(pos("build") < pos("dev") && pos("dev") < pos("apt-cacher"));
+    assert_eq!(order.len(), 4);
+
+    let cycle = graph.cycle_with("apt-cacher", &["build".to_string()]).unwrap();
+    assert_eq!(cycle.first(), cycle.last());
+    assert_eq!(cycle.len(), 4);
+
+    let cyclic = RealmDependencies::from_pairs(vec![pair("a", &["b"]), pair("b", &["a"])]);
+    assert!(cyclic.find_cycle().is_some());
+    assert_eq!(cyclic.stop_order().len(), 2);
+}
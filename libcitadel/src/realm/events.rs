@@ -6,7 +6,7 @@ use std::sync::atomic::{AtomicBool, Ordering};
 use std::thread::{self,JoinHandle};
 use std::path;
 
-use crate::{RealmManager, Result, Realm, util};
+use crate::{RealmManager, Result, Realm, RealmFS, util};
 use super::realms::HasCurrentChanged;
 use dbus::{Connection, BusType, ConnectionItem, Message, Path};
 use inotify::{Inotify, WatchMask, WatchDescriptor, Event};
@@ -17,6 +17,19 @@ pub enum RealmEvent {
     New(Realm),
     Removed(Realm),
     Current(Option<Realm>),
+    /// The config file of the realm was changed on disk and has been reloaded.
+    ConfigChanged(Realm),
+    /// Starting the realm failed with the error text.
+    StartFailed(Realm, String),
+    /// A new RealmFS image was created by forking an existing image.
+    RealmFSNew(RealmFS),
+    RealmFSRemoved(RealmFS),
+    RealmFSActivated(RealmFS),
+    RealmFSDeactivated(RealmFS),
+    RealmFSResized(RealmFS),
+    /// Changes from an update shell were applied to the RealmFS image or it was rolled
+    /// back to a previous generation.
+    RealmFSUpdated(RealmFS),
 }
 
 impl Display for RealmEvent {
@@ -28,6 +41,14 @@ impl Display for RealmEvent {
             RealmEvent::Removed(ref realm)   => write!(f, "RealmRemoved({})", realm.name()),
             RealmEvent::Current(Some(realm)) => write!(f, "RealmCurrent({})", realm.name()),
             RealmEvent::Current(None)        => write!(f, "RealmCurrent(None)"),
+            RealmEvent::ConfigChanged(ref realm) => write!(f, "RealmConfigChanged({})", realm.name()),
+            RealmEvent::StartFailed(ref realm, ref err) => write!(f, "RealmStartFailed({}, {})", realm.name(), err),
+            RealmEvent::RealmFSNew(ref realmfs) => write!(f, "RealmFSNew({})", realmfs.name()),
+            RealmEvent::RealmFSRemoved(ref realmfs) => write!(f, "RealmFSRemoved({})", realmfs.name()),
+            RealmEvent::RealmFSActivated(ref realmfs) => write!(f, "RealmFSActivated({})", realmfs.name()),
+            RealmEvent::RealmFSDeactivated(ref realmfs) => write!(f, "RealmFSDeactivated({})", realmfs.name()),
+            RealmEvent::RealmFSResized(ref realmfs) => write!(f, "RealmFSResized({})", realmfs.name()),
+            RealmEvent::RealmFSUpdated(ref realmfs) => write!(f, "RealmFSUpdated({})", realmfs.name()),
         }
     }
 }
@@ -0,0 +1,383 @@
+use std::net::{IpAddr, ToSocketAddrs};
+use std::path::Path;
+
+use crate::{Exec, FirewallPolicy, Realm, Result, util};
+
+const NFT: &str = "/usr/sbin/nft";
+
+const RESOLV_CONF: &str = "/storage/citadel-state/resolv.conf";
+
+// Name of file in realm run directory the ruleset is written to before it is loaded
+const RULESET_FILE: &str = "firewall.nft";
+
+// Private address ranges dropped when `isolate-lan` is set
+const LAN_NETWORKS: &[&str] = &["10.0.0.0/8", "172.16.0.0/12", "192.168.0.0/16", "169.254.0.0/16"];
+const LAN_NETWORKS6: &[&str] = &["fc00::/7", "fe80::/10"];
+
+// nft match keyword for the address family of `addr`
+fn family(addr: &IpAddr) -> &'static str {
+    if addr.is_ipv4() { "ip" } else { "ip6" }
+}
+
+// A single parsed `allow-outbound` entry
+struct OutboundRule {
+    addresses: Vec<String>,
+    addresses6: Vec<String>,
+    ports: Option<String>,
+    protocols: Vec<&'static str>,
+}
+
+impl OutboundRule {
+
+    fn parse(entry: &str) -> Result<Self> {
+        let entry = entry.trim();
+        // IPv6 addresses contain ':' so they must be enclosed in brackets
+        let (host, rest) = if let Some(bracketed) = entry.strip_prefix('[') {
+            match bracketed.find(']') {
+                Some(idx) => (&bracketed[..idx], &bracketed[idx + 1..]),
+                None => bail!("invalid firewall rule '{}'", entry),
+            }
+        } else {
+            match entry.find(':') {
+                Some(idx) => (&entry[..idx], &entry[idx..]),
+                None => (entry, ""),
+            }
+        };
+        let rest = match rest {
+            "" => None,
+            rest => match rest.strip_prefix(':') {
+                Some(rest) => Some(rest),
+                None => bail!("invalid firewall rule '{}'", entry),
+            },
+        };
+        let mut parts = rest.into_iter().flat_map(|rest| rest.split(':'));
+        let ports = parts.next().map(Self::parse_ports).transpose()?;
+        let protocols = match parts.next() {
+            Some("tcp") => vec!["tcp"],
+            Some("udp") => vec!["udp"],
+            Some(proto) => bail!("invalid protocol '{}' in firewall rule '{}'", proto, entry),
+            None if ports.is_some() => vec!["tcp", "udp"],
+            None => Vec::new(),
+        };
+        if parts.next().is_some() {
+            bail!("invalid firewall rule '{}'", entry);
+        }
+        let (addresses6, addresses) = Self::resolve_host(host)
+            .map_err(context!("failed to resolve host in firewall rule '{}'", entry))?
+            .into_iter()
+            .partition(|addr| addr.contains(':'));
+        Ok(OutboundRule { addresses, addresses6, ports, protocols })
+    }
+
+    fn parse_ports(ports: &str) -> Result<String> {
+        let valid = |p: &str| p.parse::<u16>().is_ok();
+        let ports = ports.split(',').map(|p| p.trim()).collect::<Vec<_>>();
+        for port in &ports {
+            let ok = match port.find('-') {
+                Some(idx) => valid(&port[..idx]) && valid(&port[idx + 1..]),
+                None => valid(port),
+            };
+            if !ok {
+                bail!("invalid port '{}' in firewall rule", port);
+            }
+        }
+        Ok(nft_set(&ports))
+    }
+
+    // Address or network literals are used directly, anything else is looked up as a hostname
+    fn resolve_host(host: &str) -> Result<Vec<String>> {
+        if host.is_empty() {
+            bail!("no host specified");
+        }
+        let literal = match host.find('/') {
+            Some(idx) => match host[..idx].parse::<IpAddr>() {
+                Ok(addr) => {
+                    let max = if addr.is_ipv4() { 32 } else { 128 };
+                    host[idx + 1..].parse::<u8>().is_ok_and(|n| n <= max)
+                },
+                Err(_) => false,
+            },
+            None => host.parse::<IpAddr>().is_ok(),
+        };
+        if literal {
+            return Ok(vec![host.to_string()]);
+        }
+
+        let mut addresses = (host, 0).to_socket_addrs()
+            .map_err(context!("failed to look up address of {}", host))?
+            .map(|addr| addr.ip().to_string())
+            .collect::<Vec<_>>();
+        addresses.sort();
+        addresses.dedup();
+        if addresses.is_empty() {
+            bail!("no address found for {}", host);
+        }
+        Ok(addresses)
+    }
+
+    fn write_rules(&self, out: &mut Vec<String>) {
+        for (family, addresses) in &[("ip", &self.addresses), ("ip6", &self.addresses6)] {
+            if !addresses.is_empty() {
+                self.write_family_rules(out, family, &nft_set(addresses));
+            }
+        }
+    }
+
+    fn write_family_rules(&self, out: &mut Vec<String>, family: &str, daddr: &str) {
+        if self.protocols.is_empty() {
+            out.push(format!("        {} daddr {} accept", family, daddr));
+        }
+        for proto in &self.protocols {
+            match self.ports {
+                Some(ref ports) => out.push(format!("        {} daddr {} {} dport {} accept", family, daddr, proto, ports)),
+                None => out.push(format!("        {} daddr {} meta l4proto {} accept", family, daddr, proto)),
+            }
+        }
+    }
+}
+
+// Format a list of values as a single nft value or an anonymous set
+fn nft_set<S: AsRef<str>>(values: &[S]) -> String {
+    if values.len() == 1 {
+        values[0].as_ref().to_string()
+    } else {
+        let values = values.iter().map(|v| v.as_ref()).collect::<Vec<_>>();
+        format!("{{ {} }}", values.join(", "))
+    }
+}
+
+///
+/// Addresses of a realm and its network zone for one address family.
+///
+pub struct ZoneAddresses {
+    /// Address allocated to the realm
+    pub address: IpAddr,
+    /// Zone network in CIDR notation
+    pub network: String,
+    /// Gateway address of the zone
+    pub gateway: IpAddr,
+    /// Addresses of running realms named in the `allow-realms` list of the policy
+    pub allowed_realms: Vec<IpAddr>,
+}
+
+impl ZoneAddresses {
+    fn family(&self) -> &'static str {
+        family(&self.address)
+    }
+}
+
+///
+/// nftables rules generated from the firewall policy of a realm.
+///
+/// Two tables are generated for each realm. Traffic routed out of the realm zone is
+/// filtered in table `inet realm-$NAME` and traffic to other realms on the zone
+/// bridge is filtered in table `bridge realm-$NAME`. Both tables only match packets
+/// with an address allocated to the realm as the source address, and the rules are
+/// generated for both IPv4 and IPv6 when the zone has an IPv6 network.
+///
+pub struct RealmFirewall {
+    realm: String,
+    policy: FirewallPolicy,
+    zones: Vec<ZoneAddresses>,
+    nameservers: Vec<IpAddr>,
+}
+
+impl RealmFirewall {
+
+    /// Create firewall rules for a realm with name `realm` from the addresses the realm
+    /// has been allocated on its zone for each enabled address family.
+    pub fn new(realm: &str, policy: &FirewallPolicy, zones: Vec<ZoneAddresses>) -> Self {
+        RealmFirewall {
+            realm: realm.to_string(),
+            policy: policy.clone(),
+            zones,
+            nameservers: Self::read_nameservers(),
+        }
+    }
+
+    // Queries to the configured nameservers are always permitted, otherwise a deny
+    // policy or LAN isolation would prevent the realm from resolving any hostnames.
+    fn read_nameservers() -> Vec<IpAddr> {
+        if !Path::new(RESOLV_CONF).exists() {
+            return Vec::new();
+        }
+        let content = match util::read_to_string(RESOLV_CONF) {
+            Ok(content) => content,
+            Err(err) => {
+                warn!("{}", err);
+                return Vec::new();
+            }
+        };
+        content.lines()
+            .filter_map(|line| line.trim().strip_prefix("nameserver"))
+            .filter_map(|addr| addr.trim().parse().ok())
+            .collect()
+    }
+
+    fn table_name(&self) -> String {
+        format!("realm-{}", self.realm)
+    }
+
+    /// Generate the complete nftables ruleset for this realm.
+    ///
+    /// The ruleset replaces any tables previously installed for the realm when it
+    /// is loaded with `nft -f`.
+    pub fn ruleset(&self) -> Result<String> {
+        let mut out = Vec::new();
+        self.write_inet_table(&mut out)?;
+        if self.policy.allow_realms().is_some() {
+            self.write_bridge_table(&mut out);
+        }
+        out.push(String::new());
+        Ok(out.join("\n"))
+    }
+
+    // Declaring the table before deleting it makes the delete succeed when the table
+    // does not exist yet, so the ruleset can always be loaded as a single transaction.
+    fn write_table_header(&self, out: &mut Vec<String>, family: &str) {
+        let name = self.table_name();
+        out.push(format!("table {} {}", family, name));
+        out.push(format!("delete table {} {}", family, name));
+        out.push(format!("table {} {} {{", family, name));
+    }
+
+    fn write_inet_table(&self, out: &mut Vec<String>) -> Result<()> {
+        let rules = self.policy.allow_outbound().into_iter()
+            .map(OutboundRule::parse)
+            .collect::<Result<Vec<_>>>()?;
+
+        self.write_table_header(out, "inet");
+        out.push("    chain forward {".to_string());
+        out.push("        type filter hook forward priority filter; policy accept;".to_string());
+        for zone in &self.zones {
+            out.push(format!("        {} saddr {} ct state established,related accept", zone.family(), zone.address));
+            out.push(format!("        {} saddr {} jump outbound", zone.family(), zone.address));
+        }
+        out.push("    }".to_string());
+        out.push("    chain outbound {".to_string());
+        for fam in &["ip", "ip6"] {
+            let nameservers = self.nameservers.iter()
+                .filter(|addr| family(addr) == *fam)
+                .map(|addr| addr.to_string())
+                .collect::<Vec<_>>();
+            if !nameservers.is_empty() {
+                out.push(format!("        {} daddr {} meta l4proto {{ tcp, udp }} th dport 53 accept", fam, nft_set(&nameservers)));
+            }
+        }
+        for rule in &rules {
+            rule.write_rules(out);
+        }
+        if self.policy.isolate_lan() {
+            out.push(format!("        ip daddr {} drop", nft_set(LAN_NETWORKS)));
+            out.push(format!("        ip6 daddr {} drop", nft_set(LAN_NETWORKS6)));
+        }
+        if !self.policy.default_outbound_allowed() {
+            out.push("        drop".to_string());
+        }
+        out.push("    }".to_string());
+        out.push("}".to_string());
+        Ok(())
+    }
+
+    fn write_bridge_table(&self, out: &mut Vec<String>) {
+        self.write_table_header(out, "bridge");
+        out.push("    chain forward {".to_string());
+        out.push("        type filter hook forward priority filter; policy accept;".to_string());
+        for zone in &self.zones {
+            let fam = zone.family();
+            let mut allowed = vec![zone.gateway.to_string()];
+            allowed.extend(zone.allowed_realms.iter().map(|a| a.to_string()));
+
+            out.push(format!("        {} saddr {} ct state established,related accept", fam, zone.address));
+            out.push(format!("        {} saddr {} {} daddr {} accept", fam, zone.address, fam, nft_set(&allowed)));
+            if zone.address.is_ipv6() {
+                // Neighbor discovery must work for replies to connections from allowed realms
+                out.push(format!("        ip6 saddr {} icmpv6 type {{ nd-neighbor-solicit, nd-neighbor-advert }} accept", zone.address));
+            }
+            out.push(format!("        {} saddr {} {} daddr {} drop", fam, zone.address, fam, zone.network));
+        }
+        out.push("    }".to_string());
+        out.push("}".to_string());
+    }
+
+    /// Generate the ruleset for `realm` and load it with `nft`.
+    pub fn install(&self, realm: &Realm) -> Result<()> {
+        let ruleset = self.ruleset()?;
+        let path = realm.run_path_file(RULESET_FILE);
+        util::write_file(&path, &ruleset)?;
+        info!("Installing firewall rules for realm-{}", self.realm);
+        Exec::new(NFT).quiet()
+            .run(format!("-f {}", path.display()))
+            .map_err(context!("failed to load firewall rules for realm-{}", self.realm))
+    }
+
+    /// Remove any firewall rules installed for `realm`.
+    pub fn remove(realm: &Realm) {
+        let path = realm.run_path_file(RULESET_FILE);
+        if !path.exists() {
+            return;
+        }
+        info!("Removing firewall rules for realm-{}", realm.name());
+        for family in &["inet", "bridge"] {
+            if let Err(err) = Exec::new(NFT).quiet().run_ok(format!("delete table {} realm-{}", family, realm.name())) {
+                warn!("Error removing {} firewall table for realm-{}: {}", family, realm.name(), err);
+            }
+        }
+        if let Err(err) = util::remove_file(&path) {
+            warn!("{}", err);
+        }
+    }
+}
+
+#[test]
+fn test_firewall_ruleset() {
+    let addr = |s: &str| s.parse::<IpAddr>().unwrap();
+    let policy = FirewallPolicy {
+        default_outbound: Some("deny".to_string()),
+        allow_outbound: Some(vec![
+            "10.1.2.3:80,443".to_string(),
+            "192.168.1.0/24:53:udp".to_string(),
+            "[2001:db8::1]:443:tcp".to_string(),
+        ]),
+        isolate_lan: Some(true),
+        allow_realms: Some(vec!["other".to_string()]),
+    };
+    let zones = vec![
+        ZoneAddresses {
+            address: addr("172.17.0.5"),
+            network: "172.17.0.0/24".to_string(),
+            gateway: addr("172.17.0.1"),
+            allowed_realms: vec![addr("172.17.0.6")],
+        },
+        ZoneAddresses {
+            address: addr("fd00::5"),
+            network: "fd00::/64".to_string(),
+            gateway: addr("fd00::1"),
+            allowed_realms: vec![],
+        },
+    ];
+    let mut firewall = RealmFirewall::new("test", &policy, zones);
+    firewall.nameservers = vec![addr("172.17.0.1")];
+    let ruleset = firewall.ruleset().unwrap();
+
+    assert!(ruleset.contains("ip daddr 172.17.0.1 meta l4proto { tcp, udp } th dport 53 accept"));
+    assert!(ruleset.contains("ip daddr 10.1.2.3 tcp dport { 80, 443 } accept"));
+    assert!(ruleset.contains("ip daddr 10.1.2.3 udp dport { 80, 443 } accept"));
+    assert!(ruleset.contains("ip daddr 192.168.1.0/24 udp dport 53 accept"));
+    assert!(!ruleset.contains("192.168.1.0/24 tcp"));
+    assert!(ruleset.contains("ip6 daddr 2001:db8::1 tcp dport 443 accept"));
+    assert!(ruleset.contains("ip saddr 172.17.0.5 ip daddr { 172.17.0.1, 172.17.0.6 } accept"));
+    assert!(ruleset.contains("ip saddr 172.17.0.5 ip daddr 172.17.0.0/24 drop"));
+    assert!(ruleset.contains("ip6 saddr fd00::5 jump outbound"));
+    assert!(ruleset.contains("ip6 saddr fd00::5 ip6 daddr fd00::1 accept"));
+    assert!(ruleset.contains("ip6 saddr fd00::5 ip6 daddr fd00::/64 drop"));
+
+    // explicitly allowed LAN hosts must come before the isolation rule
+    let allow = ruleset.find("192.168.1.0/24 udp").unwrap();
+    let isolate = ruleset.find("192.168.0.0/16").unwrap();
+    assert!(allow < isolate);
+
+    assert!(OutboundRule::parse("10.0.0.1:http").is_err());
+    assert!(OutboundRule::parse("10.0.0.1:53:icmp").is_err());
+    assert!(OutboundRule::parse("[2001:db8::1").is_err());
+}
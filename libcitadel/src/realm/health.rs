@@ -0,0 +1,75 @@
+use std::time::Duration;
+
+// Delay before the first automatic restart of a realm, doubled for each further
+// consecutive restart up to RESTART_DELAY_MAX.
+const RESTART_DELAY_BASE: u64 = 5;
+const RESTART_DELAY_MAX: u64 = 300;
+
+/// When a realm which stops or becomes unhealthy is restarted by `realmsd`.
+#[derive(Clone,Copy,Debug,PartialEq,Eq)]
+pub enum RestartPolicy {
+    /// The realm is never restarted automatically.
+    Never,
+    /// The realm is restarted if its service unit fails or its health check fails,
+    /// but not if it is shut down cleanly from inside the realm.
+    OnFailure,
+    /// The realm is restarted whenever it stops without being stopped through
+    /// `RealmManager::stop_realm()`, or its health check fails.
+    Always,
+}
+
+impl RestartPolicy {
+    pub fn from_name(s: &str) -> Option<Self> {
+        match s {
+            "never" => Some(RestartPolicy::Never),
+            "on-failure" => Some(RestartPolicy::OnFailure),
+            "always" => Some(RestartPolicy::Always),
+            _ => None,
+        }
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            RestartPolicy::Never => "never",
+            RestartPolicy::OnFailure => "on-failure",
+            RestartPolicy::Always => "always",
+        }
+    }
+
+    /// Return `true` if a realm which stopped unexpectedly should be restarted.
+    /// `failed` is `true` if the service unit of the realm did not exit successfully.
+    pub fn restart_after_stop(&self, failed: bool) -> bool {
+        match self {
+            RestartPolicy::Never => false,
+            RestartPolicy::OnFailure => failed,
+            RestartPolicy::Always => true,
+        }
+    }
+
+    /// Return `true` if a realm should be restarted when its health check fails.
+    pub fn restart_when_unhealthy(&self) -> bool {
+        *self != RestartPolicy::Never
+    }
+
+    /// Time to wait before automatic restart number `attempt` (counting from 1) of a
+    /// realm which has been restarted `attempt - 1` times without staying up.
+    pub fn restart_delay(attempt: u32) -> Duration {
+        let shift = attempt.saturating_sub(1).min(16);
+        let secs = RESTART_DELAY_BASE.saturating_mul(1 << shift);
+        Duration::from_secs(secs.min(RESTART_DELAY_MAX))
+    }
+}
+
+#[test]
+fn test_restart_policy() {
+    assert_eq!(RestartPolicy::from_name("on-failure"), Some(RestartPolicy::OnFailure));
+    assert_eq!(RestartPolicy::from_name("sometimes"), None);
+    assert!(!RestartPolicy::OnFailure.restart_after_stop(false));
+    assert!(RestartPolicy::OnFailure.restart_after_stop(true));
+    assert!(RestartPolicy::Always.restart_after_stop(false));
+    assert!(!RestartPolicy::Never.restart_when_unhealthy());
+
+    assert_eq!(RestartPolicy::restart_delay(1), Duration::from_secs(5));
+    assert_eq!(RestartPolicy::restart_delay(3), Duration::from_secs(20));
+    assert_eq!(RestartPolicy::restart_delay(50), Duration::from_secs(300));
+}
@@ -0,0 +1,270 @@
+use std::fmt::{self, Display};
+use std::fs::{File, OpenOptions};
+use std::io::{BufRead, BufReader, Write};
+use std::path::Path;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::{FileLock, Realm, RealmEvent, Result, util};
+
+const JOURNAL_PATH: &str = "/storage/citadel-state/realm-events";
+const JOURNAL_LOCK_PATH: &str = "/storage/citadel-state/.realm-events.lock";
+
+// When the journal grows beyond this size the oldest entries are discarded until
+// it is half this size.
+const MAX_JOURNAL_SIZE: u64 = 256 * 1024;
+
+#[derive(Clone,Copy,Debug,PartialEq,Eq)]
+pub enum JournalEventKind {
+    Started,
+    Stopped,
+    New,
+    Removed,
+    Current,
+    ConfigChanged,
+    StartFailed,
+    Unhealthy,
+    Restarted,
+    RealmFSUpdated,
+    RealmFSRolledBack,
+}
+
+impl JournalEventKind {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            JournalEventKind::Started => "started",
+            JournalEventKind::Stopped => "stopped",
+            JournalEventKind::New => "new",
+            JournalEventKind::Removed => "removed",
+            JournalEventKind::Current => "current",
+            JournalEventKind::ConfigChanged => "config-changed",
+            JournalEventKind::StartFailed => "start-failed",
+            JournalEventKind::Unhealthy => "unhealthy",
+            JournalEventKind::Restarted => "restarted",
+            JournalEventKind::RealmFSUpdated => "realmfs-updated",
+            JournalEventKind::RealmFSRolledBack => "realmfs-rolled-back",
+        }
+    }
+
+    pub fn from_name(s: &str) -> Option<Self> {
+        let kind = match s {
+            "started" => JournalEventKind::Started,
+            "stopped" => JournalEventKind::Stopped,
+            "new" => JournalEventKind::New,
+            "removed" => JournalEventKind::Removed,
+            "current" => JournalEventKind::Current,
+            "config-changed" => JournalEventKind::ConfigChanged,
+            "start-failed" => JournalEventKind::StartFailed,
+            "unhealthy" => JournalEventKind::Unhealthy,
+            "restarted" => JournalEventKind::Restarted,
+            "realmfs-updated" => JournalEventKind::RealmFSUpdated,
+            "realmfs-rolled-back" => JournalEventKind::RealmFSRolledBack,
+            _ => return None,
+        };
+        Some(kind)
+    }
+
+    /// Return `true` if the name of an entry of this kind is a RealmFS name rather
+    /// than a realm name.
+    pub fn is_realmfs(&self) -> bool {
+        matches!(self, JournalEventKind::RealmFSUpdated | JournalEventKind::RealmFSRolledBack)
+    }
+}
+
+/// A single recorded event.
+#[derive(Clone,Debug)]
+pub struct JournalEntry {
+    timestamp: u64,
+    kind: JournalEventKind,
+    name: String,
+    reason: String,
+}
+
+impl JournalEntry {
+    fn new(kind: JournalEventKind, name: &str, reason: &str) -> Self {
+        let timestamp = SystemTime::now().duration_since(UNIX_EPOCH)
+            .map(|d| d.as_secs())
+            .unwrap_or(0);
+        // Fields are tab separated and entries newline separated
+        let clean = |s: &str| s.replace(['\t', '\n'], " ");
+        JournalEntry { timestamp, kind, name: clean(name), reason: clean(reason) }
+    }
+
+    fn parse(line: &str) -> Option<Self> {
+        let mut fields = line.splitn(4, '\t');
+        let timestamp = fields.next()?.parse().ok()?;
+        let kind = JournalEventKind::from_name(fields.next()?)?;
+        let name = fields.next()?.to_string();
+        let reason = fields.next().unwrap_or("").to_string();
+        Some(JournalEntry { timestamp, kind, name, reason })
+    }
+
+    fn to_line(&self) -> String {
+        format!("{}\t{}\t{}\t{}\n", self.timestamp, self.kind.as_str(), self.name, self.reason)
+    }
+
+    /// Time of the event in seconds since the epoch.
+    pub fn timestamp(&self) -> u64 {
+        self.timestamp
+    }
+
+    pub fn kind(&self) -> JournalEventKind {
+        self.kind
+    }
+
+    /// Name of the realm, or of the RealmFS image for RealmFS events. Empty for
+    /// `Current` events when no realm is current.
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn reason(&self) -> &str {
+        &self.reason
+    }
+
+    /// Local time of the event formatted as `YYYY-MM-DD HH:MM:SS`.
+    pub fn time_string(&self) -> String {
+        let t = self.timestamp as libc::time_t;
+        let mut tm: libc::tm = unsafe { std::mem::zeroed() };
+        if unsafe { libc::localtime_r(&t, &mut tm) }.is_null() {
+            return self.timestamp.to_string();
+        }
+        format!("{:04}-{:02}-{:02} {:02}:{:02}:{:02}",
+                tm.tm_year + 1900, tm.tm_mon + 1, tm.tm_mday, tm.tm_hour, tm.tm_min, tm.tm_sec)
+    }
+}
+
+impl Display for JournalEntry {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let subject = if self.kind.is_realmfs() {
+            format!("{}-realmfs.img", self.name)
+        } else if self.name.is_empty() {
+            "(none)".to_string()
+        } else {
+            format!("realm-{}", self.name)
+        };
+        write!(f, "{} {:<20} {:<20} {}", self.time_string(), self.kind.as_str(), subject, self.reason)
+    }
+}
+
+/// Persistent record of realm and RealmFS events.
+///
+/// Entries are appended to `/storage/citadel-state/realm-events` so that the history
+/// survives reboots. The file is kept below a fixed size by discarding the oldest
+/// entries.
+pub struct EventJournal;
+
+impl EventJournal {
+
+    /// Record a `RealmEvent` with a reason describing the event.
+    ///
+    /// RealmFS updates and rollbacks are recorded when they happen since they are
+    /// usually performed outside of realmsd. Other RealmFS events are not recorded.
+    pub fn record_event(event: &RealmEvent) -> Result<()> {
+        match event {
+            RealmEvent::Started(realm) => Self::record(JournalEventKind::Started, realm.name(), "realm started"),
+            RealmEvent::Stopped(realm) => Self::record(JournalEventKind::Stopped, realm.name(), "realm stopped"),
+            RealmEvent::New(realm) => Self::record(JournalEventKind::New, realm.name(), "realm created"),
+            RealmEvent::Removed(realm) => Self::record(JournalEventKind::Removed, realm.name(), "realm removed"),
+            RealmEvent::Current(Some(realm)) => Self::record(JournalEventKind::Current, realm.name(), "realm became current"),
+            RealmEvent::Current(None) => Self::record(JournalEventKind::Current, "", "no realm is current"),
+            RealmEvent::ConfigChanged(realm) => Self::record(JournalEventKind::ConfigChanged, realm.name(), "config file changed"),
+            RealmEvent::StartFailed(realm, err) => Self::record(JournalEventKind::StartFailed, realm.name(), err),
+            _ => Ok(()),
+        }
+    }
+
+    /// Append an entry to the journal.
+    pub fn record(kind: JournalEventKind, name: &str, reason: &str) -> Result<()> {
+        let entry = JournalEntry::new(kind, name, reason);
+        let path = Path::new(JOURNAL_PATH);
+        if let Some(parent) = path.parent() {
+            util::create_dir(parent)?;
+        }
+
+        let _lock = FileLock::acquire(JOURNAL_LOCK_PATH)?;
+        let mut file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path)
+            .map_err(context!("failed to open event journal {:?}", path))?;
+        file.write_all(entry.to_line().as_bytes())
+            .map_err(context!("failed to write to event journal {:?}", path))?;
+
+        let size = file.metadata().map(|meta| meta.len()).unwrap_or(0);
+        if size > MAX_JOURNAL_SIZE {
+            Self::truncate(path)?;
+        }
+        Ok(())
+    }
+
+    // Discard the oldest entries until the journal is at most half the maximum size
+    fn truncate(path: &Path) -> Result<()> {
+        let lines = Self::read_lines(path)?;
+        let mut size = 0;
+        let keep = lines.iter().rev()
+            .take_while(|line| {
+                size += line.len() as u64 + 1;
+                size <= MAX_JOURNAL_SIZE / 2
+            })
+            .count();
+        let mut content = lines[lines.len() - keep..].join("\n");
+        content.push('\n');
+        util::write_file(path, content)
+    }
+
+    fn read_lines(path: &Path) -> Result<Vec<String>> {
+        let file = File::open(path)
+            .map_err(context!("failed to open event journal {:?}", path))?;
+        let lines = BufReader::new(file).lines()
+            .collect::<std::io::Result<Vec<_>>>()
+            .map_err(context!("failed to read event journal {:?}", path))?;
+        Ok(lines)
+    }
+
+    fn read_entries() -> Result<Vec<JournalEntry>> {
+        let path = Path::new(JOURNAL_PATH);
+        if !path.exists() {
+            return Ok(Vec::new());
+        }
+        let entries = Self::read_lines(path)?
+            .iter()
+            .filter_map(|line| JournalEntry::parse(line))
+            .collect();
+        Ok(entries)
+    }
+
+    fn last_entries(entries: Vec<JournalEntry>, count: usize) -> Vec<JournalEntry> {
+        let skip = entries.len().saturating_sub(count);
+        entries.into_iter().skip(skip).collect()
+    }
+
+    /// The last `count` entries of the journal, oldest first.
+    pub fn recent(count: usize) -> Result<Vec<JournalEntry>> {
+        Ok(Self::last_entries(Self::read_entries()?, count))
+    }
+
+    /// The last `count` entries about `realm` or the RealmFS image it uses, oldest first.
+    pub fn history(realm: &Realm, count: usize) -> Result<Vec<JournalEntry>> {
+        let config = realm.config();
+        let realmfs = config.realmfs();
+        let entries = Self::read_entries()?
+            .into_iter()
+            .filter(|e| if e.kind.is_realmfs() { e.name == realmfs } else { e.name == realm.name() })
+            .collect();
+        Ok(Self::last_entries(entries, count))
+    }
+}
+
+#[test]
+fn test_journal_entry_line() {
+    let entry = JournalEntry::new(JournalEventKind::RealmFSUpdated, "base", "update\tapplied\nok");
+    let line = entry.to_line();
+    assert_eq!(line.matches('\t').count(), 3);
+
+    let parsed = JournalEntry::parse(line.trim_end_matches('\n')).unwrap();
+    assert_eq!(parsed.kind(), JournalEventKind::RealmFSUpdated);
+    assert_eq!(parsed.name(), "base");
+    assert_eq!(parsed.reason(), "update applied ok");
+    assert_eq!(parsed.timestamp(), entry.timestamp());
+    assert!(JournalEntry::parse("garbage").is_none());
+}
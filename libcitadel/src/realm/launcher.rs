@@ -1,7 +1,7 @@
 use std::fmt::{self,Write};
 use std::path::{Path, PathBuf};
 
-use crate::{Realm, Result, util, realm::network::NetworkConfig};
+use crate::{Realm, ResourceLimits, Result, util, realm::network::NetworkConfig};
 
 const NSPAWN_FILE_TEMPLATE: &str = "\
 [Exec]
@@ -21,11 +21,13 @@ $EXTRA_FILE_OPTIONS
 const REALM_SERVICE_TEMPLATE: &str = "\
 [Unit]
 Description=Application Image $REALM_NAME instance
+Before=citadel-realms-shutdown.service
 
 [Service]
 
 DevicePolicy=closed
 $DEVICE_ALLOW
+$RESOURCE_LIMITS
 
 Environment=SYSTEMD_NSPAWN_SHARE_NS_IPC=1
 ExecStart=/usr/bin/systemd-nspawn --quiet --notify-ready=yes --keep-unit $NETNS_ARG --machine=$REALM_NAME --link-journal=auto --directory=$ROOTFS
@@ -74,6 +76,12 @@ impl <'a> RealmLauncher <'a> {
         }
     }
 
+    /// Return `true` if the service unit file of the realm exists. The file is removed
+    /// when the realm is stopped with `RealmManager::stop_realm()`.
+    pub fn has_launch_config_files(&self) -> bool {
+        self.realm_service_path().exists()
+    }
+
     pub fn remove_launch_config_files(&self) -> Result<()> {
         util::remove_file(self.realm_nspawn_path())?;
         util::remove_file(self.realm_service_path())
@@ -192,8 +200,12 @@ impl <'a> RealmLauncher <'a> {
             let gw = netconfig.gateway(zone)?;
             writeln!(s, "Environment=IFCONFIG_IP={}", addr)?;
             writeln!(s, "Environment=IFCONFIG_GW={}", gw)?;
+            if let (Some(addr6), Some(gw6)) = (netconfig.allocated_address6(zone, self.realm.name()), netconfig.gateway6(zone)) {
+                writeln!(s, "Environment=IFCONFIG_IP6={}/64", addr6)?;
+                writeln!(s, "Environment=IFCONFIG_GW6={}", gw6)?;
+            }
             writeln!(s, "[Network]")?;
-            writeln!(s, "Zone=clear")?;
+            writeln!(s, "Zone={}", zone)?;
         } else {
             writeln!(s, "[Network]")?;
             writeln!(s, "Private=true")?;
@@ -212,10 +224,15 @@ impl <'a> RealmLauncher <'a> {
         for dev in &self.devices {
             writeln!(s, "DeviceAllow={}", dev).unwrap();
         }
+        let mut limits = String::new();
+        for property in ResourceLimits::from_config(&self.realm.config()).unit_properties() {
+            writeln!(limits, "{}", property).unwrap();
+        }
         REALM_SERVICE_TEMPLATE.replace("$REALM_NAME", self.realm.name())
             .replace("$ROOTFS", &rootfs)
             .replace("$NETNS_ARG", &netns_arg)
             .replace("$DEVICE_ALLOW", &s)
+            .replace("$RESOURCE_LIMITS", &limits)
     }
 
     fn realm_service_path(&self) -> PathBuf {
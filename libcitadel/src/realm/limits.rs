@@ -0,0 +1,105 @@
+use crate::RealmConfig;
+
+/// Names of the realm configuration variables which set cgroup resource limits.
+pub const LIMIT_VARIABLES: &[&str] = &[
+    "memory-max", "memory-high", "cpu-weight", "cpu-quota", "io-weight", "tasks-max",
+];
+
+/// Cgroup resource limits of a realm expressed as systemd unit properties.
+pub struct ResourceLimits {
+    memory_max: Option<String>,
+    memory_high: Option<String>,
+    cpu_weight: Option<u32>,
+    cpu_quota: Option<String>,
+    io_weight: Option<u32>,
+    tasks_max: Option<u32>,
+}
+
+impl ResourceLimits {
+
+    pub fn from_config(config: &RealmConfig) -> Self {
+        ResourceLimits {
+            memory_max: config.memory_max().map(String::from),
+            memory_high: config.memory_high().map(String::from),
+            cpu_weight: config.cpu_weight(),
+            cpu_quota: config.cpu_quota().map(String::from),
+            io_weight: config.io_weight(),
+            tasks_max: config.tasks_max(),
+        }
+    }
+
+    /// Return `true` if `name` is one of `LIMIT_VARIABLES`.
+    pub fn is_limit_variable(name: &str) -> bool {
+        LIMIT_VARIABLES.contains(&name)
+    }
+
+    /// Property assignments for each configured limit, to be written into the
+    /// `[Service]` section of the realm unit.
+    pub fn unit_properties(&self) -> Vec<String> {
+        self.properties(false)
+    }
+
+    /// Property assignments for every limit, including those which are not configured
+    /// and reset to the systemd default. Used to update the limits of a running unit.
+    pub fn runtime_properties(&self) -> Vec<String> {
+        self.properties(true)
+    }
+
+    fn properties(&self, with_defaults: bool) -> Vec<String> {
+        fn add(v: &mut Vec<String>, key: &str, value: Option<String>, default: Option<&str>) {
+            match (value, default) {
+                (Some(value), _) => v.push(format!("{}={}", key, value)),
+                (None, Some(default)) => v.push(format!("{}={}", key, default)),
+                (None, None) => {},
+            }
+        }
+        let default = |s| if with_defaults { Some(s) } else { None };
+        let mut v = Vec::new();
+        add(&mut v, "MemoryMax", self.memory_max.clone(), default("infinity"));
+        add(&mut v, "MemoryHigh", self.memory_high.clone(), default("infinity"));
+        add(&mut v, "CPUWeight", self.cpu_weight.map(|n| n.to_string()), default(""));
+        add(&mut v, "CPUQuota", self.cpu_quota.clone(), default(""));
+        add(&mut v, "IOWeight", self.io_weight.map(|n| n.to_string()), default(""));
+        add(&mut v, "TasksMax", self.tasks_max.map(|n| n.to_string()), default("infinity"));
+        v
+    }
+
+    /// A memory size is a number of bytes with an optional K, M, G or T suffix, a
+    /// percentage of physical memory, or `infinity`.
+    pub fn is_valid_memory_size(s: &str) -> bool {
+        if s == "infinity" {
+            return true;
+        }
+        if let Some(percent) = s.strip_suffix('%') {
+            return percent.parse::<f64>().is_ok_and(|n| n > 0.0 && n <= 100.0);
+        }
+        let digits = s.strip_suffix(['K', 'M', 'G', 'T']).unwrap_or(s);
+        !digits.is_empty() && digits.chars().all(|c| c.is_ascii_digit())
+    }
+
+    /// A CPU quota is a positive percentage of the time of a single CPU. Values above
+    /// 100% allow the realm to use more than one CPU.
+    pub fn is_valid_cpu_quota(s: &str) -> bool {
+        s.strip_suffix('%')
+            .and_then(|n| n.parse::<u32>().ok())
+            .is_some_and(|n| n > 0)
+    }
+}
+
+#[test]
+fn test_resource_limit_properties() {
+    let mut config = RealmConfig::empty();
+    config.memory_max = Some("4G".into());
+    config.cpu_weight = Some(200);
+
+    let limits = ResourceLimits::from_config(&config);
+    assert_eq!(limits.unit_properties(), vec!["MemoryMax=4G", "CPUWeight=200"]);
+    assert_eq!(limits.runtime_properties().len(), LIMIT_VARIABLES.len());
+    assert!(limits.runtime_properties().contains(&"TasksMax=infinity".to_string()));
+
+    assert!(ResourceLimits::is_valid_memory_size("512M"));
+    assert!(ResourceLimits::is_valid_memory_size("25%"));
+    assert!(!ResourceLimits::is_valid_memory_size("4GB"));
+    assert!(ResourceLimits::is_valid_cpu_quota("150%"));
+    assert!(!ResourceLimits::is_valid_cpu_quota("50"));
+}
@@ -1,16 +1,29 @@
 use posix_acl::{PosixACL, Qualifier, ACL_EXECUTE, ACL_READ};
+use sodiumoxide::randombytes::randombytes;
 use std::collections::HashSet;
+use std::fs::{self, File};
+use std::io;
 use std::path::{Path, PathBuf};
 use std::sync::{Arc, RwLock, RwLockReadGuard, RwLockWriteGuard};
 
 use crate::realmfs::realmfs_set::RealmFSSet;
-use crate::{util, Mountpoint, Realm, RealmFS, Realms, Result};
+use crate::{util, EventJournal, RealmDependencies, RealmStage, StopDependents, JournalEventKind, KeyRing, Mountpoint, PublicKey, Realm, RealmFS, Realms, Result, GLOBAL_CONFIG};
 
+use super::archive::{self, RealmArchive};
 use super::events::{RealmEvent, RealmEventListener};
 use super::network::NetworkConfig;
 use super::systemd::Systemd;
 use crate::realm::realms::HasCurrentChanged;
 
+// Directory where systemd-machined keeps state for each registered machine
+const MACHINES_RUN_PATH: &str = "/run/systemd/machines";
+
+// Directory in a realm which files opened from Citadel are copied into
+const REALM_OPEN_DIR: &str = "/tmp";
+
+// Health check commands which run longer than this many seconds are killed and fail
+const HEALTH_CHECK_TIMEOUT: &str = "30";
+
 struct Inner {
     events: RealmEventListener,
     realms: Realms,
@@ -38,10 +51,30 @@ pub struct RealmManager {
 impl RealmManager {
     fn create_network_config() -> Result<NetworkConfig> {
         let mut network = NetworkConfig::new();
-        network.add_bridge("clear", "172.17.0.0/24")?;
+        for (name, zone) in GLOBAL_CONFIG.network_zones() {
+            if let Err(err) = network.add_zone(&name, &zone) {
+                warn!("Ignoring network zone '{}': {}", name, err);
+            }
+        }
         Ok(network)
     }
 
+    // Release addresses left allocated to deleted realms. This rewrites the network
+    // state files, so it is only done when a realm is started or deleted rather than
+    // every time a manager is loaded, and a failure is only logged.
+    fn remove_stale_allocations(&self) {
+        if let Err(err) = self.systemd.remove_stale_allocations(Self::is_stale_allocation) {
+            warn!("Failed to remove stale network address allocations: {}", err);
+        }
+    }
+
+    // An address allocation is stale if it belongs to neither an existing realm nor
+    // a running machine such as a RealmFS update shell.
+    fn is_stale_allocation(name: &str) -> bool {
+        !Path::new(Realms::BASE_PATH).join(format!("realm-{}", name)).exists() &&
+            !Path::new(MACHINES_RUN_PATH).join(name).exists()
+    }
+
     pub fn load() -> Result<Arc<Self>> {
         let inner = Inner::new()?;
         let inner = RwLock::new(inner);
@@ -54,6 +87,10 @@ impl RealmManager {
 
         manager.set_manager(&manager);
 
+        if let Some(cycle) = manager.dependencies().find_cycle() {
+            warn!("Realm dependency cycle found in realm-depends: {}", cycle.join(" -> "));
+        }
+
         Ok(manager)
     }
 
@@ -114,6 +151,38 @@ impl RealmManager {
         Systemd::machinectl_shell(realm, args, "user", use_launcher, false)
     }
 
+    /// Run the `health-check` command of running `realm` and return `true` if it
+    /// succeeds or no health check is configured.
+    pub fn check_realm_health(&self, realm: &Realm) -> Result<bool> {
+        let config = realm.config();
+        let command = match config.health_check() {
+            Some(command) => command,
+            None => return Ok(true),
+        };
+        let args = ["/usr/bin/timeout", HEALTH_CHECK_TIMEOUT, "/bin/sh", "-c", command];
+        Systemd::machinectl_shell_succeeds(realm, &args, "root")
+    }
+
+    /// If `realm` is not running but was not stopped with `stop_realm()`, because it
+    /// crashed or was shut down from inside, return the result systemd recorded for
+    /// the realm service unit such as `success`, `exit-code` or `signal`.
+    pub fn unexpected_stop_result(&self, realm: &Realm) -> Result<Option<String>> {
+        Systemd::unexpected_stop_result(realm)
+    }
+
+    /// Start a realm which stopped without `stop_realm()`, first releasing the
+    /// network allocation, firewall rules and rootfs it left behind.
+    pub fn recover_realm(&self, realm: &Realm) -> Result<()> {
+        if realm.is_active() {
+            return Ok(());
+        }
+        info!("Recovering realm {}", realm.name());
+        self.systemd.stop_realm(realm)?;
+        self.refresh_peer_firewalls(realm);
+        realm.cleanup_rootfs();
+        self.start_realm(realm)
+    }
+
     pub fn run_in_current<S: AsRef<str>>(args: &[S], use_launcher: bool) -> Result<()> {
         let realm = Realms::load_current_realm()
             .ok_or_else(|| format_err!("Could not find current realm"))?;
@@ -136,6 +205,32 @@ impl RealmManager {
             .machinectl_copy_to(realm, from.as_ref(), to.as_ref())
     }
 
+    /// Copy the contents of `file` into running `realm` as a file named `filename` and
+    /// return the path of the copy inside the realm.
+    ///
+    /// Each file is copied into a new directory with a random name, because `machinectl
+    /// copy-to` will not replace a file copied earlier with the same name.
+    pub fn copy_file_into_realm(&self, realm: &Realm, file: &mut File, filename: &str) -> Result<PathBuf> {
+        if filename.is_empty() || filename.contains('/') || filename == "." || filename == ".." {
+            bail!("invalid filename '{}'", filename);
+        }
+        let dirname = format!("citadel-open-{}", hex::encode(randombytes(8)));
+        let target = Path::new(REALM_OPEN_DIR).join(&dirname);
+        let staging = realm.run_path_file(&dirname);
+        util::create_dir(&staging)?;
+
+        let staged = staging.join(filename);
+        let result = File::create(&staged)
+            .and_then(|mut out| io::copy(file, &mut out))
+            .map_err(context!("failed to copy {} to {:?}", filename, staged))
+            .and_then(|_| self.copy_to_realm(realm, &staging, &target));
+
+        if let Err(err) = fs::remove_dir_all(&staging) {
+            warn!("Failed to remove directory {:?}: {}", staging, err);
+        }
+        result.map(|_| target.join(filename))
+    }
+
     pub fn realm_list(&self) -> Vec<Realm> {
         self.inner_mut().realms.sorted()
     }
@@ -206,6 +301,11 @@ impl RealmManager {
     }
 
     pub fn start_realm(&self, realm: &Realm) -> Result<()> {
+        self.start_realm_with_progress(realm, &|_| {})
+    }
+
+    /// Start `realm` and call `progress` as each step of starting the realm begins.
+    pub fn start_realm_with_progress(&self, realm: &Realm, progress: &dyn Fn(RealmStage)) -> Result<()> {
         if realm.is_active() {
             info!(
                 "ignoring start request on already running realm '{}'",
@@ -214,7 +314,10 @@ impl RealmManager {
             return Ok(());
         }
         info!("Starting realm {}", realm.name());
-        self._start_realm(realm, &mut HashSet::new())?;
+        if let Err(err) = self._start_realm(realm, &mut HashSet::new(), progress) {
+            self.send_event(RealmEvent::StartFailed(realm.clone(), err.to_string()));
+            return Err(err);
+        }
 
         if !Realms::is_some_realm_current() {
             self.inner_mut()
@@ -237,7 +340,10 @@ impl RealmManager {
         Ok(())
     }
 
-    fn _start_realm(&self, realm: &Realm, starting: &mut HashSet<String>) -> Result<()> {
+    fn _start_realm(&self, realm: &Realm, starting: &mut HashSet<String>, progress: &dyn Fn(RealmStage)) -> Result<()> {
+        if !realm.config().realm_depends().is_empty() {
+            progress(RealmStage::StartingDependencies);
+        }
         self.start_realm_dependencies(realm, starting)?;
 
         let home = realm.base_path_file("home");
@@ -250,7 +356,7 @@ impl RealmManager {
             util::chown_user(&home)?;
         }
 
-        let rootfs = realm.setup_rootfs()?;
+        let rootfs = realm.setup_rootfs(progress)?;
 
         realm.update_timestamp()?;
 
@@ -258,11 +364,14 @@ impl RealmManager {
             self.ensure_run_media_directory()?;
         }
 
-        self.systemd.start_realm(realm, &rootfs)?;
+        self.remove_stale_allocations();
+        self.systemd.start_realm(realm, &rootfs, progress)?;
+        self.refresh_peer_firewalls(realm);
 
         self.create_realm_namefile(realm)?;
 
         if realm.config().wayland() {
+            progress(RealmStage::LinkingWaylandSocket);
             self.link_wayland_socket(realm)
                 .unwrap_or_else(|e| warn!("Error linking wayland socket: {}", e));
         }
@@ -288,7 +397,7 @@ impl RealmManager {
             if let Some(r) = self.realm_by_name(realm_name) {
                 if !r.is_active() && !starting.contains(r.name()) {
                     info!("Starting realm dependency realm-{}", realm.name());
-                    self._start_realm(&r, starting)?;
+                    self._start_realm(&r, starting, &|_| {})?;
                 }
             } else {
                 warn!("Realm dependency '{}' not found", realm_name);
@@ -310,7 +419,41 @@ impl RealmManager {
         )
     }
 
+    /// The `realm-depends` graph of all realms.
+    pub fn dependencies(&self) -> RealmDependencies {
+        RealmDependencies::new(&self.realm_list())
+    }
+
+    /// Running realms which depend on `realm` directly or indirectly, in the order
+    /// in which they can be stopped.
+    pub fn active_dependents(&self, realm: &Realm) -> Vec<Realm> {
+        self.dependencies()
+            .all_dependents(realm.name())
+            .into_iter()
+            .filter_map(|name| self.realm_by_name(name))
+            .filter(|r| r.is_active())
+            .collect()
+    }
+
+    /// Stop `realm`. Fails if any running realm depends on it.
     pub fn stop_realm(&self, realm: &Realm) -> Result<()> {
+        self.stop_realm_with_progress(realm, StopDependents::Refuse, &|_| {})
+    }
+
+    /// Stop `realm` after stopping all running realms which depend on it.
+    pub fn stop_realm_and_dependents(&self, realm: &Realm) -> Result<()> {
+        self.stop_realm_with_progress(realm, StopDependents::Cascade, &|_| {})
+    }
+
+    /// Stop and start `realm` again. Running realms which depend on it are left running.
+    pub fn restart_realm(&self, realm: &Realm) -> Result<()> {
+        self.stop_realm_with_progress(realm, StopDependents::Ignore, &|_| {})?;
+        self.start_realm(realm)
+    }
+
+    /// Stop `realm` and call `progress` as each step of stopping the realm begins.
+    /// Running realms which depend on `realm` are handled as `dependents` specifies.
+    pub fn stop_realm_with_progress(&self, realm: &Realm, dependents: StopDependents, progress: &dyn Fn(RealmStage)) -> Result<()> {
         if !realm.is_active() {
             info!(
                 "ignoring stop request on realm '{}' which is not running",
@@ -319,10 +462,28 @@ impl RealmManager {
             return Ok(());
         }
 
+        if dependents != StopDependents::Ignore {
+            let active = self.active_dependents(realm);
+            if !active.is_empty() && dependents == StopDependents::Refuse {
+                let names = active.iter().map(|r| r.name()).collect::<Vec<_>>();
+                bail!("realm-{} is required by running realms: {}", realm.name(), names.join(", "));
+            }
+            if !active.is_empty() {
+                progress(RealmStage::StoppingDependents);
+            }
+            for dependent in active {
+                info!("Stopping realm-{} which depends on realm-{}", dependent.name(), realm.name());
+                self.stop_realm_with_progress(&dependent, StopDependents::Ignore, &|_| {})?;
+            }
+        }
+
         info!("Stopping realm {}", realm.name());
 
         realm.set_active(false);
+        progress(RealmStage::StoppingUnit);
         self.systemd.stop_realm(realm)?;
+        self.refresh_peer_firewalls(realm);
+        progress(RealmStage::RemovingRootfs);
         realm.cleanup_rootfs();
 
         if realm.is_current() {
@@ -331,6 +492,79 @@ impl RealmManager {
         Ok(())
     }
 
+    /// Stop every running realm, each before the realms it depends on, and then
+    /// deactivate all RealmFS images. Used when the system shuts down, so failing
+    /// to stop a realm does not prevent stopping the others.
+    pub fn stop_all_realms(&self) -> Result<()> {
+        let mut failed = Vec::new();
+        for name in self.dependencies().stop_order() {
+            let realm = match self.realm_by_name(name) {
+                Some(realm) if realm.is_active() => realm,
+                _ => continue,
+            };
+            if let Err(err) = self.stop_realm_with_progress(&realm, StopDependents::Ignore, &|_| {}) {
+                warn!("Failed to stop realm-{}: {}", name, err);
+                failed.push(name.to_string());
+            }
+        }
+
+        for realmfs in self.realmfs_list() {
+            if realmfs.is_activated() {
+                info!("Deactivating RealmFS {}", realmfs.name());
+                realmfs.deactivate();
+            }
+        }
+
+        if !failed.is_empty() {
+            bail!("failed to stop realms: {}", failed.join(", "));
+        }
+        Ok(())
+    }
+
+    /// Generate the nftables ruleset for the firewall policy of `realm` without
+    /// installing it. Returns `None` if the realm has no firewall policy.
+    ///
+    /// The realm does not need to be running, in which case the rules are generated
+    /// for the address it would be allocated when it is started.
+    pub fn firewall_ruleset(&self, realm: &Realm) -> Result<Option<String>> {
+        match self.systemd.realm_firewall(realm)? {
+            Some(firewall) => firewall.ruleset().map(Some),
+            None => Ok(None),
+        }
+    }
+
+    /// Regenerate and install the firewall rules of running `realm` from its current
+    /// configuration.
+    pub fn apply_firewall(&self, realm: &Realm) -> Result<()> {
+        self.systemd.refresh_firewall(realm)
+    }
+
+    /// Apply the resource limits in the current configuration of running `realm` to
+    /// its service unit.
+    pub fn apply_resource_limits(&self, realm: &Realm) -> Result<()> {
+        self.systemd.apply_resource_limits(realm)
+    }
+
+    // Rules of realms which allow traffic to `realm` contain its address, so they
+    // are regenerated when `realm` starts or stops.
+    fn refresh_peer_firewalls(&self, realm: &Realm) {
+        for peer in self.active_realms(false) {
+            let allows_realm = peer.config().firewall()
+                .and_then(|policy| policy.allow_realms())
+                .is_some_and(|names| names.contains(&realm.name()));
+
+            if peer.name() != realm.name() && allows_realm {
+                if let Err(err) = self.systemd.refresh_firewall(&peer) {
+                    warn!("Failed to update firewall rules for realm-{}: {}", peer.name(), err);
+                }
+            }
+        }
+    }
+
+    pub(crate) fn send_event(&self, event: RealmEvent) {
+        self.inner().events.send_event(event);
+    }
+
     fn inner(&self) -> RwLockReadGuard<Inner> {
         self.inner.read().unwrap()
     }
@@ -428,17 +662,87 @@ impl RealmManager {
         Ok(realm)
     }
 
+    /// Export `realm` to a new archive file at `path` signed with the RealmFS user key.
+    pub fn export_realm(&self, realm: &Realm, path: &Path) -> Result<()> {
+        let keys = KeyRing::get_kernel_keypair(RealmFS::USER_KEYNAME)?;
+        archive::export(self, realm, path, &keys)
+    }
+
+    /// Create a new realm from an archive file produced by `export_realm()`.
+    ///
+    /// The archive must be signed by `public_key`, or by the RealmFS user key of this
+    /// system if `public_key` is `None`. The new realm is named `name` if provided,
+    /// otherwise the name of the exported realm is used. If a realm with this name
+    /// already exists a numeric suffix is added.
+    pub fn import_realm(&self, path: &Path, name: Option<&str>, public_key: Option<&PublicKey>) -> Result<Realm> {
+        let public_key = match public_key {
+            Some(key) => key.clone(),
+            None => KeyRing::get_kernel_keypair(RealmFS::USER_KEYNAME)?.public_key(),
+        };
+        let archive = RealmArchive::open(path, &public_key)?;
+        archive.check_realmfs(self);
+
+        let name = self.unused_realm_name(name.unwrap_or(archive.realm_name()))?;
+        let realm = self.inner_mut().realms.import_realm(&name, &archive.payload())?;
+        Self::remove_host_config(&realm)?;
+
+        info!("Imported realm-{} from {}", name, path.display());
+        self.inner()
+            .events
+            .send_event(RealmEvent::New(realm.clone()));
+        Ok(realm)
+    }
+
+    // Return `name` if no realm with this name exists, otherwise append the
+    // first numeric suffix which produces a name that is not in use.
+    fn unused_realm_name(&self, name: &str) -> Result<String> {
+        if !Realm::is_valid_name(name) {
+            bail!("'{}' is not a valid realm name", name);
+        }
+        let mut candidate = name.to_string();
+        let mut n = 2;
+        while self.realm_by_name(&candidate).is_some() {
+            candidate = format!("{}-{}", name, n);
+            if !Realm::is_valid_name(&candidate) {
+                bail!("Unable to find an unused name for imported realm '{}'", name);
+            }
+            n += 1;
+        }
+        Ok(candidate)
+    }
+
+    // Configuration options which refer to resources of the exporting host are
+    // removed from the configuration of an imported realm.
+    fn remove_host_config(realm: &Realm) -> Result<()> {
+        let config = realm.config();
+        if config.extra_bindmounts.is_none() && config.extra_bindmounts_ro.is_none()
+            && config.reserved_ip.is_none() && config.netns.is_none() {
+            return Ok(());
+        }
+        warn!("Removing host specific bind mount, address and netns options from imported realm-{}", realm.name());
+        realm.with_mut_config(|c| {
+            c.extra_bindmounts = None;
+            c.extra_bindmounts_ro = None;
+            c.reserved_ip = None;
+            c.netns = None;
+        });
+        realm.write_config()
+    }
+
     pub fn delete_realm(&self, realm: &Realm, save_home: bool) -> Result<()> {
         if realm.is_active() {
             self.stop_realm(realm)?;
         }
         self.inner_mut()
             .realms
-            .delete_realm(realm.name(), save_home)
+            .delete_realm(realm.name(), save_home)?;
+        self.remove_stale_allocations();
+        Ok(())
     }
 
     pub fn realmfs_added(&self, realmfs: &RealmFS) {
         self.inner_mut().realmfs_set.add(realmfs);
+        self.send_event(RealmEvent::RealmFSNew(realmfs.clone()));
     }
 
     pub fn delete_realmfs(&self, realmfs: &RealmFS) -> Result<()> {
@@ -450,7 +754,27 @@ impl RealmManager {
             bail!("Unable to deactive Realmfs, cannot delete");
         }
         self.inner_mut().realmfs_set.remove(realmfs.name());
+        realmfs.remove_generations()?;
         info!("Removing RealmFS image file {}", realmfs.path().display());
-        util::remove_file(realmfs.path())
+        util::remove_file(realmfs.path())?;
+        self.send_event(RealmEvent::RealmFSRemoved(realmfs.clone()));
+        Ok(())
+    }
+
+    pub fn rollback_realmfs(&self, realmfs: &RealmFS, generation: usize) -> Result<()> {
+        if realmfs.is_in_use() {
+            bail!("Cannot roll back realmfs because it is in use");
+        }
+        realmfs.deactivate();
+        if realmfs.is_activated() {
+            bail!("Unable to deactive Realmfs, cannot roll back");
+        }
+        realmfs.restore_generation(generation)?;
+        self.send_event(RealmEvent::RealmFSUpdated(realmfs.clone()));
+        let reason = format!("rolled back to generation {}", generation);
+        if let Err(e) = EventJournal::record(JournalEventKind::RealmFSRolledBack, realmfs.name(), &reason) {
+            warn!("error recording RealmFS rollback in event journal: {}", e);
+        }
+        Ok(())
     }
 }
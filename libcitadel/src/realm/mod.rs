@@ -1,6 +1,7 @@
 
 pub(crate) mod overlay;
 pub(crate) mod config;
+pub(crate) mod config_value;
 pub(crate) mod realms;
 pub(crate) mod manager;
 #[allow(clippy::module_inception)]
@@ -8,6 +9,15 @@ pub(crate) mod realm;
 pub (crate) mod network;
 pub(crate) mod create;
 pub(crate) mod events;
+pub(crate) mod snapshot;
+pub(crate) mod archive;
+pub(crate) mod firewall;
+pub(crate) mod limits;
+pub(crate) mod stats;
+pub(crate) mod journal;
+pub(crate) mod stage;
+pub(crate) mod health;
+pub(crate) mod depends;
 mod systemd;
 mod launcher;
 
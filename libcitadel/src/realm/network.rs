@@ -1,38 +1,95 @@
 use std::path::{Path,PathBuf};
-use std::net::Ipv4Addr;
+use std::net::{Ipv4Addr,Ipv6Addr};
 use std::collections::{HashSet,HashMap};
 use std::io::{BufReader,BufRead,Write};
 use std::fs::File;
 
-use crate::{Result, util};
+use crate::{NetworkZone, Result, GLOBAL_CONFIG, util};
+use crate::realm::config::DEFAULT_ZONE;
 
 const REALMS_RUN_PATH: &str = "/run/citadel/realms";
 
-const CLEAR_BRIDGE_NETWORK: &str = "172.17.0.0/24";
+const NETWORKD_RUN_PATH: &str = "/run/systemd/network";
+const NETWORKCTL_PATH: &str = "/usr/bin/networkctl";
 
 const MIN_MASK: usize = 16;
 const MAX_MASK: usize = 24;
 const RESERVED_START: u8 = 200;
 
+// Only /64 prefixes are supported for the IPv6 network of a zone
+const IPV6_PREFIX_LEN: usize = 64;
+
 /// Manage ip address assignment for bridges
 pub struct NetworkConfig {
     allocators: HashMap<String, BridgeAllocator>,
+    zones: HashMap<String, NetworkZone>,
 }
 
 impl NetworkConfig {
     pub fn new() -> NetworkConfig {
         NetworkConfig {
             allocators: HashMap::new(),
+            zones: HashMap::new(),
         }
     }
 
-    pub fn add_bridge(&mut self, name: &str, network: &str) -> Result<()> {
-        let allocator = BridgeAllocator::for_bridge(name, network)
-            .map_err(|e| format_err!("Failed to create bridge allocator: {}", e))?;
+    /// Add an allocator for network zone `name` with the network and gateway from `zone`.
+    pub fn add_zone(&mut self, name: &str, zone: &NetworkZone) -> Result<()> {
+        if !NetworkZone::is_valid_name(name) {
+            bail!("Invalid network zone name '{}'", name);
+        }
+        let allocator = BridgeAllocator::for_zone(name, zone)
+            .map_err(|e| format_err!("Failed to create bridge allocator for zone {}: {}", name, e))?;
         self.allocators.insert(name.to_owned(), allocator);
+        self.zones.insert(name.to_owned(), zone.clone());
+        Ok(())
+    }
+
+    /// Remove address allocations for which `is_stale` returns `true` for the
+    /// allocation name from every zone.
+    pub fn remove_stale_allocations<F>(&mut self, is_stale: F) -> Result<()>
+        where F: Fn(&str) -> bool
+    {
+        for allocator in self.allocators.values_mut() {
+            allocator.remove_stale_allocations(&is_stale)?;
+        }
         Ok(())
     }
 
+    /// Write a systemd-networkd configuration file which assigns the gateway addresses
+    /// to the bridge interface of zone `bridge` and reload networkd if the file changed.
+    ///
+    /// The host configuration for the default zone is provided by the operating
+    /// system, so no file is written unless the default zone has been reconfigured.
+    pub fn write_networkd_config(&self, bridge: &str) -> Result<()> {
+        let allocator = match self.allocators.get(bridge) {
+            Some(allocator) => allocator,
+            None => bail!("Failed to configure bridge {} because it does not exist", bridge),
+        };
+        let path = Path::new(NETWORKD_RUN_PATH).join(format!("10-citadel-vz-{}.network", bridge));
+        let is_default = bridge == DEFAULT_ZONE && self.zones.get(bridge) == Some(&NetworkZone::default_zone());
+        if is_default {
+            if path.exists() {
+                util::remove_file(&path)?;
+                Self::reload_networkd()?;
+            }
+            return Ok(());
+        }
+
+        let content = allocator.networkd_config();
+        if path.exists() && util::read_to_string(&path)? == content {
+            return Ok(());
+        }
+        util::create_dir(NETWORKD_RUN_PATH)?;
+        util::write_file(&path, content)?;
+        Self::reload_networkd()
+    }
+
+    fn reload_networkd() -> Result<()> {
+        cmd!(NETWORKCTL_PATH, "reload")
+            .map_err(context!("failed to reload systemd-networkd configuration"))
+    }
+
     pub fn gateway(&self, bridge: &str) -> Result<String> {
         match self.allocators.get(bridge) {
             Some(allocator) => Ok(allocator.gateway()),
@@ -60,21 +117,76 @@ impl NetworkConfig {
             None => bail!("Failed to allocate address for bridge {} because it does not exist", bridge),
         }
     }
+
+    /// Return the address currently allocated to `realm_name` on `bridge` if any.
+    pub fn allocated_address(&self, bridge: &str, realm_name: &str) -> Option<Ipv4Addr> {
+        self.allocators.get(bridge)
+            .and_then(|allocator| allocator.allocated_address(realm_name))
+    }
+
+    /// Return the IPv6 address of `realm_name` on `bridge` if an address has been
+    /// allocated and IPv6 is enabled for the zone.
+    pub fn allocated_address6(&self, bridge: &str, realm_name: &str) -> Option<Ipv6Addr> {
+        self.allocators.get(bridge)
+            .and_then(|allocator| allocator.allocated_address6(realm_name))
+    }
+
+    /// Return the address `realm_name` has on `bridge`, or the address it would be
+    /// allocated if it were started now with the reserved octet `reserved` if any.
+    pub fn expected_address(&self, bridge: &str, realm_name: &str, reserved: Option<u8>) -> Option<Ipv4Addr> {
+        self.allocators.get(bridge)
+            .and_then(|allocator| allocator.expected_address(realm_name, reserved))
+    }
+
+    /// Return the IPv6 address on `bridge` which corresponds to IPv4 address `addr` if
+    /// IPv6 is enabled for the zone.
+    pub fn address6(&self, bridge: &str, addr: Ipv4Addr) -> Option<Ipv6Addr> {
+        self.allocators.get(bridge)
+            .and_then(|allocator| allocator.to_address6(addr))
+    }
+
+    /// Return the IPv6 gateway address of `bridge` if IPv6 is enabled for the zone.
+    pub fn gateway6(&self, bridge: &str) -> Option<Ipv6Addr> {
+        self.allocators.get(bridge)
+            .and_then(|allocator| allocator.gateway6())
+    }
+
+    /// Return the IPv6 network of `bridge` in CIDR notation if IPv6 is enabled for the zone.
+    pub fn network6(&self, bridge: &str) -> Option<String> {
+        self.allocators.get(bridge)
+            .and_then(|allocator| allocator.network6())
+    }
+
+    /// Return the network of `bridge` in CIDR notation.
+    pub fn network(&self, bridge: &str) -> Result<String> {
+        match self.allocators.get(bridge) {
+            Some(allocator) => Ok(allocator.network()),
+            None => bail!("Failed to return network for bridge {} because it does not exist", bridge),
+        }
+    }
 }
 
 ///
 /// Allocates IP addresses for a bridge shared by multiple realms.
 ///
-/// State information is stored in /run/citadel/realms/network-$bridge as
+/// State information is stored in /run/citadel/network-$bridge as
 /// colon ':' separated pairs of realm name and allocated ip address
 ///
 ///    realm-a:172.17.0.2
 ///    realm-b:172.17.0.3
 ///
+/// If the zone has an IPv6 network, each realm is also assigned the address in the
+/// IPv6 prefix with the same host number as the IPv4 address, so reserved addresses
+/// and the gateway use the same host number on both networks:
+///
+///    172.17.0.5  ->  fd8a:1c3e:77f0:1::5
+///
 pub struct BridgeAllocator {
     bridge: String,
     network: Ipv4Addr,
+    gateway: Ipv4Addr,
     mask_size: usize,
+    network6: Option<Ipv6Addr>,
     allocated: HashSet<Ipv4Addr>,
     allocations: HashMap<String, Ipv4Addr>,
 }
@@ -83,10 +195,14 @@ impl BridgeAllocator {
 
 
     pub fn default_bridge() -> Result<BridgeAllocator> {
-        BridgeAllocator::for_bridge("clear", CLEAR_BRIDGE_NETWORK)
+        let zone = GLOBAL_CONFIG.network_zones()
+            .remove(DEFAULT_ZONE)
+            .unwrap_or_else(NetworkZone::default_zone);
+        BridgeAllocator::for_zone(DEFAULT_ZONE, &zone)
     }
 
-    pub fn for_bridge(bridge: &str, network: &str) -> Result<BridgeAllocator> {
+    pub fn for_zone(bridge: &str, zone: &NetworkZone) -> Result<BridgeAllocator> {
+        let network = zone.network.as_str();
         let (addr_str, mask_size) = match network.find('/') {
             Some(idx) => {
                 let (net,bits) = network.split_at(idx);
@@ -106,18 +222,58 @@ impl BridgeAllocator {
             bail!("network {} has masked bits with netmask /{}", addr_str, mask_size);
         }
 
-        let mut conf = BridgeAllocator::new(bridge, ip, mask_size);
+        let gateway = match zone.gateway {
+            Some(ref gw) => {
+                let gw = gw.parse::<Ipv4Addr>().map_err(|_| format_err!("Failed to parse gateway address ({})", gw))?;
+                let host = u32::from(gw) & mask;
+                if (u32::from(gw) & !mask) != u32::from(ip) || host == 0 || host == mask || Self::is_reserved(gw) {
+                    bail!("gateway {} is not a usable address on network {}", gw, network);
+                }
+                gw
+            },
+            None => Ipv4Addr::from(u32::from(ip) + 1),
+        };
+
+        let network6 = match zone.network6.as_deref() {
+            Some(network6) => Some(Self::parse_network6(network6)?),
+            None => None,
+        };
+
+        let mut conf = BridgeAllocator::new(bridge, ip, gateway, mask_size, network6);
         conf.load_state()?;
         Ok(conf)
     }
 
-    fn new(bridge: &str, network: Ipv4Addr, mask_size: usize) -> BridgeAllocator {
+    fn new(bridge: &str, network: Ipv4Addr, gateway: Ipv4Addr, mask_size: usize, network6: Option<Ipv6Addr>) -> BridgeAllocator {
         BridgeAllocator {
             bridge: bridge.to_owned(),
             allocated: HashSet::new(),
             allocations: HashMap::new(),
-            network, mask_size,
+            network, gateway, mask_size, network6,
+        }
+    }
+
+    fn parse_network6(network: &str) -> Result<Ipv6Addr> {
+        let (addr_str, prefix_len) = match network.find('/') {
+            Some(idx) => (&network[..idx], &network[idx + 1..]),
+            None => bail!("IPv6 network {} must have a /{} prefix length", network, IPV6_PREFIX_LEN),
+        };
+        if prefix_len.parse::<usize>().ok() != Some(IPV6_PREFIX_LEN) {
+            bail!("Unsupported IPv6 prefix length /{}, only /{} is supported", prefix_len, IPV6_PREFIX_LEN);
         }
+        let ip = addr_str.parse::<Ipv6Addr>().map_err(|_| format_err!("Failed to parse IPv6 address ({})", addr_str))?;
+        if u128::from(ip) as u64 != 0 {
+            bail!("IPv6 network {} has masked bits with prefix /{}", addr_str, IPV6_PREFIX_LEN);
+        }
+        Ok(ip)
+    }
+
+    // Map an IPv4 address on this bridge to the address with the same host number in
+    // the IPv6 prefix.
+    fn to_address6(&self, addr: Ipv4Addr) -> Option<Ipv6Addr> {
+        let mask = (1u32 << (32 - self.mask_size)) - 1;
+        let host = u32::from(addr) & mask;
+        self.network6.map(|net| Ipv6Addr::from(u128::from(net) | u128::from(host)))
     }
 
     pub fn allocate_address_for(&mut self, realm_name: &str) -> Result<String> {
@@ -146,9 +302,9 @@ impl BridgeAllocator {
     fn find_free_address(&self) -> Option<Ipv4Addr> {
         let mask = (1u32 << (32 - self.mask_size)) - 1;
         let net =  u32::from(self.network);
-        for i in 2..mask {
+        for i in 1..mask {
             let addr = Ipv4Addr::from(net + i);
-            if !Self::is_reserved(addr) && !self.allocated.contains(&addr) {
+            if addr != self.gateway && !Self::is_reserved(addr) && !self.allocated.contains(&addr) {
                 return Some(addr);
             }
         }
@@ -159,10 +315,79 @@ impl BridgeAllocator {
         addr.octets()[3] >= RESERVED_START
     }
 
+    pub fn allocated_address(&self, realm_name: &str) -> Option<Ipv4Addr> {
+        self.allocations.get(realm_name).cloned()
+    }
+
+    fn expected_address(&self, realm_name: &str, reserved: Option<u8>) -> Option<Ipv4Addr> {
+        if let Some(addr) = self.allocated_address(realm_name) {
+            return Some(addr);
+        }
+        match reserved {
+            Some(octet) if octet >= RESERVED_START => Some(Ipv4Addr::from(u32::from(self.network) | u32::from(octet))),
+            Some(_) => None,
+            None => self.find_free_address(),
+        }
+    }
+
+    pub fn network(&self) -> String {
+        format!("{}/{}", self.network, self.mask_size)
+    }
+
     pub fn gateway(&self) -> String {
-        let gw = u32::from(self.network) + 1;
-        let addr = Ipv4Addr::from(gw);
-        addr.to_string()
+        self.gateway.to_string()
+    }
+
+    pub fn allocated_address6(&self, realm_name: &str) -> Option<Ipv6Addr> {
+        self.allocated_address(realm_name)
+            .and_then(|addr| self.to_address6(addr))
+    }
+
+    pub fn network6(&self) -> Option<String> {
+        self.network6.map(|net| format!("{}/{}", net, IPV6_PREFIX_LEN))
+    }
+
+    pub fn gateway6(&self) -> Option<Ipv6Addr> {
+        self.to_address6(self.gateway)
+    }
+
+    fn networkd_config(&self) -> String {
+        let mut addresses = format!("Address={}/{}\n", self.gateway, self.mask_size);
+        let masquerade = match self.gateway6() {
+            Some(gw6) => {
+                addresses.push_str(&format!("Address={}/{}\nIPv6SendRA=no\n", gw6, IPV6_PREFIX_LEN));
+                "both"
+            },
+            None => "ipv4",
+        };
+        format!("\
+[Match]
+Name=vz-{}
+Driver=bridge
+
+[Network]
+{}IPMasquerade={}
+ConfigureWithoutCarrier=yes
+", self.bridge, addresses, masquerade)
+    }
+
+    fn remove_stale_allocations<F>(&mut self, is_stale: &F) -> Result<()>
+        where F: Fn(&str) -> bool
+    {
+        let stale = self.allocations.keys()
+            .filter(|name| is_stale(name))
+            .cloned()
+            .collect::<Vec<_>>();
+        if stale.is_empty() {
+            return Ok(());
+        }
+        for name in &stale {
+            info!("Removing stale address allocation for {} on zone {}", name, self.bridge);
+            if let Some(ip) = self.allocations.remove(name) {
+                self.allocated.remove(&ip);
+            }
+        }
+        self.write_state()
     }
 
     fn allocate_reserved(&mut self, realm_name: &str, octet: u8) -> Result<String> {
@@ -236,3 +461,18 @@ impl BridgeAllocator {
         Ok(())
     }
 }
+
+#[test]
+fn test_ipv6_address_mapping() {
+    let network6 = BridgeAllocator::parse_network6("fd00:1:2:3::/64").unwrap();
+    let alloc = BridgeAllocator::new("test", Ipv4Addr::new(172, 17, 0, 0), Ipv4Addr::new(172, 17, 0, 1), 24, Some(network6));
+    assert_eq!(alloc.gateway6(), Some("fd00:1:2:3::1".parse().unwrap()));
+    assert_eq!(alloc.to_address6(Ipv4Addr::new(172, 17, 0, 200)), Some("fd00:1:2:3::c8".parse().unwrap()));
+
+    assert!(BridgeAllocator::parse_network6("fd00:1:2:3::/48").is_err());
+    assert!(BridgeAllocator::parse_network6("fd00:1:2:3::1/64").is_err());
+
+    let ipv4_only = BridgeAllocator::new("test", Ipv4Addr::new(172, 17, 0, 0), Ipv4Addr::new(172, 17, 0, 1), 24, None);
+    assert_eq!(ipv4_only.gateway6(), None);
+    assert!(ipv4_only.networkd_config().contains("IPMasquerade=ipv4\n"));
+}
@@ -4,6 +4,7 @@ use std::path::{Path,PathBuf};
 use crate::{Realm, Result, util};
 use crate::Exec;
 use crate::realm::config::OverlayType;
+use crate::realm::snapshot::OVERLAY_RESTORE;
 
 const REALMS_BASE_PATH: &str = "/realms";
 const REALMS_RUN_PATH: &str = "/run/citadel/realms";
@@ -90,6 +91,15 @@ impl RealmOverlay {
         }
     }
 
+    /// Return the number of bytes used by files written to the overlay.
+    pub fn disk_usage(&self) -> Result<u64> {
+        let upper = self.overlay_directory().join("upperdir");
+        if !upper.exists() {
+            return Ok(0);
+        }
+        util::disk_usage(&upper)
+    }
+
     fn remove_tmpfs(&self, base: &Path) -> Result<()> {
         fs::remove_dir_all(base)
             .map_err(context!("failed to remove overlay directory {:?}", base))
@@ -130,10 +140,34 @@ impl RealmOverlay {
             self.umount_overlay();
             self.remove_btrfs(&subvolume)?;
         }
-        Exec::new("/usr/bin/btrfs").quiet().run(format!("subvolume create {}", subvolume.display()))?;
+        let staged = subvolume.with_file_name(OVERLAY_RESTORE);
+        if staged.exists() {
+            info!("Using overlay state restored from snapshot for realm-{}", self.realm);
+            util::rename(&staged, &subvolume)?;
+            self.clear_restored_overlay(&subvolume)?;
+        } else {
+            Exec::new("/usr/bin/btrfs").quiet().run(format!("subvolume create {}", subvolume.display()))?;
+        }
         self.setup_overlay(&subvolume, lower)
     }
 
+    // A restored overlay subvolume still contains the 'lower' link and work directory
+    // from when the snapshot was taken. Only the upper directory is kept.
+    fn clear_restored_overlay(&self, base: &Path) -> Result<()> {
+        // the link target is usually gone, so test for the link itself rather than use exists()
+        let lower = base.join("lower");
+        if lower.symlink_metadata().is_ok() {
+            fs::remove_file(&lower)
+                .map_err(context!("failed to remove overlay lower link {:?}", lower))?;
+        }
+        let work = base.join("workdir");
+        if work.exists() {
+            fs::remove_dir_all(&work)
+                .map_err(context!("failed to remove overlay work directory {:?}", work))?;
+        }
+        Ok(())
+    }
+
     fn setup_overlay(&self, base: &Path, lower: &Path) -> Result<PathBuf> {
         let upper = self.mkdir(base, "upperdir")?;
         let work = self.mkdir(base, "workdir")?;
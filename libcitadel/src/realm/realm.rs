@@ -6,12 +6,13 @@ use std::os::unix::fs::MetadataExt;
 
 
 use super::overlay::RealmOverlay;
+use super::snapshot::{self, RealmSnapshot};
 use super::config::{RealmConfig,GLOBAL_CONFIG};
 use super::realms::Realms;
 use super::systemd::Systemd;
 
 use crate::realmfs::Mountpoint;
-use crate::{symlink, util, Result, RealmFS, CommandLine, RealmManager, OverlayType};
+use crate::{symlink, util, Result, RealmFS, CommandLine, RealmManager, OverlayType, RealmStage, RealmEvent};
 
 
 const MAX_REALM_NAME_LEN:usize = 128;
@@ -84,6 +85,11 @@ impl Realm {
         }
     }
 
+    /// Return the manager of this realm or `None` if it was loaded without one.
+    pub(crate) fn try_manager(&self) -> Option<Arc<RealmManager>> {
+        self.manager.upgrade()
+    }
+
     fn inner(&self) -> RwLockReadGuard<Inner> {
         self.inner.read().unwrap()
     }
@@ -214,14 +220,18 @@ impl Realm {
     ///   3) create 'rootfs' symlink in realm run path pointing to rootfs base
     ///   4) create 'realmfs-mountpoint' symlink pointing to realmfs mount
     ///
-    pub fn setup_rootfs(&self) -> Result<PathBuf> {
+    pub fn setup_rootfs(&self, progress: &dyn Fn(RealmStage)) -> Result<PathBuf> {
+        progress(RealmStage::MountingRealmFS);
         let realmfs = self.get_named_realmfs(self.config().realmfs())?;
 
         realmfs.activate()?;
         let mountpoint = realmfs.mountpoint();
 
         let rootfs = match RealmOverlay::for_realm(self) {
-            Some(ref overlay) => overlay.create(mountpoint.path())?,
+            Some(ref overlay) => {
+                progress(RealmStage::CreatingOverlay);
+                overlay.create(mountpoint.path())?
+            },
             _ => mountpoint.path().to_owned(),
         };
 
@@ -347,16 +357,27 @@ impl Realm {
 
     /// Return `Arc<RealmConfig>` containing the configuration of this realm.
     /// If the config file has not yet been loaded from disk, it is lazy loaded
-    /// the first time this method is called.
+    /// the first time this method is called. If the config file has changed on
+    /// disk since it was loaded it is reloaded and a `ConfigChanged` event is sent.
     pub fn config(&self) -> Arc<RealmConfig> {
-        if self.inner_config().is_stale() {
+        let config = self.inner_config();
+        if config.is_stale() {
             if let Err(err) = self.with_mut_config(|config| config.reload()) {
                 warn!("error loading config file for realm {}: {}", self.name(), err);
+            } else if config.is_loaded() {
+                if let Some(manager) = self.try_manager() {
+                    manager.send_event(RealmEvent::ConfigChanged(self.clone()));
+                }
             }
         }
         self.inner_config()
     }
 
+    /// Write the configuration of this realm to the config file.
+    pub fn write_config(&self) -> Result<()> {
+        self.with_mut_config(|config| config.write())
+    }
+
     fn inner_config(&self) -> Arc<RealmConfig> {
         self.inner().config.clone()
     }
@@ -467,6 +488,26 @@ impl Realm {
             util::write_file(&path, notes)
         }
     }
+
+    /// Save the current home directory and storage overlay state of this realm
+    /// as a new snapshot with name `label`.
+    pub fn snapshot(&self, label: &str) -> Result<RealmSnapshot> {
+        snapshot::create(self, label)
+    }
+
+    /// Return the snapshots of this realm ordered from oldest to newest.
+    pub fn list_snapshots(&self) -> Result<Vec<RealmSnapshot>> {
+        snapshot::list(self)
+    }
+
+    /// Restore the state saved in snapshot `label`. The realm must not be running.
+    pub fn restore_snapshot(&self, label: &str) -> Result<()> {
+        snapshot::restore(self, label)
+    }
+
+    pub fn delete_snapshot(&self, label: &str) -> Result<()> {
+        snapshot::delete(self, label)
+    }
 }
 
 impl Eq for Realm {}
@@ -241,6 +241,20 @@ impl Realms {
         Ok(self.add_realm(name))
     }
 
+    pub fn import_realm(&mut self, name: &str, payload: &Path) -> Result<Realm> {
+        let _lock = Self::realmslock()?;
+
+        if !Realm::is_valid_name(name) {
+            bail!("'{}' is not a valid realm name. Only letters, numbers and dash '-' symbol allowed in name. First character must be a letter", name);
+        } else if self.by_name(name).is_some() {
+            bail!("A realm with name '{}' already exists", name);
+        }
+
+        RealmCreateDestroy::new(name).import(payload)?;
+
+        Ok(self.add_realm(name))
+    }
+
     pub fn delete_realm(&mut self, name: &str, save_home: bool) -> Result<()> {
         let _lock = Self::realmslock()?;
 
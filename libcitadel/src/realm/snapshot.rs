@@ -0,0 +1,280 @@
+use std::fs;
+use std::os::unix::fs::MetadataExt;
+use std::path::{Path, PathBuf};
+
+use crate::{Exec, Realm, Result, OverlayType, util};
+
+const BTRFS: &str = "/usr/bin/btrfs";
+const TAR: &str = "/usr/bin/tar";
+
+// Maximum length of a snapshot label
+const MAX_LABEL_LEN: usize = 40;
+
+// Name of directory in realm base path where snapshots are stored
+const SNAPSHOTS_DIR: &str = "snapshots";
+
+// Overlay state from a restored snapshot waiting to be used the next time the realm starts
+pub(crate) const OVERLAY_RESTORE: &str = "overlay.restore";
+
+///
+/// A saved copy of the home directory and storage overlay of a realm.
+///
+/// Snapshots are stored in the realm directory:
+///
+///```text
+///     /realms/realm-$NAME/snapshots/$LABEL/home
+///     /realms/realm-$NAME/snapshots/$LABEL/overlay
+///```
+///
+/// If the saved directory is a btrfs subvolume, it is stored as a read-only subvolume
+/// snapshot. Otherwise the contents of the directory are stored in a tar archive with
+/// the same name and a `.tar` extension.
+///
+/// The storage overlay of a realm only exists while the realm is running, so overlay
+/// state is only saved when a snapshot is taken of a running realm. When a snapshot with
+/// overlay state is restored, the overlay is staged and becomes the overlay of the realm
+/// the next time it is started.
+///
+#[derive(Clone)]
+pub struct RealmSnapshot {
+    label: String,
+    path: PathBuf,
+    timestamp: i64,
+}
+
+impl RealmSnapshot {
+
+    fn load(path: PathBuf) -> Option<Self> {
+        let label = path.file_name()?.to_str()?.to_string();
+        if !Self::is_valid_label(&label) {
+            return None;
+        }
+        let timestamp = path.metadata().map(|meta| meta.mtime()).ok()?;
+        Some(RealmSnapshot { label, path, timestamp })
+    }
+
+    /// Return `true` if `label` is a valid name for a snapshot.
+    ///
+    /// Valid labels follow the same rules as RealmFS names.
+    pub fn is_valid_label(label: &str) -> bool {
+        util::is_valid_name(label, MAX_LABEL_LEN)
+    }
+
+    pub fn label(&self) -> &str {
+        &self.label
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Time this snapshot was created in seconds since the epoch.
+    pub fn timestamp(&self) -> i64 {
+        self.timestamp
+    }
+
+    /// Return `true` if this snapshot contains saved overlay state.
+    pub fn has_overlay(&self) -> bool {
+        self.has_component("overlay")
+    }
+
+    fn has_component(&self, name: &str) -> bool {
+        self.path.join(name).exists() || self.tar_path(name).exists()
+    }
+
+    fn tar_path(&self, name: &str) -> PathBuf {
+        self.path.join(format!("{}.tar", name))
+    }
+
+    // Save directory `source` into this snapshot as component `name`
+    fn save(&self, source: &Path, name: &str) -> Result<()> {
+        if is_subvolume(source) {
+            let target = self.path.join(name);
+            Exec::new(BTRFS).quiet()
+                .run(format!("subvolume snapshot -r {} {}", source.display(), target.display()))
+                .map_err(context!("failed to snapshot btrfs subvolume {:?}", source))
+        } else {
+            cmd!(TAR, "-C {} -cpf {} .", source.display(), self.tar_path(name).display())
+                .map_err(context!("failed to archive directory {:?}", source))
+        }
+    }
+
+    // Restore component `name` of this snapshot to a new directory at `target`
+    fn restore_to(&self, name: &str, target: &Path) -> Result<()> {
+        let subvolume = self.path.join(name);
+        if subvolume.exists() {
+            Exec::new(BTRFS).quiet()
+                .run(format!("subvolume snapshot {} {}", subvolume.display(), target.display()))
+                .map_err(context!("failed to restore btrfs snapshot {:?}", subvolume))
+        } else {
+            let archive = self.tar_path(name);
+            util::create_dir(target)?;
+            cmd!(TAR, "-C {} -xpf {}", target.display(), archive.display())
+                .map_err(context!("failed to extract archive {:?}", archive))
+        }
+    }
+
+    fn remove(&self) -> Result<()> {
+        for name in &["home", "overlay"] {
+            let subvolume = self.path.join(name);
+            if subvolume.exists() {
+                remove_directory(&subvolume)?;
+            }
+        }
+        fs::remove_dir_all(&self.path)
+            .map_err(context!("failed to remove snapshot directory {:?}", self.path))
+    }
+}
+
+// A btrfs subvolume always has inode number 256
+fn is_subvolume(path: &Path) -> bool {
+    path.metadata().map(|meta| meta.ino() == 256).unwrap_or(false) &&
+        Exec::new(BTRFS).quiet()
+            .run_ok(format!("subvolume show {}", path.display()))
+            .unwrap_or(false)
+}
+
+// Remove directory `path` which may be a btrfs subvolume
+fn remove_directory(path: &Path) -> Result<()> {
+    if is_subvolume(path) {
+        Exec::new(BTRFS).quiet()
+            .run(format!("subvolume delete {}", path.display()))
+            .map_err(context!("failed to remove btrfs subvolume {:?}", path))
+    } else {
+        fs::remove_dir_all(path)
+            .map_err(context!("failed to remove directory {:?}", path))
+    }
+}
+
+fn snapshot_path(realm: &Realm, label: &str) -> PathBuf {
+    realm.base_path_file(SNAPSHOTS_DIR).join(label)
+}
+
+/// Create a new snapshot of `realm` with name `label`.
+pub fn create(realm: &Realm, label: &str) -> Result<RealmSnapshot> {
+    if !RealmSnapshot::is_valid_label(label) {
+        bail!("Invalid snapshot label '{}'", label);
+    }
+    let path = snapshot_path(realm, label);
+    if path.exists() {
+        bail!("A snapshot named '{}' already exists for realm-{}", label, realm.name());
+    }
+    util::create_dir(&path)?;
+    let snapshot = RealmSnapshot { label: label.to_string(), path, timestamp: 0 };
+
+    info!("Creating snapshot '{}' of realm-{}", label, realm.name());
+    if let Err(err) = save_state(realm, &snapshot) {
+        if let Err(e) = snapshot.remove() {
+            warn!("Failed to remove incomplete snapshot {:?}: {}", snapshot.path(), e);
+        }
+        return Err(err);
+    }
+
+    match RealmSnapshot::load(snapshot.path) {
+        Some(snapshot) => Ok(snapshot),
+        None => bail!("Failed to load newly created snapshot '{}'", label),
+    }
+}
+
+fn save_state(realm: &Realm, snapshot: &RealmSnapshot) -> Result<()> {
+    snapshot.save(&realm.base_path_file("home"), "home")?;
+
+    let overlay = realm.base_path_file("overlay");
+    if realm.is_active() && realm.config().overlay() == OverlayType::Storage && overlay.exists() {
+        snapshot.save(&overlay, "overlay")?;
+    }
+    Ok(())
+}
+
+/// Return all snapshots of `realm` ordered from oldest to newest.
+pub fn list(realm: &Realm) -> Result<Vec<RealmSnapshot>> {
+    let dir = realm.base_path_file(SNAPSHOTS_DIR);
+    let mut v = Vec::new();
+    if !dir.exists() {
+        return Ok(v);
+    }
+    util::read_directory(&dir, |dent| {
+        if let Some(snapshot) = RealmSnapshot::load(dent.path()) {
+            v.push(snapshot);
+        }
+        Ok(())
+    })?;
+    v.sort_by(|a, b| a.timestamp.cmp(&b.timestamp).then_with(|| a.label.cmp(&b.label)));
+    Ok(v)
+}
+
+fn find(realm: &Realm, label: &str) -> Result<RealmSnapshot> {
+    if RealmSnapshot::is_valid_label(label) {
+        if let Some(snapshot) = RealmSnapshot::load(snapshot_path(realm, label)) {
+            return Ok(snapshot);
+        }
+    }
+    bail!("No snapshot named '{}' found for realm-{}", label, realm.name())
+}
+
+/// Replace the home directory of `realm` with the copy saved in the snapshot `label` and
+/// stage any saved overlay state to be used when the realm is next started.
+///
+/// The realm must not be running.
+pub fn restore(realm: &Realm, label: &str) -> Result<()> {
+    if realm.is_active() {
+        bail!("Cannot restore snapshot while realm-{} is running", realm.name());
+    }
+    let snapshot = find(realm, label)?;
+    info!("Restoring snapshot '{}' of realm-{}", label, realm.name());
+
+    // Extract the saved home directory next to the current one before replacing it
+    let home = realm.base_path_file("home");
+    let restored = realm.base_path_file("home.restore");
+    let old = realm.base_path_file("home.old");
+    for path in &[&restored, &old] {
+        if path.exists() {
+            remove_directory(path)?;
+        }
+    }
+    snapshot.restore_to("home", &restored)?;
+    if home.exists() {
+        util::rename(&home, &old)?;
+    }
+    util::rename(&restored, &home)?;
+    if old.exists() {
+        remove_directory(&old)?;
+    }
+
+    let staged = realm.base_path_file(OVERLAY_RESTORE);
+    if staged.exists() {
+        remove_directory(&staged)?;
+    }
+    if snapshot.has_overlay() {
+        snapshot.restore_to("overlay", &staged)?;
+    }
+    Ok(())
+}
+
+/// Remove snapshot `label` of `realm`.
+pub fn delete(realm: &Realm, label: &str) -> Result<()> {
+    let snapshot = find(realm, label)?;
+    info!("Removing snapshot '{}' of realm-{}", label, realm.name());
+    snapshot.remove()
+}
+
+/// Remove all snapshots stored in the realm directory `basepath`.
+///
+/// Snapshot components saved as read-only btrfs subvolumes cannot be removed along
+/// with the rest of the realm directory, so this must be called before the realm
+/// directory is deleted.
+pub(crate) fn remove_all(basepath: &Path) -> Result<()> {
+    let dir = basepath.join(SNAPSHOTS_DIR);
+    if !dir.exists() {
+        return Ok(());
+    }
+    util::read_directory(&dir, |dent| {
+        let path = dent.path();
+        if path.is_dir() {
+            let label = dent.file_name().to_string_lossy().to_string();
+            info!("Removing snapshot '{}' from {:?}", label, basepath);
+            RealmSnapshot { label, path, timestamp: 0 }.remove()?;
+        }
+        Ok(())
+    })
+}
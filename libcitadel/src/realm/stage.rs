@@ -0,0 +1,48 @@
+/// A step of starting or stopping a realm.
+///
+/// Reported to the progress callback of `RealmManager::start_realm_with_progress()`
+/// and `RealmManager::stop_realm_with_progress()` as each step begins. When starting
+/// or stopping fails the most recently reported stage is the step which failed.
+#[derive(Clone,Copy,Debug,PartialEq,Eq)]
+pub enum RealmStage {
+    StartingDependencies,
+    MountingRealmFS,
+    CreatingOverlay,
+    ConfiguringNetwork,
+    StartingUnit,
+    LinkingWaylandSocket,
+    StoppingDependents,
+    StoppingUnit,
+    RemovingRootfs,
+}
+
+impl RealmStage {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            RealmStage::StartingDependencies => "starting-dependencies",
+            RealmStage::MountingRealmFS => "mounting-realmfs",
+            RealmStage::CreatingOverlay => "creating-overlay",
+            RealmStage::ConfiguringNetwork => "configuring-network",
+            RealmStage::StartingUnit => "starting-unit",
+            RealmStage::LinkingWaylandSocket => "linking-wayland-socket",
+            RealmStage::StoppingDependents => "stopping-dependents",
+            RealmStage::StoppingUnit => "stopping-unit",
+            RealmStage::RemovingRootfs => "removing-rootfs",
+        }
+    }
+
+    /// A short description of the step suitable for display to the user.
+    pub fn description(&self) -> &'static str {
+        match self {
+            RealmStage::StartingDependencies => "Starting realms this realm depends on",
+            RealmStage::MountingRealmFS => "Mounting RealmFS image",
+            RealmStage::CreatingOverlay => "Creating rootfs overlay",
+            RealmStage::ConfiguringNetwork => "Allocating network address and installing firewall",
+            RealmStage::StartingUnit => "Starting realm service unit",
+            RealmStage::LinkingWaylandSocket => "Linking wayland socket",
+            RealmStage::StoppingDependents => "Stopping realms which depend on this realm",
+            RealmStage::StoppingUnit => "Stopping realm service unit",
+            RealmStage::RemovingRootfs => "Removing rootfs overlay and mounts",
+        }
+    }
+}
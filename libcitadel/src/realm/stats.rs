@@ -0,0 +1,204 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+
+use crate::{Realm, RealmOverlay, Result};
+
+const CGROUP_BASE_PATH: &str = "/sys/fs/cgroup/system.slice";
+
+/// Resource usage of a realm.
+///
+/// The CPU, memory, IO and task values are read from the cgroup accounting of the
+/// `realm-NAME.service` unit and are zero when the realm is not running. Disk usage
+/// of the RealmFS image and of the rootfs overlay is only read by `RealmStats::load()`
+/// since walking the overlay can be slow. Use `refresh_usage()` to update a sample.
+#[derive(Clone,Debug)]
+pub struct RealmStats {
+    timestamp: Instant,
+    cpu_usage_usec: u64,
+    memory_current: u64,
+    memory_peak: u64,
+    io_read_bytes: u64,
+    io_write_bytes: u64,
+    tasks_current: u64,
+    realmfs_size: u64,
+    realmfs_used: u64,
+    overlay_used: u64,
+}
+
+impl RealmStats {
+
+    fn new() -> Self {
+        RealmStats {
+            timestamp: Instant::now(),
+            cpu_usage_usec: 0,
+            memory_current: 0,
+            memory_peak: 0,
+            io_read_bytes: 0,
+            io_write_bytes: 0,
+            tasks_current: 0,
+            realmfs_size: 0,
+            realmfs_used: 0,
+            overlay_used: 0,
+        }
+    }
+
+    /// Read the cgroup accounting and the disk usage of `realm`.
+    pub fn load(realm: &Realm) -> Result<Self> {
+        let mut stats = Self::load_usage(realm)?;
+        stats.load_disk_usage(realm)?;
+        Ok(stats)
+    }
+
+    /// Read only the cgroup accounting of `realm`.
+    pub fn load_usage(realm: &Realm) -> Result<Self> {
+        let mut stats = Self::new();
+        if realm.is_active() {
+            let cgroup = Self::cgroup_path(realm);
+            if cgroup.exists() {
+                stats.load_cgroup(&cgroup)?;
+            }
+        }
+        Ok(stats)
+    }
+
+    /// Read the cgroup accounting of `realm` again, keeping the disk usage of this sample.
+    pub fn refresh_usage(&self, realm: &Realm) -> Result<Self> {
+        let mut stats = Self::load_usage(realm)?;
+        stats.realmfs_size = self.realmfs_size;
+        stats.realmfs_used = self.realmfs_used;
+        stats.overlay_used = self.overlay_used;
+        Ok(stats)
+    }
+
+    fn cgroup_path(realm: &Realm) -> PathBuf {
+        Path::new(CGROUP_BASE_PATH).join(format!("realm-{}.service", realm.name()))
+    }
+
+    fn load_cgroup(&mut self, cgroup: &Path) -> Result<()> {
+        let cpu_stat = read_cgroup_file(cgroup, "cpu.stat")?;
+        self.cpu_usage_usec = keyed_value(&cpu_stat, "usage_usec").unwrap_or(0);
+        self.memory_current = read_cgroup_value(cgroup, "memory.current")?;
+        // memory.peak is only available on Linux 5.19 and later
+        self.memory_peak = read_cgroup_value(cgroup, "memory.peak").unwrap_or(0);
+        // pids.current and io.stat are missing if the pids or io controllers are not enabled
+        self.tasks_current = read_cgroup_value(cgroup, "pids.current").unwrap_or(0);
+
+        // Each line of io.stat holds the counters of one block device:
+        //   MAJ:MIN rbytes=N wbytes=N rios=N wios=N dbytes=N dios=N
+        let io_stat = read_cgroup_file(cgroup, "io.stat").unwrap_or_default();
+        for line in io_stat.lines() {
+            for field in line.split_whitespace().skip(1) {
+                match field.split_once('=') {
+                    Some(("rbytes", n)) => self.io_read_bytes += n.parse::<u64>().unwrap_or(0),
+                    Some(("wbytes", n)) => self.io_write_bytes += n.parse::<u64>().unwrap_or(0),
+                    _ => {},
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn load_disk_usage(&mut self, realm: &Realm) -> Result<()> {
+        let config = realm.config();
+        let realmfs = realm.try_manager()
+            .and_then(|manager| manager.realmfs_by_name(config.realmfs()));
+        if let Some(realmfs) = realmfs {
+            let nblocks = realmfs.metainfo().nblocks();
+            let free = realmfs.free_size_blocks()?;
+            self.realmfs_size = nblocks as u64 * 4096;
+            self.realmfs_used = nblocks.saturating_sub(free) as u64 * 4096;
+        }
+        if let Some(overlay) = RealmOverlay::for_realm(realm) {
+            self.overlay_used = overlay.disk_usage()?;
+        }
+        Ok(())
+    }
+
+    /// Total CPU time used by processes of the realm in microseconds.
+    pub fn cpu_usage_usec(&self) -> u64 {
+        self.cpu_usage_usec
+    }
+
+    /// Percentage of one CPU used by the realm between an earlier sample `previous` and
+    /// this one.
+    pub fn cpu_percent(&self, previous: &RealmStats) -> f64 {
+        let elapsed = self.timestamp.saturating_duration_since(previous.timestamp);
+        if elapsed == Duration::ZERO {
+            return 0.0;
+        }
+        let used = self.cpu_usage_usec.saturating_sub(previous.cpu_usage_usec);
+        used as f64 * 100.0 / elapsed.as_micros() as f64
+    }
+
+    /// Memory currently used by the realm in bytes.
+    pub fn memory_current(&self) -> u64 {
+        self.memory_current
+    }
+
+    /// Highest memory use of the realm in bytes since it was started, or zero if not
+    /// supported by the kernel.
+    pub fn memory_peak(&self) -> u64 {
+        self.memory_peak
+    }
+
+    /// Bytes read from block devices by the realm.
+    pub fn io_read_bytes(&self) -> u64 {
+        self.io_read_bytes
+    }
+
+    /// Bytes written to block devices by the realm.
+    pub fn io_write_bytes(&self) -> u64 {
+        self.io_write_bytes
+    }
+
+    /// Number of processes and threads running in the realm.
+    pub fn tasks_current(&self) -> u64 {
+        self.tasks_current
+    }
+
+    /// Size in bytes of the filesystem in the RealmFS image of the realm.
+    pub fn realmfs_size(&self) -> u64 {
+        self.realmfs_size
+    }
+
+    /// Bytes used in the filesystem of the RealmFS image of the realm.
+    pub fn realmfs_used(&self) -> u64 {
+        self.realmfs_used
+    }
+
+    /// Bytes written to the rootfs overlay of the realm, or zero if the realm has
+    /// no overlay.
+    pub fn overlay_used(&self) -> u64 {
+        self.overlay_used
+    }
+}
+
+fn read_cgroup_file(cgroup: &Path, name: &str) -> Result<String> {
+    let path = cgroup.join(name);
+    fs::read_to_string(&path)
+        .map_err(context!("failed to read cgroup file {:?}", path))
+}
+
+fn read_cgroup_value(cgroup: &Path, name: &str) -> Result<u64> {
+    let s = read_cgroup_file(cgroup, name)?;
+    match s.trim() {
+        "max" => Ok(u64::MAX),
+        s => s.parse().map_err(|_| format_err!("cgroup file {} contains invalid value '{}'", name, s)),
+    }
+}
+
+fn keyed_value(content: &str, key: &str) -> Option<u64> {
+    content.lines()
+        .filter_map(|line| line.split_once(' '))
+        .find(|(k, _)| *k == key)
+        .and_then(|(_, v)| v.trim().parse().ok())
+}
+
+#[test]
+fn test_keyed_value() {
+    let cpu_stat = "usage_usec 1234567\nuser_usec 1000000\nsystem_usec 234567\n";
+    assert_eq!(keyed_value(cpu_stat, "usage_usec"), Some(1234567));
+    assert_eq!(keyed_value(cpu_stat, "system_usec"), Some(234567));
+    assert_eq!(keyed_value(cpu_stat, "nr_periods"), None);
+}
@@ -1,10 +1,12 @@
 use std::env;
+use std::net::IpAddr;
 use std::path::Path;
 use std::process::{Command,Stdio};
 use std::sync::Mutex;
 
-use crate::{Result,Realm};
+use crate::{Result,Realm,RealmStage,ResourceLimits};
 use crate::realm::{
+    firewall::{RealmFirewall, ZoneAddresses},
     launcher::RealmLauncher,
     network::NetworkConfig
 };
@@ -23,10 +25,21 @@ impl Systemd {
         Systemd { network }
     }
 
-    pub fn start_realm(&self, realm: &Realm, rootfs: &Path) -> Result<()> {
+    pub fn start_realm(&self, realm: &Realm, rootfs: &Path, progress: &dyn Fn(RealmStage)) -> Result<()> {
+        progress(RealmStage::ConfiguringNetwork);
         let mut lock = self.network.lock().unwrap();
         let mut launcher = RealmLauncher::new(realm);
+        let config = realm.config();
+        if config.network() && !config.has_netns() {
+            lock.write_networkd_config(config.network_zone())?;
+        }
         launcher.write_launch_config_files(rootfs, &mut lock)?;
+        if let Err(err) = Self::install_firewall(realm, &lock) {
+            launcher.remove_launch_config_files()?;
+            lock.free_allocation_for(realm.config().network_zone(), realm.name())?;
+            return Err(err);
+        }
+        progress(RealmStage::StartingUnit);
         self.systemctl_start(&launcher.realm_service_name())?;
         if realm.config().ephemeral_home() {
             self.setup_ephemeral_home(realm)?;
@@ -71,12 +84,123 @@ impl Systemd {
         let launcher = RealmLauncher::new(realm);
         self.systemctl_stop(&launcher.realm_service_name())?;
         launcher.remove_launch_config_files()?;
+        RealmFirewall::remove(realm);
 
         let mut network = self.network.lock().unwrap();
         network.free_allocation_for(realm.config().network_zone(), realm.name())?;
         Ok(())
     }
 
+    /// Remove network address allocations for which `is_stale` returns `true`.
+    pub fn remove_stale_allocations<F>(&self, is_stale: F) -> Result<()>
+        where F: Fn(&str) -> bool
+    {
+        self.network.lock().unwrap().remove_stale_allocations(is_stale)
+    }
+
+    /// Generate the firewall rules for `realm` from the current network allocations
+    /// or return `None` if the realm has no firewall policy.
+    ///
+    /// If `realm` is not running, the rules are generated for the address it would be
+    /// allocated if it were started now.
+    pub fn realm_firewall(&self, realm: &Realm) -> Result<Option<RealmFirewall>> {
+        let network = self.network.lock().unwrap();
+        Self::create_firewall(realm, &network)
+    }
+
+    /// Regenerate and install the firewall rules for running `realm`.
+    pub fn refresh_firewall(&self, realm: &Realm) -> Result<()> {
+        let network = self.network.lock().unwrap();
+        Self::install_firewall(realm, &network)
+    }
+
+    fn install_firewall(realm: &Realm, network: &NetworkConfig) -> Result<()> {
+        match Self::create_firewall(realm, network)? {
+            Some(firewall) => firewall.install(realm),
+            None => {
+                RealmFirewall::remove(realm);
+                Ok(())
+            },
+        }
+    }
+
+    fn create_firewall(realm: &Realm, network: &NetworkConfig) -> Result<Option<RealmFirewall>> {
+        let config = realm.config();
+        let policy = match config.firewall() {
+            Some(policy) if config.network() && !config.has_netns() => policy,
+            _ => return Ok(None),
+        };
+        let zone = config.network_zone();
+        let allowed = policy.allow_realms().unwrap_or_default();
+
+        let address = if realm.is_active() {
+            network.allocated_address(zone, realm.name())
+        } else {
+            network.expected_address(zone, realm.name(), config.reserved_ip())
+        };
+        let address = match address {
+            Some(address) => address,
+            None => bail!("realm-{} has no address allocated on network zone '{}'", realm.name(), zone),
+        };
+        let gateway = network.gateway(zone)?;
+        let mut zones = vec![ZoneAddresses {
+            address: IpAddr::V4(address),
+            network: network.network(zone)?,
+            gateway: gateway.parse().map_err(|_| format_err!("Failed to parse gateway address ({})", gateway))?,
+            allowed_realms: allowed.iter()
+                .flat_map(|name| network.allocated_address(zone, name))
+                .map(IpAddr::V4)
+                .collect(),
+        }];
+
+        if let (Some(address), Some(network6), Some(gateway)) = (network.address6(zone, address), network.network6(zone), network.gateway6(zone)) {
+            zones.push(ZoneAddresses {
+                address: IpAddr::V6(address),
+                network: network6,
+                gateway: IpAddr::V6(gateway),
+                allowed_realms: allowed.iter()
+                    .flat_map(|name| network.allocated_address6(zone, name))
+                    .map(IpAddr::V6)
+                    .collect(),
+            });
+        }
+        Ok(Some(RealmFirewall::new(realm.name(), policy, zones)))
+    }
+
+    /// Update the cgroup limits of the running service unit of `realm` to match its
+    /// current configuration. The change is not persistent and the unit file written
+    /// at the next start carries the same limits.
+    pub fn apply_resource_limits(&self, realm: &Realm) -> Result<()> {
+        let launcher = RealmLauncher::new(realm);
+        let properties = ResourceLimits::from_config(&realm.config()).runtime_properties();
+        let ok = Command::new(SYSTEMCTL_PATH)
+            .args(["set-property", "--runtime", launcher.realm_service_name()])
+            .args(&properties)
+            .status()
+            .map(|status| status.success())
+            .map_err(context!("failed to execute {}", SYSTEMCTL_PATH))?;
+        if !ok {
+            bail!("failed to set resource limits on {}", launcher.realm_service_name());
+        }
+        Ok(())
+    }
+
+    /// If the service unit of `realm` is no longer running but was not stopped with
+    /// `stop_realm()`, return the result systemd recorded for the unit, which is
+    /// `success` if the realm was shut down cleanly.
+    pub fn unexpected_stop_result(realm: &Realm) -> Result<Option<String>> {
+        let launcher = RealmLauncher::new(realm);
+        if !launcher.has_launch_config_files() || Self::is_active(realm)? {
+            return Ok(None);
+        }
+        let output = Command::new(SYSTEMCTL_PATH)
+            .args(["show", "--property=Result", "--value", launcher.realm_service_name()])
+            .output()
+            .map_err(context!("failed to execute {}", SYSTEMCTL_PATH))?;
+        let result = String::from_utf8_lossy(&output.stdout).trim().to_string();
+        Ok(Some(result))
+    }
+
     fn systemctl_start(&self, name: &str) -> Result<bool> {
         self.run_systemctl("start", name)
     }
@@ -147,6 +271,23 @@ impl Systemd {
     }
 
     pub fn machinectl_shell<S: AsRef<str>>(realm: &Realm, args: &[S], user: &str, launcher: bool, quiet: bool) -> Result<()> {
+        Self::machinectl_shell_command(realm, args, user, launcher, quiet)
+            .status()
+            .map_err(context!("failed to execute {}", MACHINECTL_PATH))?;
+        Ok(())
+    }
+
+    /// Run a command in `realm` like `machinectl_shell()` with output discarded and
+    /// return `true` if the command exited successfully.
+    pub fn machinectl_shell_succeeds<S: AsRef<str>>(realm: &Realm, args: &[S], user: &str) -> Result<bool> {
+        let ok = Self::machinectl_shell_command(realm, args, user, false, true)
+            .status()
+            .map(|status| status.success())
+            .map_err(context!("failed to execute {}", MACHINECTL_PATH))?;
+        Ok(ok)
+    }
+
+    fn machinectl_shell_command<S: AsRef<str>>(realm: &Realm, args: &[S], user: &str, launcher: bool, quiet: bool) -> Command {
         let mut cmd = Command::new(MACHINECTL_PATH);
         cmd.arg("--quiet");
 
@@ -177,8 +318,6 @@ impl Systemd {
         for arg in args {
             cmd.arg(arg.as_ref());
         }
-
-        cmd.status().map_err(context!("failed to execute {}", MACHINECTL_PATH))?;
-        Ok(())
+        cmd
     }
 }
@@ -0,0 +1,233 @@
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use crate::{ImageHeader, MetaInfo, RealmFS, Result, GLOBAL_CONFIG, util};
+
+///
+/// A previous version of a RealmFS image which was saved when the image was updated.
+///
+/// Generations are stored in the same directory as the current image file and are
+/// named by appending the generation number to the image filename. Generation 0 is
+/// the most recent:
+///
+///```text
+///     /storage/realms/realmfs-images/main-realmfs.img.0
+///     /storage/realms/realmfs-images/main-realmfs.img.1
+///```
+///
+/// The number of generations which are kept is configured with the `realmfs-generations`
+/// option in the global realm configuration file.
+///
+pub struct RealmFSGeneration {
+    index: usize,
+    path: PathBuf,
+    header: ImageHeader,
+}
+
+impl RealmFSGeneration {
+
+    fn load(index: usize, path: PathBuf) -> Result<Self> {
+        let header = ImageHeader::from_file(&path)?;
+        if !header.is_magic_valid() {
+            bail!("RealmFS generation file {} does not have a valid header", path.display());
+        }
+        Ok(RealmFSGeneration { index, path, header })
+    }
+
+    /// Generation number of this image. Lower numbers are more recent.
+    pub fn index(&self) -> usize {
+        self.index
+    }
+
+    /// Return the `Path` to the image file of this generation.
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    pub fn header(&self) -> &ImageHeader {
+        &self.header
+    }
+
+    pub fn metainfo(&self) -> Arc<MetaInfo> {
+        self.header.metainfo()
+    }
+
+    /// Compare the metainfo of this generation with the metainfo of `header` and
+    /// return a list of `(field, ours, theirs)` for each field which differs.
+    pub fn diff_metainfo(&self, header: &ImageHeader) -> Vec<(String, Option<String>, Option<String>)> {
+        let ours = metainfo_fields(&self.header);
+        let theirs = metainfo_fields(header);
+
+        let mut keys = ours.keys().chain(theirs.keys()).cloned().collect::<Vec<_>>();
+        keys.sort();
+        keys.dedup();
+
+        keys.into_iter()
+            .filter(|k| ours.get(k) != theirs.get(k))
+            .map(|k| {
+                let a = ours.get(&k).cloned();
+                let b = theirs.get(&k).cloned();
+                (k, a, b)
+            })
+            .collect()
+    }
+}
+
+// Parse metainfo bytes of `header` into a sorted map of field name to value
+fn metainfo_fields(header: &ImageHeader) -> BTreeMap<String, String> {
+    let bytes = header.metainfo_bytes();
+    let table = match toml::from_slice::<toml::value::Table>(&bytes) {
+        Ok(table) => table,
+        Err(err) => {
+            warn!("Failed to parse image metainfo: {}", err);
+            return BTreeMap::new();
+        }
+    };
+    table.into_iter()
+        .map(|(k,v)| match v {
+            toml::Value::String(s) => (k, s),
+            v => (k, v.to_string()),
+        })
+        .collect()
+}
+
+/// Maximum number of generations to keep for each RealmFS image.
+pub fn max_generations() -> usize {
+    GLOBAL_CONFIG.realmfs_generations()
+}
+
+fn generation_path(realmfs: &RealmFS, index: usize) -> PathBuf {
+    realmfs.path_with_filename(format!("{}-realmfs.img.{}", realmfs.name(), index))
+}
+
+// Scan the image directory for generation files belonging to `realmfs` and return the
+// path for each generation number found.
+fn generation_paths(realmfs: &RealmFS) -> Result<BTreeMap<usize, PathBuf>> {
+    let prefix = format!("{}-realmfs.img.", realmfs.name());
+    let dir = realmfs.path().parent().unwrap_or_else(|| Path::new(RealmFS::BASE_PATH));
+    let mut paths = BTreeMap::new();
+    util::read_directory(dir, |dent| {
+        if let Ok(filename) = dent.file_name().into_string() {
+            if let Some(n) = filename.strip_prefix(&prefix).and_then(|s| s.parse::<usize>().ok()) {
+                paths.insert(n, dent.path());
+            }
+        }
+        Ok(())
+    })?;
+    Ok(paths)
+}
+
+/// Return all saved generations of `realmfs` ordered from most recent to oldest.
+pub fn list(realmfs: &RealmFS) -> Result<Vec<RealmFSGeneration>> {
+    let mut v = Vec::new();
+    for (index, path) in generation_paths(realmfs)? {
+        match RealmFSGeneration::load(index, path) {
+            Ok(generation) => v.push(generation),
+            Err(err) => warn!("Ignoring generation {} of RealmFS '{}': {}", index, realmfs.name(), err),
+        }
+    }
+    Ok(v)
+}
+
+/// Return generation number `index` of `realmfs`.
+pub fn load(realmfs: &RealmFS, index: usize) -> Result<RealmFSGeneration> {
+    let path = generation_path(realmfs, index);
+    if !path.exists() {
+        bail!("RealmFS '{}' has no generation {}", realmfs.name(), index);
+    }
+    RealmFSGeneration::load(index, path)
+}
+
+/// Remove all saved generations of `realmfs`.
+pub fn remove_all(realmfs: &RealmFS) -> Result<()> {
+    for (_, path) in generation_paths(realmfs)? {
+        info!("Removing RealmFS generation file {}", path.display());
+        util::remove_file(path)?;
+    }
+    Ok(())
+}
+
+/// Replace the image file of `realmfs` with the image at `new_image` and save the
+/// current image as generation 0, shifting older generations up by one.
+///
+/// The current image is hard linked as generation 0 before the new image is renamed over
+/// it, so at every point a complete image file exists at the path of `realmfs`.
+pub fn rotate(realmfs: &RealmFS, new_image: &Path) -> Result<()> {
+    let count = max_generations();
+    prune(realmfs, count.saturating_sub(1))?;
+
+    if count > 0 {
+        for i in (1..count).rev() {
+            let from = generation_path(realmfs, i - 1);
+            if from.exists() {
+                util::rename(&from, generation_path(realmfs, i))?;
+            }
+        }
+        util::hard_link(realmfs.path(), generation_path(realmfs, 0))?;
+    }
+
+    if let Err(err) = util::rename(new_image, realmfs.path()) {
+        if count > 0 {
+            let _ = util::remove_file(generation_path(realmfs, 0));
+        }
+        return Err(err);
+    }
+    Ok(())
+}
+
+/// Swap the image file of `realmfs` with generation `index`.
+///
+/// The current image becomes generation 0 and the generations more recent than `index`
+/// each move up by one, so rolling back to generation 0 again reverses the operation.
+///
+/// If any step fails, the files which were already moved are put back so that the
+/// current image and all generations are left as they were.
+pub fn rollback(realmfs: &RealmFS, index: usize) -> Result<()> {
+    let source = generation_path(realmfs, index);
+    let staged = realmfs.path_with_filename(format!("{}-realmfs.img.rollback", realmfs.name()));
+    util::rename(&source, &staged)?;
+
+    let mut moved = Vec::new();
+    let result = swap_generation(realmfs, index, &staged, &mut moved);
+    if result.is_err() {
+        for (from, to) in moved.iter().rev() {
+            if let Err(err) = util::rename(to, from) {
+                warn!("Failed to restore RealmFS generation file {}: {}", from.display(), err);
+            }
+        }
+        if let Err(err) = util::rename(&staged, &source) {
+            warn!("Failed to restore RealmFS generation file {}: {}", source.display(), err);
+        }
+    }
+    result
+}
+
+// Shift the generations more recent than `index` up by one, recording each rename in
+// `moved`, then save the current image as generation 0 and replace it with `staged`.
+fn swap_generation(realmfs: &RealmFS, index: usize, staged: &Path, moved: &mut Vec<(PathBuf, PathBuf)>) -> Result<()> {
+    for i in (1..=index).rev() {
+        let from = generation_path(realmfs, i - 1);
+        if from.exists() {
+            let to = generation_path(realmfs, i);
+            util::rename(&from, &to)?;
+            moved.push((from, to));
+        }
+    }
+    let saved = generation_path(realmfs, 0);
+    util::hard_link(realmfs.path(), &saved)?;
+    if let Err(err) = util::rename(staged, realmfs.path()) {
+        let _ = util::remove_file(&saved);
+        return Err(err);
+    }
+    Ok(())
+}
+
+// Remove generation files with a generation number greater or equal to `count`
+fn prune(realmfs: &RealmFS, count: usize) -> Result<()> {
+    for (_, path) in generation_paths(realmfs)?.range(count..) {
+        info!("Removing expired RealmFS generation file {}", path.display());
+        util::remove_file(path)?;
+    }
+    Ok(())
+}
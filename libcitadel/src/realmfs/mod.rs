@@ -1,9 +1,11 @@
 pub(crate) mod resizer;
 mod mountpoint;
 mod update;
+pub(crate) mod generations;
 pub(crate) mod realmfs_set;
 #[allow(clippy::module_inception)]
 mod realmfs;
 
 pub use self::realmfs::RealmFS;
 pub use self::mountpoint::Mountpoint;
+pub use self::generations::RealmFSGeneration;
@@ -5,7 +5,8 @@ use std::os::unix::fs::MetadataExt;
 use std::path::{Path,PathBuf};
 use std::sync::{Arc, Weak, RwLock};
 
-use crate::{ImageHeader, MetaInfo, Result, KeyRing, KeyPair, util, RealmManager, PublicKey, ResizeSize};
+use crate::{ImageHeader, MetaInfo, Result, KeyRing, KeyPair, util, RealmManager, RealmEvent, TrustPolicy, ResizeSize, FileLock};
+use crate::realmfs::generations::{self, RealmFSGeneration};
 use crate::realmfs::resizer::Superblock;
 use crate::realmfs::update::Update;
 use super::mountpoint::Mountpoint;
@@ -100,7 +101,7 @@ impl RealmFS {
             .expect(&format!("No manager set on realmfs {}", self.name))
     }
 
-    fn with_manager<F>(&self, f: F)
+    pub(crate) fn with_manager<F>(&self, f: F)
         where F: FnOnce(Arc<RealmManager>)
     {
         if let Some(manager) = self.manager.upgrade() {
@@ -262,7 +263,11 @@ impl RealmFS {
     /// Return `true` if deactivation occurs.
     pub fn deactivate(&self) {
         if !self.is_in_use() {
+            let was_activated = self.is_activated();
             self.mountpoint().deactivate();
+            if was_activated && !self.is_activated() {
+                self.with_manager(|m| m.send_event(RealmEvent::RealmFSDeactivated(self.clone())));
+            }
         }
     }
 
@@ -271,22 +276,64 @@ impl RealmFS {
         update.run_interactive_update(scheme)
     }
 
-    // Return the public key for verifying the signature on this image
-    fn public_key(&self) -> Result<PublicKey> {
-        let pubkey = if self.metainfo().channel() == RealmFS::USER_KEYNAME {
-            self.sealing_keys()?.public_key()
+    /// Return the saved previous generations of this image, most recent first.
+    pub fn generations(&self) -> Result<Vec<RealmFSGeneration>> {
+        generations::list(self)
+    }
+
+    /// Return saved generation number `index` of this image.
+    pub fn generation(&self, index: usize) -> Result<RealmFSGeneration> {
+        generations::load(self, index)
+    }
+
+    /// Replace the current image with saved generation number `index`. The current
+    /// image is kept as generation 0.
+    ///
+    /// Fails if any running realm is using this RealmFS.
+    pub fn rollback(&self, index: usize) -> Result<()> {
+        match self.manager.upgrade() {
+            Some(manager) => manager.rollback_realmfs(self, index),
+            None => bail!("Cannot roll back realmfs {} because no manager is set", self.name),
+        }
+    }
+
+    pub(crate) fn remove_generations(&self) -> Result<()> {
+        generations::remove_all(self)
+    }
+
+    // Called from RealmManager once the RealmFS has been deactivated.
+    pub(crate) fn restore_generation(&self, index: usize) -> Result<()> {
+        let _lock = FileLock::nonblocking_acquire(self.path().with_extension("lock"))?
+            .ok_or(format_err!("Unable to obtain file lock to roll back realmfs image: {}", self.name()))?;
+
+        let generation = self.generation(index)?;
+        let candidate = Self::load_from_path(generation.path())?;
+        if candidate.name() != self.name() {
+            bail!("RealmFS generation {} has name '{}', expected '{}'", index, candidate.name(), self.name());
+        }
+        candidate.verify_signature()?;
+
+        info!("Rolling back RealmFS '{}' to generation {}", self.name(), index);
+        generations::rollback(self, index)?;
+        self.check_stale_header(true)
+    }
+
+    // Return the trust policy for verifying the signatures on this image
+    fn trust_policy(&self) -> Result<TrustPolicy> {
+        let policy = if self.metainfo().channel() == RealmFS::USER_KEYNAME {
+            TrustPolicy::single(self.sealing_keys()?.public_key())
         } else {
-            match self.header().public_key()? {
-                Some(pubkey) => pubkey,
+            match self.header().trust_policy()? {
+                Some(policy) => policy,
                 None => bail!("No public key available for channel {}", self.metainfo().channel()),
             }
         };
-        Ok(pubkey)
+        Ok(policy)
     }
 
     pub(super) fn verify_signature(&self) -> Result<()> {
-        let pubkey = self.public_key()?;
-        if !self.header().verify_signature(pubkey) {
+        let policy = self.trust_policy()?;
+        if !policy.verify(self.header()) {
             bail!("header signature verification failed on realmfs image '{}'", self.name());
         }
         info!("header signature verified on realmfs image '{}'", self.name());
@@ -390,14 +437,18 @@ impl RealmFS {
         info!("Resizing to {} blocks", size.nblocks());
         let mut update = Update::create(self)?;
         update.grow_to(size);
-        update.resize()
+        update.resize()?;
+        self.with_manager(|m| m.send_event(RealmEvent::RealmFSResized(self.clone())));
+        Ok(())
     }
 
     pub fn resize_grow_by(&self, size: ResizeSize) -> Result<()> {
         info!("Resizing to an increase of {} blocks", size.nblocks());
         let mut update = Update::create(self)?;
         update.grow_by(size);
-        update.resize()
+        update.resize()?;
+        self.with_manager(|m| m.send_event(RealmEvent::RealmFSResized(self.clone())));
+        Ok(())
     }
 
     pub fn free_size_blocks(&self) -> Result<usize> {
@@ -413,7 +464,12 @@ impl RealmFS {
 
     /// Activate this RealmFS image if not yet activated.
     pub fn activate(&self) -> Result<()> {
-        self.mountpoint().activate(self)
+        if self.is_activated() {
+            return Ok(());
+        }
+        self.mountpoint().activate(self)?;
+        self.with_manager(|m| m.send_event(RealmEvent::RealmFSActivated(self.clone())));
+        Ok(())
     }
 
     /// Return `true` if this RealmFS is 'activated'.
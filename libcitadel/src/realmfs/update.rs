@@ -5,17 +5,15 @@ use std::process::Command;
 
 use sodiumoxide::randombytes::randombytes;
 
-use crate::{Result, RealmFS, FileLock, ImageHeader, LoopDevice, ResizeSize, util, Error};
+use crate::{Result, RealmFS, FileLock, ImageHeader, LoopDevice, ResizeSize, util, Error, EventJournal, JournalEventKind, RealmEvent};
 use crate::realm::BridgeAllocator;
+use crate::realmfs::generations;
 use crate::util::is_euid_root;
 use crate::terminal::TerminalRestorer;
 use crate::verity::Verity;
 
 const BLOCK_SIZE: usize  = 4096;
 
-// The maximum number of backup copies the rotate() method will create
-const NUM_BACKUPS: usize = 2;
-
 const E2FSCK: &str = "e2fsck";
 const RESIZE2FS: &str = "resize2fs";
 
@@ -313,8 +311,12 @@ impl <'a> Update<'a> {
         let addr = alloc.allocate_address_for(&self.name())?;
         let gw = alloc.gateway();
         self.network_allocated = true;
-        Command::new("/usr/bin/systemd-nspawn")
-            .arg(format!("--setenv=IFCONFIG_IP={}", addr))
+        let mut cmd = Command::new("/usr/bin/systemd-nspawn");
+        if let (Some(addr6), Some(gw6)) = (alloc.allocated_address6(self.name()), alloc.gateway6()) {
+            cmd.arg(format!("--setenv=IFCONFIG_IP6={}/64", addr6))
+                .arg(format!("--setenv=IFCONFIG_GW6={}", gw6));
+        }
+        cmd.arg(format!("--setenv=IFCONFIG_IP={}", addr))
             .arg(format!("--setenv=IFCONFIG_GW={}", gw))
             .arg("--quiet")
             .arg(format!("--machine={}", self.name()))
@@ -335,24 +337,20 @@ impl <'a> Update<'a> {
         self.unmount_update_image();
         self.seal()?;
         self.rotate()?;
+        self.realmfs.with_manager(|m| m.send_event(RealmEvent::RealmFSUpdated(self.realmfs.clone())));
+        let reason = "update applied, previous image saved as generation 0";
+        if let Err(e) = EventJournal::record(JournalEventKind::RealmFSUpdated, self.realmfs.name(), reason) {
+            warn!("error recording RealmFS update in event journal: {}", e);
+        }
         Ok(())
     }
 
+    // Install the sealed update copy as the current image, keeping the previous
+    // image as generation 0. The new image is verified before anything is moved.
     fn rotate(&self) -> Result<()> {
-        let backup = |n: usize|
-            Path::new(RealmFS::BASE_PATH)
-                .join(format!("{}-realmfs.img.{}", self.realmfs.name(), n));
-
-        for i in (1..NUM_BACKUPS).rev() {
-            let from = backup(i - 1);
-            if from.exists() {
-                let to = backup(i);
-                util::rename(&from, &to)?;
-            }
-        }
-        let to = backup(0);
-        util::rename(self.realmfs.path(), &to)?;
-        util::rename(self.target(), self.realmfs.path())
+        let updated = RealmFS::load_from_path(self.target())?;
+        updated.verify_signature()?;
+        generations::rotate(self.realmfs, self.target())
     }
 }
 
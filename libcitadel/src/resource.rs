@@ -1,12 +1,13 @@
 use std::fs::{File,DirEntry};
-use std::io::{self, Seek, SeekFrom};
+use std::io::{BufReader, BufWriter, Seek, SeekFrom, Write};
 use std::path::{Path, PathBuf};
 
-use crate::{Result, CommandLine, OsRelease, ImageHeader, MetaInfo, Partition, Mounts, util, LoopDevice};
+use crate::{Result, CommandLine, Compression, OsRelease, ImageHeader, ImageDelta, MetaInfo, Partition, Mounts, util, LoopDevice};
 
 use std::sync::Arc;
 use crate::UtsName;
 use crate::verity::Verity;
+use crate::manifest::ResourceManifest;
 
 const STORAGE_BASEDIR: &str = "/sysroot/storage/resources";
 const RUN_DIRECTORY: &str = "/run/citadel/images";
@@ -15,9 +16,10 @@ const RUN_DIRECTORY: &str = "/run/citadel/images";
 ///
 /// Resource image files are files containing a disk image that can be
 /// loop mounted, optionally secured with dm-verity. The root directory
-/// of the mounted image may contain a file called `manifest` which
-/// contains a list of bind mounts to perform from the mounted tree to
-/// the system rootfs.
+/// of the mounted image may contain a file called `manifest.toml` (see
+/// `ResourceManifest`) or, in older images, a file called `manifest`
+/// which contains a list of bind mounts to perform from the mounted
+/// tree to the system rootfs.
 ///
 /// Various kernel command line options control how the resource file is
 /// searched for and how it is mounted.
@@ -134,35 +136,109 @@ impl ResourceImage {
         self.header.has_flag(ImageHeader::FLAG_DATA_COMPRESSED)
     }
 
+    /// Return the algorithm the image data is compressed with or `None` if the image
+    /// is not compressed.
+    pub fn compression(&self) -> Option<Compression> {
+        Compression::from_header(&self.header)
+    }
+
     pub fn has_verity_hashtree(&self) -> bool {
         self.header.has_flag(ImageHeader::FLAG_HASH_TREE)
     }
 
-    pub fn decompress(&self, early_remove: bool) -> Result<()> {
-        if !self.is_compressed() {
-            return Ok(())
+    pub fn is_delta(&self) -> bool {
+        self.header.has_flag(ImageHeader::FLAG_DELTA)
+    }
+
+    /// Reconstruct the full image described by this delta image by applying the delta
+    /// data to the image data found in `base` starting at byte offset `base_offset`.
+    ///
+    /// The full image is written to a new file at `target` with the same header as this
+    /// image, minus `FLAG_DELTA`. The caller is responsible for verifying the shasum and
+    /// dm-verity root of the resulting image.
+    pub fn apply_delta(&self, base: &Path, base_offset: u64, target: &Path) -> Result<ResourceImage> {
+        if !self.is_delta() {
+            bail!("image file {} is not a delta image", self.path().display());
+        }
+        if self.is_compressed() {
+            self.decompress(false)?;
+        }
+        info!("applying delta image {} to {}", self.path().display(), base.display());
+
+        let mut delta = BufReader::new(File::open(self.path())
+            .map_err(context!("error opening image file {:?}", self.path()))?);
+        delta.seek(SeekFrom::Start(4096))
+            .map_err(context!("error seeking to offset 4096 in image file {:?}", self.path()))?;
+
+        let mut base = File::open(base)
+            .map_err(context!("error opening delta base {:?}", base))?;
+
+        let header = ImageHeader::from_file(self.path())?;
+        header.clear_flag(ImageHeader::FLAG_DELTA);
+        header.clear_flag(ImageHeader::FLAG_HASH_TREE);
+
+        let mut out = BufWriter::new(File::create(target)
+            .map_err(context!("error creating image file {:?}", target))?);
+        header.write_header(&mut out)
+            .map_err(context!("error writing header to image file {:?}", target))?;
+
+        let nblocks = ImageDelta::apply(&mut base, base_offset, &mut delta, &mut out)?;
+        if nblocks != self.metainfo().nblocks() {
+            bail!("delta image produced {} blocks but metainfo nblocks is {}", nblocks, self.metainfo().nblocks());
         }
-        info!("decompressing image file {}", self.path().display());
+        out.flush()
+            .map_err(context!("error writing image file {:?}", target))?;
+        drop(out);
+
+        Self::from_header(header, target)
+    }
+
+    pub fn decompress(&self, early_remove: bool) -> Result<()> {
+        self.decompress_with_progress(early_remove, &|_,_| {})
+    }
+
+    /// Decompress the image data in a single pass while reading it from the image file.
+    ///
+    /// If `early_remove` is `true` the compressed image file is removed as soon as it
+    /// has been opened and the decompressed image is written directly to the same path.
+    /// Otherwise the decompressed image is written to a temporary file which then
+    /// replaces the compressed image.
+    ///
+    /// `progress` is called periodically with the number of bytes of compressed data
+    /// read so far and the total size of the compressed data.
+    pub fn decompress_with_progress(&self, early_remove: bool, progress: &dyn Fn(u64, u64)) -> Result<()> {
+        let compression = match self.compression() {
+            Some(compression) => compression,
+            None => return Ok(()),
+        };
+        info!("decompressing image file {} ({})", self.path().display(), compression.as_str());
         let mut reader = File::open(self.path())
             .map_err(context!("error opening image file {:?}", self.path()))?;
+        let total = reader.metadata()
+            .map_err(context!("failed to read metadata from image file {:?}", self.path()))?
+            .len().saturating_sub(4096);
         reader.seek(SeekFrom::Start(4096))
             .map_err(context!("error seeking to offset 4096 in image file {:?}", self.path()))?;
 
-        let xzfile = self.path.with_extension("tmp.xz");
-        let mut out = File::create(&xzfile)
-            .map_err(context!("error creating temporary file {:?}", xzfile))?;
-        io::copy(&mut reader, &mut out)
-            .map_err(context!("error copying image file {:?} to temporary file", self.path()))?;
-
-        if early_remove {
+        let target = if early_remove {
             util::remove_file(self.path())?;
-        }
+            self.path.clone()
+        } else {
+            self.path.with_extension("tmp")
+        };
 
-        util::xz_decompress(xzfile)?;
-        let tmpfile = self.path.with_extension("tmp");
-        util::rename(&tmpfile, self.path())?;
+        let mut out = BufWriter::new(File::create(&target)
+            .map_err(context!("error creating image file {:?}", target))?);
+        compression.decompress(BufReader::new(reader), &mut out, total, progress)?;
+        out.flush()
+            .map_err(context!("error writing image file {:?}", target))?;
+        drop(out);
+
+        if !early_remove {
+            util::rename(&target, self.path())?;
+        }
 
-        self.header.clear_flag(ImageHeader::FLAG_DATA_COMPRESSED);
+        Compression::clear_header_flags(&self.header);
         self.header.write_header_to(self.path())
     }
 
@@ -171,6 +247,10 @@ impl ResourceImage {
             bail!("cannot write to partition, image type is not rootfs");
         }
 
+        if self.is_delta() {
+            bail!("cannot write delta image to partition, delta must be applied first");
+        }
+
         if !self.has_verity_hashtree() {
             self.generate_verity_hashtree()?;
         }
@@ -199,10 +279,11 @@ impl ResourceImage {
 
     pub fn setup_verity_device(&self) -> Result<String> {
         if !CommandLine::nosignatures() {
-            match self.header.public_key()? {
-                Some(pubkey) => {
-                    if !self.header.verify_signature(pubkey) {
-                        bail!("header signature verification failed");
+            match self.header.trust_policy()? {
+                Some(policy) => {
+                    let count = policy.count_valid_signatures(&self.header);
+                    if count < policy.threshold() {
+                        bail!("header signature verification failed ({} of {} required signatures valid)", count, policy.threshold());
                     }
                     info!("Image header signature is valid");
                 }
@@ -288,6 +369,10 @@ impl ResourceImage {
     // Read and process a manifest file in the root directory of a mounted resource image.
     fn process_manifest_file(&self) -> Result<()> {
         info!("Processing manifest file for {}", self.path.display());
+        let manifest = self.mount_path().join("manifest.toml");
+        if manifest.exists() {
+            return ResourceManifest::load(&manifest)?.apply(&self.mount_path());
+        }
         let manifest = self.mount_path().join("manifest");
         if !manifest.exists() {
             warn!("No manifest file found for resource image: {}", self.path.display());
@@ -0,0 +1,135 @@
+use crate::{Result, CommandLine, OsRelease, ImageHeader, PublicKey, devkeys};
+
+///
+/// Policy describing which signatures an image header must carry to be trusted
+/// for a channel.
+///
+/// A channel lists one or more public keys and a threshold. An image is trusted if
+/// at least `threshold` of the listed keys have a valid signature in the image header,
+/// either as the primary signature or in the signature block.
+///
+/// Listing both an old and a new key with a threshold of 1 allows channel signing keys
+/// to be rotated. Images signed during the transition carry signatures from both keys
+/// so that systems which only know the old key continue to accept them.
+///
+/// Channel keys are configured in one of these places:
+///
+///   * /etc/os-release variables `CITADEL_IMAGE_PUBKEY` and `CITADEL_IMAGE_PUBKEYS` (a list
+///     of hex encoded keys separated by spaces or commas) for the channel `CITADEL_CHANNEL`.
+///     The threshold is set with `CITADEL_IMAGE_SIG_THRESHOLD`.
+///
+///   * kernel command line `citadel.channel=name:[hex key],[hex key]` and the threshold
+///     with `citadel.sig-threshold=N`
+///
+/// The threshold is 1 if not configured.
+///
+#[derive(Clone)]
+pub struct TrustPolicy {
+    keys: Vec<PublicKey>,
+    threshold: usize,
+}
+
+impl TrustPolicy {
+
+    pub fn new(keys: Vec<PublicKey>, threshold: usize) -> Result<Self> {
+        if keys.is_empty() {
+            bail!("trust policy must contain at least one public key");
+        }
+        if threshold == 0 || threshold > keys.len() {
+            bail!("trust policy threshold {} is invalid for {} keys", threshold, keys.len());
+        }
+        Ok(TrustPolicy { keys, threshold })
+    }
+
+    /// Create a policy which requires a valid signature from a single key.
+    pub fn single(key: PublicKey) -> Self {
+        TrustPolicy { keys: vec![key], threshold: 1 }
+    }
+
+    /// Return the trust policy configured for `channel` or `None` if no public
+    /// keys are available for the channel.
+    pub fn for_channel(channel: &str) -> Result<Option<Self>> {
+        if channel == "dev" {
+            return Ok(Some(Self::single(devkeys().public_key())));
+        }
+
+        // Look in /etc/os-release
+        if Some(channel) == OsRelease::citadel_channel() {
+            let hexkeys = OsRelease::citadel_image_pubkey().into_iter()
+                .chain(OsRelease::citadel_image_pubkeys().map(split_keys).unwrap_or_default())
+                .collect::<Vec<_>>();
+            if !hexkeys.is_empty() {
+                let threshold = OsRelease::citadel_image_sig_threshold().unwrap_or(1);
+                return Self::from_hex_keys(&hexkeys, threshold).map(Some);
+            }
+        }
+
+        // Does kernel command line have citadel.channel=name:[hex encoded pubkey],...
+        if Some(channel) == CommandLine::channel_name() {
+            if let Some(hex) = CommandLine::channel_pubkey() {
+                let threshold = CommandLine::sig_threshold().unwrap_or(1);
+                return Self::from_hex_keys(&split_keys(hex), threshold).map(Some);
+            }
+        }
+
+        Ok(None)
+    }
+
+    fn from_hex_keys(hexkeys: &[&str], threshold: usize) -> Result<Self> {
+        let mut keys: Vec<PublicKey> = Vec::new();
+        for hex in hexkeys {
+            let key = PublicKey::from_hex(hex)?;
+            if !keys.iter().any(|k| k.key_id() == key.key_id()) {
+                keys.push(key);
+            }
+        }
+        Self::new(keys, threshold)
+    }
+
+    pub fn keys(&self) -> &[PublicKey] {
+        &self.keys
+    }
+
+    pub fn threshold(&self) -> usize {
+        self.threshold
+    }
+
+    /// Return the number of keys in this policy which have a valid signature in `header`.
+    pub fn count_valid_signatures(&self, header: &ImageHeader) -> usize {
+        self.keys.iter()
+            .filter(|key| header.has_valid_signature_from(key))
+            .count()
+    }
+
+    /// Return `true` if `header` carries valid signatures from at least `threshold` keys.
+    pub fn verify(&self, header: &ImageHeader) -> bool {
+        self.count_valid_signatures(header) >= self.threshold
+    }
+}
+
+fn split_keys(s: &str) -> Vec<&str> {
+    s.split(|c: char| c == ',' || c.is_whitespace())
+        .filter(|s| !s.is_empty())
+        .collect()
+}
+
+#[test]
+fn test_threshold_policy() {
+    use crate::KeyPair;
+    let k1 = KeyPair::from_hex(&"11".repeat(32)).unwrap();
+    let k2 = KeyPair::from_hex(&"22".repeat(32)).unwrap();
+    let k3 = KeyPair::from_hex(&"33".repeat(32)).unwrap();
+
+    let header = ImageHeader::new();
+    let metainfo = b"image-type = \"rootfs\"\nchannel = \"test\"\n";
+    header.set_metainfo_bytes(metainfo).unwrap();
+    header.add_signature(&k1.public_key(), k1.sign(metainfo).to_bytes()).unwrap();
+    header.add_signature(&k2.public_key(), k2.sign(metainfo).to_bytes()).unwrap();
+    assert_eq!(header.signature_block().len(), 2);
+
+    let keys = vec![k1.public_key(), k2.public_key(), k3.public_key()];
+    assert!(TrustPolicy::new(keys.clone(), 2).unwrap().verify(&header));
+    assert!(!TrustPolicy::new(keys, 3).unwrap().verify(&header));
+    assert!(TrustPolicy::single(k2.public_key()).verify(&header));
+    assert!(!TrustPolicy::single(k3.public_key()).verify(&header));
+}
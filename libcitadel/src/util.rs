@@ -128,17 +128,6 @@ where
     Ok(String::from_utf8(output.stdout).unwrap().trim().to_owned())
 }
 
-pub fn xz_compress<P: AsRef<Path>>(path: P) -> Result<()> {
-    let path = path.as_ref();
-    cmd!("/usr/bin/xz", "-T0 {}", path.display()).map_err(context!("failed to compress {:?}", path))
-}
-
-pub fn xz_decompress<P: AsRef<Path>>(path: P) -> Result<()> {
-    let path = path.as_ref();
-    cmd!("/usr/bin/xz", "-d {}", path.display())
-        .map_err(context!("failed to decompress {:?}", path))
-}
-
 pub fn mount<P: AsRef<Path>>(
     source: impl AsRef<str>,
     target: P,
@@ -191,6 +180,17 @@ pub fn rename(from: impl AsRef<Path>, to: impl AsRef<Path>) -> Result<()> {
     fs::rename(from, to).map_err(context!("error renaming {:?} to {:?}", from, to))
 }
 
+/// Create a new hard link at path `dst` to the file at path `src`
+///
+/// A wrapper around `fs::hard_link()` which on failure returns an error indicating the source and
+/// destination paths.
+///
+pub fn hard_link(src: impl AsRef<Path>, dst: impl AsRef<Path>) -> Result<()> {
+    let src = src.as_ref();
+    let dst = dst.as_ref();
+    fs::hard_link(src, dst).map_err(context!("failed to create hard link {:?} to {:?}", dst, src))
+}
+
 /// Create a symlink at path `dst` which points to `src`
 ///
 /// A wrapper around `fs::symlink()` which on failure returns an error indicating the source and
@@ -339,6 +339,19 @@ pub fn chown_tree(base: &Path, chown_to: (u32, u32), include_base: bool) -> Resu
     Ok(())
 }
 
+/// Return the number of bytes of disk space allocated to the files in the directory
+/// tree at `base`. Mountpoints below `base` are not crossed.
+pub fn disk_usage(base: &Path) -> Result<u64> {
+    let mut total = 0;
+    for entry in WalkDir::new(base).same_file_system(true) {
+        let entry = entry.map_err(|e| format_err!("Error reading directory entry: {}", e))?;
+        let meta = entry.metadata()
+            .map_err(|e| format_err!("Error reading metadata of {:?}: {}", entry.path(), e))?;
+        total += meta.blocks() * 512;
+    }
+    Ok(total)
+}
+
 pub fn is_euid_root() -> bool {
     unsafe { libc::geteuid() == 0 }
 }
@@ -1,9 +1,14 @@
-use std::collections::HashMap;
+use std::fmt;
 use std::fs::{File, OpenOptions};
-use std::io;
+use std::io::{BufReader, BufWriter, Read, Seek, SeekFrom, Write};
 use std::path::{Path, PathBuf};
+use std::thread;
 
-use crate::{util, ImageHeader, LoopDevice, MetaInfo, Partition, Result};
+use byteorder::{ByteOrder, LittleEndian};
+use sodiumoxide::crypto::hash::sha256;
+use sodiumoxide::randombytes::randombytes;
+
+use crate::{ImageHeader, LoopDevice, MetaInfo, Partition, Result};
 use std::sync::Arc;
 
 pub struct Verity {
@@ -23,20 +28,28 @@ impl Verity {
         })
     }
 
+    /// Generate a dm-verity hash tree for the entire file `image` with a random salt
+    /// and write it to the file `output`.
+    // usize::is_multiple_of() requires Rust 1.87
+    #[allow(clippy::manual_is_multiple_of)]
     pub fn generate_initial_hashtree(
         image: impl AsRef<Path>,
         output: impl AsRef<Path>,
     ) -> Result<VerityOutput> {
         let image = image.as_ref();
         let output = output.as_ref();
-        // Don't use absolute path to veritysetup so that the build will correctly find the version from cryptsetup-native
-        let output = cmd_with_output!(
-            "veritysetup",
-            "format {} {}",
-            image.display(),
-            output.display()
-        )?;
-        Ok(VerityOutput::parse(&output))
+        let meta = image.metadata().map_err(context!(
+            "failed to read metadata from image file {:?}",
+            image
+        ))?;
+        let len = meta.len() as usize;
+        if len == 0 || len % BLOCK_SIZE != 0 {
+            bail!("image file size ({}) is not a multiple of the block size", len);
+        }
+        let tree = HashTree::new(&randombytes(SALT_SIZE), len / BLOCK_SIZE)?;
+        let mut output = File::create(output)
+            .map_err(context!("failed to create verity hashtree file {:?}", output))?;
+        tree.generate(image, 0, &mut output)
     }
 
     pub fn generate_image_hashtree(&self) -> Result<VerityOutput> {
@@ -50,15 +63,13 @@ impl Verity {
         salt: &str,
         nblocks: usize,
     ) -> Result<VerityOutput> {
-        let verityfile = self.image.with_extension("verity");
-
         // Make sure file size is correct or else verity tree will be appended in wrong place
         let meta = self.image.metadata().map_err(context!(
             "failed to read metadata from image file {:?}",
             self.image
         ))?;
         let len = meta.len() as usize;
-        let expected = (nblocks + 1) * 4096;
+        let expected = (nblocks + 1) * BLOCK_SIZE;
         if len != expected {
             bail!(
                 "actual file size ({}) does not match expected size ({})",
@@ -66,42 +77,40 @@ impl Verity {
                 expected
             );
         }
-        let vout = LoopDevice::with_loop(self.path(), Some(4096), true, |loopdev| {
-            let output = cmd_with_output!(
-                Self::VERITYSETUP,
-                "--data-blocks={} --salt={} format {} {}",
-                nblocks,
-                salt,
-                loopdev,
-                verityfile.display()
-            )?;
-            Ok(VerityOutput::parse(&output))
-        })?;
-        let mut input = File::open(&verityfile).map_err(context!(
-            "failed to open temporary verity hashtree file {:?}",
-            verityfile
-        ))?;
+        let salt = hex::decode(salt)
+            .map_err(context!("verity salt {} is not a hex string", salt))?;
+        let tree = HashTree::new(&salt, nblocks)?;
         let mut output = OpenOptions::new()
             .append(true)
             .open(self.path())
             .map_err(context!("failed to open image file {:?}", self.path()))?;
-        io::copy(&mut input, &mut output)
-            .map_err(context!("i/o error copying verity hashtree to image file"))?;
-        util::remove_file(&verityfile)?;
-        Ok(vout)
+        tree.generate(self.path(), BLOCK_SIZE as u64, &mut output)
     }
 
     pub fn verify(&self) -> Result<bool> {
-        LoopDevice::with_loop(self.path(), Some(4096), true, |loopdev| {
-            cmd_ok!(
-                Self::VERITYSETUP,
-                "--hash-offset={} verify {} {} {}",
-                self.metainfo.nblocks() * 4096,
-                loopdev,
-                loopdev,
-                self.metainfo.verity_root()
-            )
-        })
+        match self.find_corrupt_block()? {
+            Some(corrupt) => {
+                warn!("Verity verification of {:?} failed at {}", self.path(), corrupt);
+                Ok(false)
+            }
+            None => Ok(true),
+        }
+    }
+
+    /// Check the image data and the appended hash tree against the `verity-root` of
+    /// the image header without setting up a device, and return the first block which
+    /// does not match its hash.
+    pub fn find_corrupt_block(&self) -> Result<Option<VerityCorruption>> {
+        let nblocks = self.metainfo.nblocks();
+        let root = hex::decode(self.metainfo.verity_root())
+            .map_err(context!("verity-root of image header is not a hex string"))?;
+        let data_offset = BLOCK_SIZE as u64;
+        let hash_offset = ((nblocks + 1) * BLOCK_SIZE) as u64;
+        let tree = HashTree::read_superblock(self.path(), hash_offset)?;
+        if tree.data_blocks != nblocks {
+            bail!("verity superblock has {} data blocks but image header has {}", tree.data_blocks, nblocks);
+        }
+        tree.verify(self.path(), data_offset, hash_offset, &root)
     }
 
     pub fn setup(&self) -> Result<String> {
@@ -160,44 +169,377 @@ impl Verity {
     }
 }
 
-/// The output from the `veritysetup format` command can be parsed as key/value
-/// pairs. This class parses the output and stores it in a map for querying.
+/// The parameters and root hash of a generated hash tree, which can be displayed
+/// in the same key/value format as the output of `veritysetup format`.
 pub struct VerityOutput {
-    output: String,
-    map: HashMap<String, String>,
+    uuid: String,
+    data_blocks: usize,
+    salt: String,
+    root_hash: String,
 }
 
 impl VerityOutput {
-    /// Parse the string `output` as standard output from the dm-verity
-    /// `veritysetup format` command.
-    fn parse(output: &str) -> Self {
-        let mut vo = VerityOutput {
-            output: output.to_owned(),
-            map: HashMap::new(),
-        };
-        for line in output.lines() {
-            vo.parse_line(line);
+    pub fn root_hash(&self) -> Option<&str> {
+        Some(&self.root_hash)
+    }
+
+    pub fn salt(&self) -> Option<&str> {
+        Some(&self.salt)
+    }
+
+    pub fn output(&self) -> String {
+        format!("UUID:            \t{}\n\
+                 Hash type:       \t{}\n\
+                 Data blocks:     \t{}\n\
+                 Data block size: \t{}\n\
+                 Hash block size: \t{}\n\
+                 Hash algorithm:  \t{}\n\
+                 Salt:            \t{}\n\
+                 Root hash:      \t{}\n",
+                self.uuid, HASH_TYPE, self.data_blocks, BLOCK_SIZE, BLOCK_SIZE,
+                HASH_ALGORITHM, self.salt, self.root_hash)
+    }
+}
+
+/// A block found by `Verity::find_corrupt_block()` which does not match the hash
+/// stored for it in the level above.
+#[derive(Clone,Copy,Debug,PartialEq,Eq)]
+pub enum VerityCorruption {
+    /// Data block of the image, counted from the first block after the image header.
+    DataBlock(usize),
+    /// Block of the hash tree at `level`, where level 0 holds the hashes of the data blocks.
+    HashBlock { level: usize, block: usize },
+}
+
+impl fmt::Display for VerityCorruption {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            VerityCorruption::DataBlock(block) => write!(f, "data block {}", block),
+            VerityCorruption::HashBlock { level, block } => write!(f, "hash block {} of level {}", block, level),
         }
-        vo
     }
+}
+
+const BLOCK_SIZE: usize = 4096;
+const DIGEST_SIZE: usize = 32;
+const SALT_SIZE: usize = 32;
+const HASH_TYPE: u32 = 1;
+const HASH_ALGORITHM: &str = "sha256";
+
+// Layout of the 512 byte verity superblock which precedes the hash tree.
+// The superblock is padded to BLOCK_SIZE and all fields are little endian.
+const SB_SIGNATURE: &[u8] = b"verity\0\0";
+const SB_VERSION: u32 = 1;
+const SB_SIZE: usize = 512;
+const SB_MAX_SALT_SIZE: usize = 256;
+const SB_ALGORITHM_SIZE: usize = 32;
+
+/// A dm-verity hash tree in the on-disk format created by `veritysetup format` with
+/// the default parameters: format version 1, sha256, 4096 byte data and hash blocks
+/// and a superblock in front of the tree.
+///
+/// Each data block is hashed as `sha256(salt || block)` and the hashes are packed
+/// into hash blocks which form level 0 of the tree. Each further level holds the
+/// hashes of the blocks of the level below, until a level fits in a single block.
+/// The root hash is the hash of that block. The levels are stored after the
+/// superblock starting from the top level.
+struct HashTree {
+    uuid: [u8; 16],
+    salt: Vec<u8>,
+    data_blocks: usize,
+}
 
-    fn parse_line(&mut self, line: &str) {
-        let v = line.split(':').map(|s| s.trim()).collect::<Vec<_>>();
+impl HashTree {
+    fn new(salt: &[u8], data_blocks: usize) -> Result<Self> {
+        if salt.len() > SB_MAX_SALT_SIZE {
+            bail!("verity salt is longer than {} bytes", SB_MAX_SALT_SIZE);
+        }
+        if data_blocks == 0 {
+            bail!("cannot generate verity hash tree for empty image");
+        }
+        let mut uuid = [0u8; 16];
+        uuid.copy_from_slice(&randombytes(16));
+        // random (version 4) uuid
+        uuid[6] = (uuid[6] & 0x0F) | 0x40;
+        uuid[8] = (uuid[8] & 0x3F) | 0x80;
+        Ok(HashTree { uuid, salt: salt.to_vec(), data_blocks })
+    }
 
-        if v.len() == 2 {
-            self.map.insert(v[0].to_owned(), v[1].to_owned());
+    /// Number of hash blocks in each level of the tree, starting from level 0.
+    fn level_sizes(&self) -> Vec<usize> {
+        let per_block = BLOCK_SIZE / DIGEST_SIZE;
+        let mut sizes = Vec::new();
+        let mut n = self.data_blocks;
+        while n > 1 {
+            n = n.div_ceil(per_block);
+            sizes.push(n);
         }
+        sizes
     }
 
-    pub fn root_hash(&self) -> Option<&str> {
-        self.map.get("Root hash").map(|s| s.as_str())
+    fn hash_block(&self, block: &[u8]) -> [u8; DIGEST_SIZE] {
+        let mut state = sha256::State::new();
+        state.update(&self.salt);
+        state.update(block);
+        state.finalize().0
     }
 
-    pub fn salt(&self) -> Option<&str> {
-        self.map.get("Salt").map(|s| s.as_str())
+    // Hash each block of `blocks` and return the concatenated hashes
+    fn hash_blocks(&self, blocks: &[u8]) -> Vec<u8> {
+        blocks.chunks(BLOCK_SIZE)
+            .flat_map(|b| self.hash_block(b))
+            .collect()
+    }
+
+    /// Hash the data blocks which start at `offset` in the file `path`, splitting the
+    /// work between one thread for each available cpu.
+    fn hash_data(&self, path: &Path, offset: u64) -> Result<Vec<u8>> {
+        let nthreads = thread::available_parallelism().map(|n| n.get()).unwrap_or(1);
+        let per_thread = self.data_blocks.div_ceil(nthreads);
+        let mut digests = vec![0u8; self.data_blocks * DIGEST_SIZE];
+
+        thread::scope(|s| {
+            let handles = digests.chunks_mut(per_thread * DIGEST_SIZE)
+                .enumerate()
+                .map(|(i, out)| {
+                    let start = offset + (i * per_thread * BLOCK_SIZE) as u64;
+                    s.spawn(move || self.hash_data_range(path, start, out))
+                })
+                .collect::<Vec<_>>();
+            handles.into_iter()
+                .try_for_each(|h| h.join().unwrap_or_else(|_| bail!("verity hashing thread panicked")))
+        })?;
+        Ok(digests)
     }
 
-    pub fn output(&self) -> &str {
-        &self.output
+    fn hash_data_range(&self, path: &Path, offset: u64, out: &mut [u8]) -> Result<()> {
+        let mut file = File::open(path)
+            .map_err(context!("failed to open image file {:?}", path))?;
+        file.seek(SeekFrom::Start(offset))
+            .map_err(context!("failed to seek to offset {} of image file {:?}", offset, path))?;
+        let mut reader = BufReader::with_capacity(BLOCK_SIZE * 256, file);
+        let mut block = vec![0u8; BLOCK_SIZE];
+        for digest in out.chunks_mut(DIGEST_SIZE) {
+            reader.read_exact(&mut block)
+                .map_err(context!("error reading data block from image file {:?}", path))?;
+            digest.copy_from_slice(&self.hash_block(&block));
+        }
+        Ok(())
     }
+
+    /// Build the hash tree for the data blocks at `offset` in the file `path` and
+    /// write the superblock and tree to `out`.
+    fn generate(&self, path: &Path, offset: u64, out: &mut File) -> Result<VerityOutput> {
+        let mut digests = self.hash_data(path, offset)?;
+        let mut levels = Vec::new();
+        // the hash of the single block of the top level (or of the only data block) is the root hash
+        while digests.len() > DIGEST_SIZE {
+            let level = pad_to_block(digests);
+            digests = self.hash_blocks(&level);
+            levels.push(level);
+        }
+
+        let mut writer = BufWriter::new(out);
+        writer.write_all(&self.superblock())
+            .map_err(context!("error writing verity superblock"))?;
+        for level in levels.iter().rev() {
+            writer.write_all(level)
+                .map_err(context!("error writing verity hash tree"))?;
+        }
+        writer.flush()
+            .map_err(context!("error writing verity hash tree"))?;
+
+        Ok(VerityOutput {
+            uuid: self.uuid_string(),
+            data_blocks: self.data_blocks,
+            salt: hex::encode(&self.salt),
+            root_hash: hex::encode(digests),
+        })
+    }
+
+    /// Check the tree stored at `hash_offset` in `path` against `root` from the top
+    /// level down and then the data blocks stored at `data_offset` against level 0.
+    /// Checking from the top ensures that each block is compared against a hash which
+    /// has already been verified.
+    fn verify(&self, path: &Path, data_offset: u64, hash_offset: u64, root: &[u8]) -> Result<Option<VerityCorruption>> {
+        let sizes = self.level_sizes();
+        let mut file = File::open(path)
+            .map_err(context!("failed to open image file {:?}", path))?;
+        file.seek(SeekFrom::Start(hash_offset + BLOCK_SIZE as u64))
+            .map_err(context!("failed to seek to verity hash tree in {:?}", path))?;
+        let mut levels = Vec::new();
+        for size in sizes.iter().rev() {
+            let mut level = vec![0u8; size * BLOCK_SIZE];
+            file.read_exact(&mut level)
+                .map_err(context!("error reading verity hash tree from {:?}", path))?;
+            levels.insert(0, level);
+        }
+
+        if let Some(top) = levels.last() {
+            if self.hash_block(top).as_slice() != root {
+                return Ok(Some(VerityCorruption::HashBlock { level: levels.len() - 1, block: 0 }));
+            }
+        }
+        for level in (1..levels.len()).rev() {
+            let hashes = self.hash_blocks(&levels[level - 1]);
+            if let Some(block) = first_mismatch(&hashes, &levels[level], sizes[level - 1]) {
+                return Ok(Some(VerityCorruption::HashBlock { level: level - 1, block }));
+            }
+        }
+
+        let digests = self.hash_data(path, data_offset)?;
+        let expected = levels.first().map(|v| v.as_slice()).unwrap_or(root);
+        Ok(first_mismatch(&digests, expected, self.data_blocks)
+            .map(VerityCorruption::DataBlock))
+    }
+
+    fn superblock(&self) -> Vec<u8> {
+        let mut sb = Vec::with_capacity(BLOCK_SIZE);
+        sb.extend_from_slice(SB_SIGNATURE);
+        sb.extend_from_slice(&SB_VERSION.to_le_bytes());
+        sb.extend_from_slice(&HASH_TYPE.to_le_bytes());
+        sb.extend_from_slice(&self.uuid);
+        let mut algorithm = [0u8; SB_ALGORITHM_SIZE];
+        algorithm[..HASH_ALGORITHM.len()].copy_from_slice(HASH_ALGORITHM.as_bytes());
+        sb.extend_from_slice(&algorithm);
+        sb.extend_from_slice(&(BLOCK_SIZE as u32).to_le_bytes());
+        sb.extend_from_slice(&(BLOCK_SIZE as u32).to_le_bytes());
+        sb.extend_from_slice(&(self.data_blocks as u64).to_le_bytes());
+        sb.extend_from_slice(&(self.salt.len() as u16).to_le_bytes());
+        sb.extend_from_slice(&[0u8; 6]);
+        let mut salt = [0u8; SB_MAX_SALT_SIZE];
+        salt[..self.salt.len()].copy_from_slice(&self.salt);
+        sb.extend_from_slice(&salt);
+        sb.resize(BLOCK_SIZE, 0);
+        sb
+    }
+
+    /// Read the superblock at `offset` in `path`, which must describe a tree in the
+    /// format generated by `HashTree`.
+    fn read_superblock(path: &Path, offset: u64) -> Result<Self> {
+        let mut file = File::open(path)
+            .map_err(context!("failed to open image file {:?}", path))?;
+        file.seek(SeekFrom::Start(offset))
+            .map_err(context!("failed to seek to verity superblock in {:?}", path))?;
+        let mut sb = vec![0u8; SB_SIZE];
+        file.read_exact(&mut sb)
+            .map_err(context!("error reading verity superblock from {:?}", path))?;
+
+        let u32_at = |off: usize| LittleEndian::read_u32(&sb[off..]);
+        if &sb[..8] != SB_SIGNATURE {
+            bail!("no verity superblock found in {:?}", path);
+        }
+        if u32_at(8) != SB_VERSION || u32_at(12) != HASH_TYPE {
+            bail!("unsupported verity superblock version {} or hash type {}", u32_at(8), u32_at(12));
+        }
+        let algorithm = &sb[32..32 + SB_ALGORITHM_SIZE];
+        if !algorithm.starts_with(HASH_ALGORITHM.as_bytes()) || algorithm[HASH_ALGORITHM.len()] != 0 {
+            bail!("unsupported verity hash algorithm {}", String::from_utf8_lossy(algorithm).trim_end_matches('\0'));
+        }
+        if u32_at(64) as usize != BLOCK_SIZE || u32_at(68) as usize != BLOCK_SIZE {
+            bail!("unsupported verity block sizes {} and {}", u32_at(64), u32_at(68));
+        }
+        let data_blocks = LittleEndian::read_u64(&sb[72..]) as usize;
+        let salt_size = LittleEndian::read_u16(&sb[80..]) as usize;
+        if salt_size > SB_MAX_SALT_SIZE {
+            bail!("invalid verity salt size {}", salt_size);
+        }
+        let mut uuid = [0u8; 16];
+        uuid.copy_from_slice(&sb[16..32]);
+        let salt = sb[88..88 + salt_size].to_vec();
+        Ok(HashTree { uuid, salt, data_blocks })
+    }
+
+    fn uuid_string(&self) -> String {
+        let u = hex::encode(self.uuid);
+        format!("{}-{}-{}-{}-{}", &u[..8], &u[8..12], &u[12..16], &u[16..20], &u[20..])
+    }
+}
+
+fn pad_to_block(mut v: Vec<u8>) -> Vec<u8> {
+    let len = v.len().div_ceil(BLOCK_SIZE) * BLOCK_SIZE;
+    v.resize(len, 0);
+    v
+}
+
+// Index of the first of `count` digests which differs between `a` and `b`
+fn first_mismatch(a: &[u8], b: &[u8], count: usize) -> Option<usize> {
+    (0..count).find(|&i| {
+        let range = i * DIGEST_SIZE..(i + 1) * DIGEST_SIZE;
+        a[range.clone()] != b[range]
+    })
+}
+
+#[test]
+fn test_verity_hash_tree() {
+    let path = std::env::temp_dir().join(format!("verity-test-{}.img", std::process::id()));
+    // one header block, 300 data blocks and a tree with two levels
+    let nblocks = 300;
+    let data = (0..(nblocks + 1) * BLOCK_SIZE).map(|i| (i / BLOCK_SIZE) as u8).collect::<Vec<_>>();
+    std::fs::write(&path, &data).unwrap();
+
+    let salt = [7u8; SALT_SIZE];
+    let tree = HashTree::new(&salt, nblocks).unwrap();
+    assert_eq!(tree.level_sizes(), vec![3, 1]);
+    let mut out = OpenOptions::new().append(true).open(&path).unwrap();
+    let output = tree.generate(&path, BLOCK_SIZE as u64, &mut out).unwrap();
+    let root = hex::decode(output.root_hash().unwrap()).unwrap();
+
+    let data_offset = BLOCK_SIZE as u64;
+    let hash_offset = ((nblocks + 1) * BLOCK_SIZE) as u64;
+    assert_eq!(path.metadata().unwrap().len(), hash_offset + 5 * BLOCK_SIZE as u64);
+    let tree = HashTree::read_superblock(&path, hash_offset).unwrap();
+    assert_eq!(tree.data_blocks, nblocks);
+    assert_eq!(tree.salt, salt.to_vec());
+    assert_eq!(tree.verify(&path, data_offset, hash_offset, &root).unwrap(), None);
+
+    let corrupt = |offset: usize| {
+        let mut bytes = std::fs::read(&path).unwrap();
+        bytes[offset] ^= 0xFF;
+        let copy = path.with_extension("corrupt");
+        std::fs::write(&copy, &bytes).unwrap();
+        let result = tree.verify(&copy, data_offset, hash_offset, &root).unwrap();
+        std::fs::remove_file(&copy).unwrap();
+        result
+    };
+    assert_eq!(corrupt(BLOCK_SIZE * 43 + 100), Some(VerityCorruption::DataBlock(42)));
+    // level 0 is stored after the superblock and the single block of level 1
+    assert_eq!(corrupt(hash_offset as usize + BLOCK_SIZE * 3), Some(VerityCorruption::HashBlock { level: 0, block: 1 }));
+    assert_eq!(corrupt(hash_offset as usize + BLOCK_SIZE), Some(VerityCorruption::HashBlock { level: 1, block: 0 }));
+    std::fs::remove_file(&path).unwrap();
+}
+
+// Known answer for a tree with two levels over 129 data blocks, where block `i` is filled
+// with byte `i`. The expected root hash and hash file are those of `veritysetup format
+// --salt=000102..1f --uuid=01234567-89ab-4def-8123-456789abcdef` with the default
+// parameters, computed with a separate implementation of the on-disk format.
+#[test]
+fn test_verity_known_answer() {
+    let path = std::env::temp_dir().join(format!("verity-kat-{}.img", std::process::id()));
+    let hashes = path.with_extension("hashes");
+    let nblocks = 129;
+    let data = (0..nblocks * BLOCK_SIZE).map(|i| (i / BLOCK_SIZE) as u8).collect::<Vec<_>>();
+    std::fs::write(&path, &data).unwrap();
+
+    let salt = (0..SALT_SIZE as u8).collect::<Vec<_>>();
+    let mut tree = HashTree::new(&salt, nblocks).unwrap();
+    tree.uuid.copy_from_slice(&hex::decode("0123456789ab4def8123456789abcdef").unwrap());
+    let mut out = File::create(&hashes).unwrap();
+    let output = tree.generate(&path, 0, &mut out).unwrap();
+    drop(out);
+    assert_eq!(output.root_hash(), Some("6093a2333523050b628581510028976d3d3e9c62458642a83727e6df641397a4"));
+    assert_eq!(output.salt(), Some("000102030405060708090a0b0c0d0e0f101112131415161718191a1b1c1d1e1f"));
+
+    let written = std::fs::read(&hashes).unwrap();
+    assert_eq!(written.len(), 4 * BLOCK_SIZE);
+    assert_eq!(hex::encode(&written[..120]),
+        "766572697479000001000000010000000123456789ab4def8123456789abcdef\
+         7368613235360000000000000000000000000000000000000000000000000000\
+         0010000000100000810000000000000020000000000000000001020304050607\
+         08090a0b0c0d0e0f101112131415161718191a1b1c1d1e1f");
+    assert!(written[120..BLOCK_SIZE].iter().all(|&b| b == 0));
+    assert_eq!(hex::encode(sha256::hash(&written).0), "a0e761abad1f810e05bf5e3371096a2e40778f4617b5b370e6051b90b793e1de");
+
+    std::fs::remove_file(&path).unwrap();
+    std::fs::remove_file(&hashes).unwrap();
 }
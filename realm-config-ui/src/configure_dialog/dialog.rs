@@ -7,7 +7,7 @@ use gtk::subclass::prelude::*;
 use gtk::CompositeTemplate;
 
 use crate::colorscheme::ColorSchemeDialog;
-use crate::configure_dialog::ConfigOptions;
+use crate::configure_dialog::{ConfigOptions, LimitOption};
 use crate::configure_dialog::settings::CitadelSettings;
 use crate::realmsd::RealmConfig;
 
@@ -17,6 +17,9 @@ pub struct ConfigureDialog {
     #[template_child(id="bool-options-box")]
     bool_option_list: TemplateChild<gtk::ListBox>,
 
+    #[template_child(id="limit-options-box")]
+    limit_option_list: TemplateChild<gtk::ListBox>,
+
     #[template_child(id="overlay-combo")]
     overlay: TemplateChild<gtk::ComboBoxText>,
 
@@ -33,6 +36,8 @@ pub struct ConfigureDialog {
 
     bool_option_rows: RefCell<Vec<super::ConfigureOption>>,
 
+    limit_option_rows: RefCell<Vec<(LimitOption, gtk::Entry)>>,
+
     colorscheme_dialog: ColorSchemeDialog,
 
     settings: RefCell<CitadelSettings>,
@@ -85,6 +90,9 @@ impl ConfigureDialog {
         for row in rows.iter() {
             row.update();
         }
+        for (op, entry) in self.limit_option_rows.borrow().iter() {
+            entry.set_text(&op.value());
+        }
         let overlay_id = self.options().overlay_id();
         self.overlay.set_active_id(Some(&overlay_id));
 
@@ -104,6 +112,31 @@ impl ConfigureDialog {
         }
     }
 
+    fn create_limit_rows(&self) {
+        let mut rows = self.limit_option_rows.borrow_mut();
+        let options = self.options.borrow();
+        for op in options.limit_options() {
+            let row = gtk::Box::new(gtk::Orientation::Horizontal, 0);
+            row.set_tooltip_markup(Some(op.tooltip()));
+
+            let label = gtk::Label::new(Some(op.description()));
+            label.set_hexpand(true);
+            label.set_halign(gtk::Align::Start);
+            row.add(&label);
+
+            let entry = gtk::Entry::new();
+            entry.set_placeholder_text(Some("No limit"));
+            let option = op.clone();
+            entry.connect_changed(move |entry| {
+                option.set_value(entry.text().as_str());
+            });
+            row.add(&entry);
+
+            self.limit_option_list.add(&row);
+            rows.push((op.clone(), entry));
+        }
+    }
+
     fn setup_overlay(&self) {
         let options = self.options.clone();
         self.overlay.connect_changed(move |combo| {
@@ -151,6 +184,7 @@ impl ConfigureDialog {
 
     fn setup_widgets(&self) {
         self.create_option_rows();
+        self.create_limit_rows();
         self.setup_overlay();
         self.setup_realmfs();
         self.setup_colorscheme();
@@ -162,6 +196,7 @@ impl Default for ConfigureDialog {
     fn default() -> Self {
         ConfigureDialog {
             bool_option_list: Default::default(),
+            limit_option_list: Default::default(),
             overlay: Default::default(),
             realmfs: Default::default(),
             colorscheme: Default::default(),
@@ -170,6 +205,7 @@ impl Default for ConfigureDialog {
             options: Rc::new(RefCell::new(ConfigOptions::new())),
             settings: RefCell::new(CitadelSettings::new()),
             bool_option_rows: RefCell::new(Vec::new()),
+            limit_option_rows: RefCell::new(Vec::new()),
         }
     }
 }
@@ -3,7 +3,7 @@ use gtk::prelude::*;
 use glib::subclass::prelude::*;
 
 use crate::realmsd::RealmConfig;
-pub use crate::configure_dialog::options::{ConfigOptions,BoolOption};
+pub use crate::configure_dialog::options::{ConfigOptions,BoolOption,LimitOption};
 
 mod dialog;
 mod option_row;
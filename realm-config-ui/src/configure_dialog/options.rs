@@ -1,7 +1,7 @@
-use std::cell::Cell;
+use std::cell::{Cell, RefCell};
 use std::rc::Rc;
 
-use libcitadel::OverlayType;
+use libcitadel::{ConfigKind, OverlayType};
 use libcitadel::terminal::Base16Scheme;
 
 use crate::realmsd::RealmConfig;
@@ -71,6 +71,44 @@ const BOOL_OPTIONS: &[(&str, &str, &str)] = &[
     ("use-ephemeral-home", "Use ephemeral tmpfs mount for home directory", EPHERMERAL_HOME_TOOLTIP),
 ];
 
+const MEMORY_MAX_TOOLTIP: &str = r#"Hard limit on the memory used by all processes in the realm. When the limit is reached processes in the realm are killed.
+
+Size in bytes with an optional suffix such as <tt><b>4G</b></tt> or <tt><b>512M</b></tt>, or a percentage of system memory such as <tt><b>50%</b></tt>.
+"#;
+
+const MEMORY_HIGH_TOOLTIP: &str = r#"Memory use above which processes in the realm are slowed down and memory is reclaimed aggressively.
+
+Size in bytes with an optional suffix such as <tt><b>3G</b></tt> or a percentage of system memory.
+"#;
+
+const CPU_WEIGHT_TOOLTIP: &str = r#"Share of CPU time given to the realm relative to other realms when the CPU is busy.
+
+A value between <tt><b>1</b></tt> and <tt><b>10000</b></tt>. The default weight is <tt><b>100</b></tt>.
+"#;
+
+const CPU_QUOTA_TOOLTIP: &str = r#"Limit on CPU time used by the realm as a percentage of a single CPU.
+
+For example <tt><b>50%</b></tt> allows half of one CPU and <tt><b>200%</b></tt> allows two full CPUs.
+"#;
+
+const IO_WEIGHT_TOOLTIP: &str = r#"Share of disk bandwidth given to the realm relative to other realms.
+
+A value between <tt><b>1</b></tt> and <tt><b>10000</b></tt>. The default weight is <tt><b>100</b></tt>.
+"#;
+
+const TASKS_MAX_TOOLTIP: &str = "\
+Maximum number of processes and threads which may run in the realm.
+";
+
+const LIMIT_OPTIONS: &[(&str, &str, &str)] = &[
+    ("memory-max", "Memory Limit", MEMORY_MAX_TOOLTIP),
+    ("memory-high", "Memory Throttle", MEMORY_HIGH_TOOLTIP),
+    ("cpu-weight", "CPU Weight", CPU_WEIGHT_TOOLTIP),
+    ("cpu-quota", "CPU Quota", CPU_QUOTA_TOOLTIP),
+    ("io-weight", "IO Weight", IO_WEIGHT_TOOLTIP),
+    ("tasks-max", "Task Limit", TASKS_MAX_TOOLTIP),
+];
+
 #[derive(Clone)]
 pub struct BoolOption {
     id: String,
@@ -141,6 +179,81 @@ impl BoolOption {
     }
 }
 
+/// A resource limit which is edited as text. An empty value means no limit is set.
+#[derive(Clone)]
+pub struct LimitOption {
+    id: String,
+    description: String,
+    tooltip: String,
+    original: Rc<RefCell<String>>,
+    value: Rc<RefCell<String>>,
+}
+
+impl LimitOption {
+    fn create_options() -> Vec<LimitOption> {
+        LIMIT_OPTIONS.iter()
+            .map(|(id, description, tooltip)| LimitOption::new(id, description, tooltip))
+            .collect()
+    }
+
+    fn new(id: &str, description: &str, tooltip: &str) -> Self {
+        let id = id.to_string();
+        let description = description.to_string();
+        let tooltip = format!("<b><big>{}</big></b>\n\n{}", description, tooltip);
+        let original = Rc::new(RefCell::new(String::new()));
+        let value = Rc::new(RefCell::new(String::new()));
+        LimitOption { id, description, tooltip, original, value }
+    }
+
+    pub fn value(&self) -> String {
+        self.value.borrow().clone()
+    }
+
+    pub fn set_value(&self, v: &str) {
+        *self.value.borrow_mut() = v.trim().to_string();
+    }
+
+    pub fn description(&self) -> &str {
+        &self.description
+    }
+
+    pub fn tooltip(&self) -> &str {
+        &self.tooltip
+    }
+
+    fn is_integer(&self) -> bool {
+        libcitadel::config_kind(&self.id) == Some(ConfigKind::Integer)
+    }
+
+    fn configure(&self, config: &RealmConfig) {
+        // integer limits are reported as 0 when not set
+        let v = match config.get_string(&self.id) {
+            Some("0") if self.is_integer() => "",
+            Some(v) => v,
+            None => "",
+        };
+        *self.original.borrow_mut() = v.to_string();
+        self.set_value(v);
+    }
+
+    fn reset(&self) {
+        let original = self.original.borrow().clone();
+        self.set_value(&original);
+    }
+
+    fn add_changes(&self, result: &mut Vec<(String, String)>) {
+        let value = self.value();
+        if value != *self.original.borrow() {
+            let v = if value.is_empty() && self.is_integer() {
+                "0".to_string()
+            } else {
+                value
+            };
+            result.push((self.id.clone(), v));
+        }
+    }
+}
+
 struct OverlayOption {
     original: OverlayType,
     current: OverlayType,
@@ -303,6 +416,7 @@ impl ColorSchemeOption {
 
 pub struct ConfigOptions {
     bool_options: Vec<BoolOption>,
+    limit_options: Vec<LimitOption>,
     overlay: OverlayOption,
     realmfs: RealmFsOption,
     colorscheme: ColorSchemeOption,
@@ -314,6 +428,9 @@ impl ConfigOptions {
         for op in &self.bool_options {
             op.configure(config);
         }
+        for op in &self.limit_options {
+            op.configure(config);
+        }
         self.overlay.configure(config);
         self.realmfs.configure(config);
         self.colorscheme.configure(config);
@@ -324,6 +441,9 @@ impl ConfigOptions {
         for op in &self.bool_options {
             op.reset();
         }
+        for op in &self.limit_options {
+            op.reset();
+        }
         self.overlay.reset();
         self.realmfs.reset();
         self.colorscheme.reset();
@@ -334,6 +454,9 @@ impl ConfigOptions {
         for op in &self.bool_options {
             op.add_changes(&mut changes);
         }
+        for op in &self.limit_options {
+            op.add_changes(&mut changes);
+        }
         self.overlay.add_changes(&mut changes);
         self.realmfs.add_changes(&mut changes);
         self.colorscheme.add_changes(&mut changes);
@@ -342,11 +465,12 @@ impl ConfigOptions {
 
     pub fn new() -> Self {
         let bool_options = BoolOption::create_options();
+        let limit_options = LimitOption::create_options();
         let overlay = OverlayOption::new();
         let realmfs = RealmFsOption::new();
         let colorscheme = ColorSchemeOption::new();
         ConfigOptions {
-            bool_options, overlay, realmfs, colorscheme,
+            bool_options, limit_options, overlay, realmfs, colorscheme,
         }
     }
 
@@ -354,6 +478,10 @@ impl ConfigOptions {
         &self.bool_options
     }
 
+    pub fn limit_options(&self) -> &[LimitOption] {
+        &self.limit_options
+    }
+
     pub fn realmfs_list(&self) -> Vec<String> {
         self.realmfs.realmfs_list()
     }
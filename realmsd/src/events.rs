@@ -1,6 +1,7 @@
-use zbus::{Connection, ObjectServer};
-use crate::realms_manager::{RealmsManagerServer, REALMS_SERVER_OBJECT_PATH, realm_status};
-use libcitadel::{RealmEvent, Realm};
+use zbus::Connection;
+use crate::realms_manager::{RealmsManagerServer, realm_status};
+use crate::signals;
+use libcitadel::{RealmEvent, Realm, RealmFS, EventJournal};
 
 pub struct EventHandler {
     connection: Connection,
@@ -13,6 +14,9 @@ impl EventHandler {
     }
 
     pub fn handle_event(&self, ev: &RealmEvent)  {
+        if let Err(err) = EventJournal::record_event(ev) {
+            warn!("Error recording realm event {} in journal: {}", ev, err);
+        }
         if let Err(err) = self.dispatch_event(ev) {
             warn!("Error emitting signal for realm event {}: {}", ev, err);
         }
@@ -25,6 +29,14 @@ impl EventHandler {
             RealmEvent::New(realm) => self.on_new(realm),
             RealmEvent::Removed(realm) => self.on_removed(realm),
             RealmEvent::Current(realm) => self.on_current(realm.as_ref()),
+            RealmEvent::ConfigChanged(realm) => self.with_server(|server| server.realm_config_changed(realm.name())),
+            RealmEvent::StartFailed(realm, err) => self.with_server(|server| server.realm_start_failed(realm.name(), err)),
+            RealmEvent::RealmFSNew(realmfs) => self.with_server(|server| server.realm_f_s_new(realmfs.name())),
+            RealmEvent::RealmFSRemoved(realmfs) => self.with_server(|server| server.realm_f_s_removed(realmfs.name())),
+            RealmEvent::RealmFSActivated(realmfs) => self.with_server(|server| server.realm_f_s_activated(realmfs.name())),
+            RealmEvent::RealmFSDeactivated(realmfs) => self.with_server(|server| server.realm_f_s_deactivated(realmfs.name())),
+            RealmEvent::RealmFSResized(realmfs) => self.on_realmfs_resized(realmfs),
+            RealmEvent::RealmFSUpdated(realmfs) => self.with_server(|server| server.realm_f_s_updated(realmfs.name())),
         }
     }
 
@@ -32,9 +44,7 @@ impl EventHandler {
         where
             F: Fn(&RealmsManagerServer) -> zbus::Result<()>,
     {
-        let mut object_server = ObjectServer::new(&self.connection);
-        object_server.at(REALMS_SERVER_OBJECT_PATH, self.realms_server.clone())?;
-        object_server.with(REALMS_SERVER_OBJECT_PATH, |iface: &RealmsManagerServer| func(iface))
+        signals::with_server(&self.connection, &self.realms_server, func)
     }
 
     fn on_started(&self, realm: &Realm) -> zbus::Result<()> {
@@ -58,6 +68,11 @@ impl EventHandler {
         self.with_server(|server| server.realm_removed(realm.name()))
     }
 
+    fn on_realmfs_resized(&self, realmfs: &RealmFS) -> zbus::Result<()> {
+        let nblocks = realmfs.metainfo().nblocks() as u64;
+        self.with_server(|server| server.realm_f_s_resized(realmfs.name(), nblocks))
+    }
+
     fn on_current(&self, realm: Option<&Realm>) -> zbus::Result<()> {
         self.with_server(|server| {
             match realm {
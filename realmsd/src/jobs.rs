@@ -0,0 +1,128 @@
+use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicU32, Ordering};
+use std::thread;
+
+use zbus::Connection;
+use libcitadel::{Realm, RealmStage, StopDependents};
+
+use crate::realms_manager::{RealmsManagerServer, JobErrorItem};
+use crate::signals;
+
+#[derive(Clone,Copy)]
+pub enum JobKind {
+    Start,
+    Stop,
+    StopWithDependents,
+    Restart,
+}
+
+/// Runs realm start, stop and restart requests in the background.
+///
+/// Each request is assigned a job id which is returned to the caller. The steps of
+/// the job are reported with `JobProgress` signals and the result with a single
+/// `JobFinished` signal carrying the id.
+#[derive(Clone)]
+pub struct RealmJobs {
+    connection: Connection,
+    next_id: Arc<AtomicU32>,
+}
+
+impl RealmJobs {
+    pub fn new(connection: Connection) -> Self {
+        // Job id 0 is returned when no job was started
+        RealmJobs { connection, next_id: Arc::new(AtomicU32::new(1)) }
+    }
+
+    /// Run `kind` on `realm` in a new thread and return the id of the job.
+    pub fn spawn(&self, server: RealmsManagerServer, kind: JobKind, realm: Realm) -> u32 {
+        let id = self.next_id.fetch_add(1, Ordering::SeqCst);
+        let job = RealmJob {
+            id, kind, realm, server,
+            connection: self.connection.clone(),
+            stage: Mutex::new(None),
+        };
+        thread::spawn(move || job.run());
+        id
+    }
+}
+
+struct RealmJob {
+    id: u32,
+    kind: JobKind,
+    realm: Realm,
+    server: RealmsManagerServer,
+    connection: Connection,
+    // The most recently started step, which is the step that failed if the job fails
+    stage: Mutex<Option<RealmStage>>,
+}
+
+impl RealmJob {
+    fn run(&self) {
+        let manager = self.server.manager();
+        let realm = &self.realm;
+        let progress = |stage| self.on_progress(stage);
+
+        let result = match self.kind {
+            JobKind::Start => manager.start_realm_with_progress(realm, &progress),
+            JobKind::Stop => manager.stop_realm_with_progress(realm, StopDependents::Refuse, &progress),
+            JobKind::StopWithDependents => manager.stop_realm_with_progress(realm, StopDependents::Cascade, &progress),
+            JobKind::Restart => manager.stop_realm_with_progress(realm, StopDependents::Ignore, &progress)
+                .and_then(|_| manager.start_realm_with_progress(realm, &progress)),
+        };
+
+        let error = match result {
+            Ok(()) => JobErrorItem::default(),
+            Err(err) => {
+                warn!("Job {} on realm-{} failed: {}", self.id, realm.name(), err);
+                self.error_item(&err.to_string())
+            }
+        };
+        let success = error.is_empty();
+        if let Err(err) = self.with_server(|server| server.job_finished(self.id, realm.name(), success, error.clone())) {
+            warn!("Error emitting JobFinished signal: {}", err);
+        }
+    }
+
+    fn on_progress(&self, stage: RealmStage) {
+        *self.stage.lock().unwrap() = Some(stage);
+        let realm = self.realm.name();
+        if let Err(err) = self.with_server(|server| server.job_progress(self.id, realm, stage.as_str(), stage.description())) {
+            warn!("Error emitting JobProgress signal: {}", err);
+        }
+    }
+
+    // Classify a failure by the step in which it occurred
+    fn error_item(&self, message: &str) -> JobErrorItem {
+        let stage = *self.stage.lock().unwrap();
+        let kind = match stage {
+            Some(RealmStage::StartingDependencies) | Some(RealmStage::StoppingDependents) => "dependency",
+            Some(RealmStage::MountingRealmFS) => {
+                let config = self.realm.config();
+                if self.server.manager().realmfs_name_exists(config.realmfs()) {
+                    "realmfs-activation"
+                } else {
+                    "realmfs-missing"
+                }
+            },
+            Some(RealmStage::CreatingOverlay) | Some(RealmStage::RemovingRootfs) => "overlay",
+            Some(RealmStage::ConfiguringNetwork) => "network",
+            Some(RealmStage::StartingUnit) | Some(RealmStage::StoppingUnit) => "unit",
+            // stopping is refused before any step begins while running realms depend on the realm
+            None if self.has_active_dependents() => "dependency",
+            Some(RealmStage::LinkingWaylandSocket) | None => "other",
+        };
+        let stage = stage.map(|s| s.as_str()).unwrap_or("");
+        JobErrorItem::new(kind, stage, message)
+    }
+
+    fn has_active_dependents(&self) -> bool {
+        matches!(self.kind, JobKind::Stop) && !self.server.manager().active_dependents(&self.realm).is_empty()
+    }
+
+    fn with_server<F>(&self, func: F) -> zbus::Result<()>
+        where
+            F: Fn(&RealmsManagerServer) -> zbus::Result<()>,
+    {
+        signals::with_server(&self.connection, &self.server, func)
+    }
+}
@@ -8,6 +8,10 @@ use crate::realms_manager::RealmsManagerServer;
 
 mod realms_manager;
 mod events;
+mod jobs;
+mod stats;
+mod signals;
+mod supervisor;
 
 
 fn main() {
@@ -1,11 +1,17 @@
-use libcitadel::{RealmManager, Realm, OverlayType, Result};
+use libcitadel::{RealmManager, Realm, RealmSnapshot, Result, ConfigKind, ConfigValue, ConfigError, CONFIG_VARIABLES, ResourceLimits, RealmStats, JournalEntry};
 use std::sync::Arc;
 use zbus::{dbus_interface, ObjectServer,Connection};
 use zvariant::derive::Type;
+use zvariant::{Fd, OwnedValue, Value};
+use std::fs::File;
+use std::os::unix::io::{AsRawFd, BorrowedFd};
 use std::thread;
 use std::collections::HashMap;
 use serde::{Serialize,Deserialize};
 use crate::events::EventHandler;
+use crate::jobs::{JobKind, RealmJobs};
+use crate::stats::StatsMonitor;
+use crate::supervisor::RealmSupervisor;
 use libcitadel::terminal::Base16Scheme;
 
 pub const REALMS_SERVER_OBJECT_PATH: &str = "/com/subgraph/realms";
@@ -13,83 +19,42 @@ pub const REALMS_SERVER_OBJECT_PATH: &str = "/com/subgraph/realms";
 #[derive(Clone)]
 pub struct RealmsManagerServer {
     manager: Arc<RealmManager>,
+    jobs: RealmJobs,
 }
 
-const BOOL_CONFIG_VARS: &[&str] = &[
-    "use-gpu", "use-wayland", "use-x11", "use-sound",
-    "use-shared-dir", "use-network", "use-kvm", "use-ephemeral-home"
-];
-
-fn is_bool_config_variable(variable: &str) -> bool {
-    BOOL_CONFIG_VARS.iter().any(|&s| s == variable)
-}
-
-fn save_config(realm: &Realm) {
-    let path = realm.base_path_file("config");
-    if let Err(e) = realm.config().write_to(&path) {
-        warn!("Error writing config file {}: {}", path.display(), e);
-    }
+fn save_config(realm: &Realm) -> Result<()> {
+    realm.write_config()
 }
 
-fn configure_realm_boolean_config(realm: &Realm, variable: &str, value: &str) {
-
-    let val = match value {
-        "true" => true,
-        "false" => false,
-        _ => {
-            warn!("Not a valid boolean value '{}'", value);
-            return;
+// Convert a D-Bus variant to a configuration value of type `kind`
+fn config_value_from_variant(kind: ConfigKind, value: &Value) -> Option<ConfigValue> {
+    match (kind, value) {
+        (ConfigKind::Bool, Value::Bool(b)) => Some(ConfigValue::Bool(*b)),
+        (ConfigKind::Integer, Value::U8(n)) => Some(ConfigValue::Integer(i64::from(*n))),
+        (ConfigKind::Integer, Value::I32(n)) => Some(ConfigValue::Integer(i64::from(*n))),
+        (ConfigKind::Integer, Value::U32(n)) => Some(ConfigValue::Integer(i64::from(*n))),
+        (ConfigKind::Integer, Value::I64(n)) => Some(ConfigValue::Integer(*n)),
+        (ConfigKind::String, Value::Str(s)) => Some(ConfigValue::String(s.as_str().to_string())),
+        (ConfigKind::StringList, Value::Array(array)) => {
+            let v = array.get().iter()
+                .map(|item| match item {
+                    Value::Str(s) => Some(s.as_str().to_string()),
+                    _ => None,
+                })
+                .collect::<Option<Vec<_>>>()?;
+            Some(ConfigValue::StringList(v))
         },
-    };
-
-    realm.with_mut_config(|c| {
-        match variable {
-            "use-gpu"            if c.gpu() != val            => c.use_gpu = Some(val),
-            "use-wayland"        if c.wayland() != val        => c.use_wayland = Some(val),
-            "use-x11"            if c.x11() != val            => c.use_x11 = Some(val),
-            "use-sound"          if c.sound() != val          => c.use_sound = Some(val),
-            "use-shared-dir"     if c.shared_dir() != val     => c.use_shared_dir = Some(val),
-            "use-network"        if c.network() != val        => c.use_network = Some(val),
-            "use-kvm"            if c.kvm() != val            => c.use_kvm = Some(val),
-            "use-ephemeral-home" if c.ephemeral_home() != val => c.use_ephemeral_home = Some(val),
-            _ => {},
-        }
-    });
-    save_config(realm);
+        (_, Value::Value(inner)) => config_value_from_variant(kind, inner),
+        _ => None,
+    }
 }
 
-fn configure_realm(manager: &RealmManager, realm: &Realm, variable: &str, value: &str) {
-    if is_bool_config_variable(variable) {
-        configure_realm_boolean_config(realm, variable, value);
-    } else if variable == "overlay" {
-        if value == "tmpfs" || value == "storage" || value == "none" {
-            realm.with_mut_config(|c| {
-                c.overlay = Some(value.to_string());
-            });
-            save_config(realm);
-        } else {
-            warn!("Invalid storage type '{}'", value);
-            return;
-        }
-    } else if variable == "terminal-scheme" {
-        match Base16Scheme::by_name(value) {
-            Some(scheme) => if let Err(err) = scheme.apply_to_realm(manager, realm) {
-                    warn!("Error applying terminal color scheme '{}' to realm-{}: {}", value, realm.name(), err);
-                    return;
-                },
-            None => {
-                warn!("No terminal color scheme with name '{}' available", value);
-                return;
-            },
-        };
-        realm.with_mut_config(|c| {
-            c.terminal_scheme = Some(value.to_string());
-        });
-        save_config(realm);
-    } else if variable == "realmfs" {
-        warn!("Changing realmfs config variable not implemented");
-    } else {
-        warn!("Unknown config variable '{}'", variable);
+fn config_value_to_variant(value: ConfigValue) -> OwnedValue {
+    match value {
+        ConfigValue::Bool(b) => Value::from(b).into(),
+        ConfigValue::Integer(n) => Value::from(n).into(),
+        ConfigValue::String(s) => Value::from(s).into(),
+        ConfigValue::StringList(v) => Value::from(v).into(),
     }
 }
 
@@ -101,15 +66,104 @@ impl RealmsManagerServer {
         self.manager.start_event_task()
     }
 
+    fn spawn_job(&self, kind: JobKind, name: &str) -> u32 {
+        match self.manager.realm_by_name(name) {
+            Some(realm) => self.jobs.spawn(self.clone(), kind, realm),
+            None => 0,
+        }
+    }
+
+    pub fn manager(&self) -> &RealmManager {
+        &self.manager
+    }
+
     pub fn register(connection: &Connection) -> Result<ObjectServer> {
         let manager = RealmManager::load()?;
-        let iface = RealmsManagerServer { manager };
+        let jobs = RealmJobs::new(connection.clone());
+        let iface = RealmsManagerServer { manager, jobs };
         iface.register_events(connection)?;
+        StatsMonitor::new(connection.clone(), iface.clone()).start();
+        RealmSupervisor::new(connection.clone(), iface.clone()).start();
         let mut object_server = ObjectServer::new(connection);
         object_server.at(REALMS_SERVER_OBJECT_PATH, iface).map_err(context!("ZBus error"))?;
         Ok(object_server)
     }
 
+    // Check values which depend on the state of the system rather than only on the value
+    fn check_system_value(&self, realm: &Realm, name: &str, value: &ConfigValue) -> Option<String> {
+        match (name, value) {
+            ("realmfs", ConfigValue::String(realmfs)) if !self.manager.realmfs_name_exists(realmfs) => {
+                Some(format!("no RealmFS named '{}' exists", realmfs))
+            },
+            ("terminal-scheme", ConfigValue::String(scheme)) if !scheme.is_empty() && Base16Scheme::by_name(scheme).is_none() => {
+                Some(format!("no terminal color scheme with name '{}' available", scheme))
+            },
+            ("realm-depends", ConfigValue::StringList(names)) if names.iter().any(|n| n == realm.name()) => {
+                Some("a realm cannot depend on itself".to_string())
+            },
+            ("realm-depends", ConfigValue::StringList(names)) => {
+                self.manager.dependencies()
+                    .cycle_with(realm.name(), names)
+                    .map(|cycle| format!("dependency cycle: {}", cycle.join(" -> ")))
+            },
+            _ => None,
+        }
+    }
+
+    /// Validate every value in `values` and apply them to the configuration of `realm`
+    /// only if all of them are valid. Emits `ConfigChanged` for each variable which
+    /// changed value and returns any validation errors.
+    fn apply_config_values(&self, realm: &Realm, values: Vec<(String, ConfigValue)>) -> Vec<ConfigErrorItem> {
+        let mut config = realm.config().as_ref().clone();
+        let mut errors = Vec::new();
+        let mut changed = Vec::new();
+
+        for (name, value) in values {
+            if let Some(message) = self.check_system_value(realm, &name, &value) {
+                errors.push(ConfigErrorItem::new(&name, &message));
+                continue;
+            }
+            match config.set_value(&name, value.clone()) {
+                Ok(true) => changed.push((name, value)),
+                Ok(false) => {},
+                Err(err) => errors.push(ConfigErrorItem::from_error(&err)),
+            }
+        }
+
+        if !errors.is_empty() || changed.is_empty() {
+            return errors;
+        }
+
+        for (name, value) in &changed {
+            if let ("terminal-scheme", ConfigValue::String(scheme)) = (name.as_str(), value) {
+                if let Some(scheme) = Base16Scheme::by_name(scheme) {
+                    if let Err(err) = scheme.apply_to_realm(&self.manager, realm) {
+                        return vec![ConfigErrorItem::new(name, &format!("error applying terminal color scheme: {}", err))];
+                    }
+                }
+            }
+        }
+
+        realm.with_mut_config(|c| *c = config);
+        if let Err(err) = save_config(realm) {
+            warn!("Error writing config file for realm-{}: {}", realm.name(), err);
+            return vec![ConfigErrorItem::new("", &format!("error writing config file: {}", err))];
+        }
+
+        if realm.is_active() && changed.iter().any(|(name,_)| ResourceLimits::is_limit_variable(name)) {
+            if let Err(err) = self.manager.apply_resource_limits(realm) {
+                warn!("Error applying resource limits to realm-{}: {}", realm.name(), err);
+            }
+        }
+
+        for (name, value) in changed {
+            let value = config_value_to_variant(value);
+            if let Err(err) = self.config_changed(realm.name(), &name, &value) {
+                warn!("Error emitting ConfigChanged signal: {}", err);
+            }
+        }
+        errors
+    }
 }
 
 
@@ -139,35 +193,30 @@ impl RealmsManagerServer {
         realms
     }
 
-    fn start(&self, name: &str) {
-        let realm = match self.manager.realm_by_name(name) {
-            Some(r) => r,
-            None => return,
-        };
-        let manager = self.manager.clone();
+    /// Start realm `name` in the background. Returns the id of the job which is
+    /// reported in `JobProgress` and `JobFinished` signals, or 0 if the realm does
+    /// not exist.
+    fn start(&self, name: &str) -> u32 {
+        self.spawn_job(JobKind::Start, name)
+    }
 
-        thread::spawn(move || {
-            if let Err(e) = manager.start_realm(&realm) {
-                warn!("failed to start realm {}: {}", realm.name(), e);
-            }
-        });
+    /// Stop realm `name` in the background. The job fails if running realms depend
+    /// on the realm.
+    fn stop(&self, name: &str) -> u32 {
+        self.spawn_job(JobKind::Stop, name)
     }
 
-    fn stop(&self, name: &str) {
-        let realm = match self.manager.realm_by_name(name) {
-            Some(r) => r,
-            None => return,
-        };
-        let manager = self.manager.clone();
+    /// Stop realm `name` in the background after stopping all running realms which
+    /// depend on it.
+    fn stop_with_dependents(&self, name: &str) -> u32 {
+        self.spawn_job(JobKind::StopWithDependents, name)
+    }
 
-        thread::spawn(move || {
-            if let Err(e) = manager.stop_realm(&realm) {
-                warn!("failed to stop realm {}: {}", realm.name(), e);
-            }
-        });
+    fn restart(&self, name: &str) -> u32 {
+        self.spawn_job(JobKind::Restart, name)
     }
 
-    fn restart(&self, name: &str) {
+    fn terminal(&self, name: &str) {
         let realm = match self.manager.realm_by_name(name) {
             Some(r) => r,
             None => return,
@@ -175,15 +224,19 @@ impl RealmsManagerServer {
         let manager = self.manager.clone();
 
         thread::spawn(move || {
-            if let Err(e) = manager.stop_realm(&realm) {
-                warn!("failed to stop realm {}: {}", realm.name(), e);
-            } else if let Err(e) = manager.start_realm(&realm) {
-                warn!("failed to restart realm {}: {}", realm.name(), e);
+            if !realm.is_active() {
+                if let Err(err) = manager.start_realm(&realm) {
+                    warn!("failed to start realm {}: {}", realm.name(), err);
+                    return;
+                }
+            }
+            if let Err(err) = manager.launch_terminal(&realm) {
+                warn!("error launching terminal for realm {}: {}", realm.name(), err);
             }
         });
     }
 
-    fn terminal(&self, name: &str) {
+    fn run(&self, name: &str, args: Vec<String>) {
         let realm = match self.manager.realm_by_name(name) {
             Some(r) => r,
             None => return,
@@ -197,17 +250,31 @@ impl RealmsManagerServer {
                     return;
                 }
             }
-            if let Err(err) = manager.launch_terminal(&realm) {
-                warn!("error launching terminal for realm {}: {}", realm.name(), err);
+            if let Err(err) = manager.run_in_realm(&realm, &args, true) {
+                warn!("error running {:?} in realm {}: {}", args, realm.name(), err);
             }
         });
     }
 
-    fn run(&self, name: &str, args: Vec<String>) {
+    /// Open a file in realm `name` with xdg-open, starting the realm first if needed.
+    ///
+    /// The file is read from `fd`, which was opened by the caller, and copied into the
+    /// realm with the name `filename`.
+    fn open_file(&self, name: &str, filename: &str, fd: Fd) {
         let realm = match self.manager.realm_by_name(name) {
             Some(r) => r,
             None => return,
         };
+        // The descriptor is closed when this call returns
+        let fd = unsafe { BorrowedFd::borrow_raw(fd.as_raw_fd()) };
+        let mut file = match fd.try_clone_to_owned() {
+            Ok(fd) => File::from(fd),
+            Err(err) => {
+                warn!("failed to duplicate descriptor of file {}: {}", filename, err);
+                return;
+            }
+        };
+        let filename = filename.to_string();
         let manager = self.manager.clone();
 
         thread::spawn(move || {
@@ -217,8 +284,15 @@ impl RealmsManagerServer {
                     return;
                 }
             }
-            if let Err(err) = manager.run_in_realm(&realm, &args, true) {
-                warn!("error running {:?} in realm {}: {}", args, realm.name(), err);
+            let path = match manager.copy_file_into_realm(&realm, &mut file, &filename) {
+                Ok(path) => path.display().to_string(),
+                Err(err) => {
+                    warn!("error copying {} into realm {}: {}", filename, realm.name(), err);
+                    return;
+                }
+            };
+            if let Err(err) = manager.run_in_realm(&realm, &["/usr/bin/xdg-open", path.as_str()], true) {
+                warn!("error opening {} in realm {}: {}", path, realm.name(), err);
             }
         });
     }
@@ -247,11 +321,61 @@ impl RealmsManagerServer {
             },
         };
 
-        for var in &vars {
-            configure_realm(&self.manager, &realm, &var.0, &var.1);
+        let mut values = Vec::new();
+        for (variable, value) in vars {
+            match libcitadel::config_kind(&variable).and_then(|kind| ConfigValue::parse(kind, &value)) {
+                Some(value) => values.push((variable, value)),
+                None => warn!("Invalid value '{}' for config variable '{}'", value, variable),
+            }
+        }
+        for err in self.apply_config_values(&realm, values) {
+            warn!("Error setting config variable '{}' of realm-{}: {}", err.variable, name, err.message);
         }
     }
 
+    fn config_variables(&self) -> Vec<(String,String)> {
+        CONFIG_VARIABLES.iter()
+            .map(|(name, kind)| (name.to_string(), kind.name().to_string()))
+            .collect()
+    }
+
+    fn realm_config_values(&self, name: &str) -> HashMap<String,OwnedValue> {
+        let realm = match self.manager.realm_by_name(name) {
+            Some(r) => r,
+            None => return HashMap::new(),
+        };
+        let config = realm.config();
+        CONFIG_VARIABLES.iter()
+            .filter_map(|(variable, _)| {
+                config.get_value(variable)
+                    .map(|value| (variable.to_string(), config_value_to_variant(value)))
+            })
+            .collect()
+    }
+
+    fn realm_set_config_values(&self, name: &str, values: HashMap<String,OwnedValue>) -> Vec<ConfigErrorItem> {
+        let realm = match self.manager.realm_by_name(name) {
+            Some(r) => r,
+            None => return vec![ConfigErrorItem::new("", &format!("no realm named '{}' found", name))],
+        };
+
+        let mut errors = Vec::new();
+        let mut config_values = Vec::new();
+        for (variable, value) in values {
+            match libcitadel::config_kind(&variable) {
+                Some(kind) => match config_value_from_variant(kind, &value) {
+                    Some(value) => config_values.push((variable, value)),
+                    None => errors.push(ConfigErrorItem::new(&variable, &format!("expected a {} value", kind.name()))),
+                },
+                None => errors.push(ConfigErrorItem::new(&variable, "unknown configuration variable")),
+            }
+        }
+        if !errors.is_empty() {
+            return errors;
+        }
+        self.apply_config_values(&realm, config_values)
+    }
+
     fn realm_exists(&self, name: &str) -> bool {
         Realm::is_valid_name(name) && self.manager.realm_by_name(name).is_some()
     }
@@ -265,6 +389,59 @@ impl RealmsManagerServer {
         }
     }
 
+    fn list_snapshots(&self, name: &str) -> Vec<SnapshotItem> {
+        let realm = match self.manager.realm_by_name(name) {
+            Some(r) => r,
+            None => return Vec::new(),
+        };
+        match realm.list_snapshots() {
+            Ok(snapshots) => snapshots.iter().map(SnapshotItem::new_from_snapshot).collect(),
+            Err(err) => {
+                warn!("Error listing snapshots of realm ({}): {}", name, err);
+                Vec::new()
+            }
+        }
+    }
+
+    fn create_snapshot(&self, name: &str, label: &str) -> bool {
+        let realm = match self.manager.realm_by_name(name) {
+            Some(r) => r,
+            None => return false,
+        };
+        if let Err(err) = realm.snapshot(label) {
+            warn!("Error creating snapshot '{}' of realm ({}): {}", label, name, err);
+            false
+        } else {
+            true
+        }
+    }
+
+    fn restore_snapshot(&self, name: &str, label: &str) -> bool {
+        let realm = match self.manager.realm_by_name(name) {
+            Some(r) => r,
+            None => return false,
+        };
+        if let Err(err) = realm.restore_snapshot(label) {
+            warn!("Error restoring snapshot '{}' of realm ({}): {}", label, name, err);
+            false
+        } else {
+            true
+        }
+    }
+
+    fn delete_snapshot(&self, name: &str, label: &str) -> bool {
+        let realm = match self.manager.realm_by_name(name) {
+            Some(r) => r,
+            None => return false,
+        };
+        if let Err(err) = realm.delete_snapshot(label) {
+            warn!("Error removing snapshot '{}' of realm ({}): {}", label, name, err);
+            false
+        } else {
+            true
+        }
+    }
+
     fn list_realm_f_s(&self) -> Vec<String> {
         self.manager.realmfs_list()
             .into_iter()
@@ -272,6 +449,44 @@ impl RealmsManagerServer {
             .collect()
     }
 
+    fn realm_stats(&self, name: &str) -> RealmStatsItem {
+        let realm = match self.manager.realm_by_name(name) {
+            Some(r) => r,
+            None => return RealmStatsItem::default(),
+        };
+        match RealmStats::load(&realm) {
+            Ok(stats) => RealmStatsItem::new_from_stats(&stats),
+            Err(err) => {
+                warn!("Error reading resource usage of realm-{}: {}", name, err);
+                RealmStatsItem::default()
+            }
+        }
+    }
+
+    fn realm_history(&self, name: &str, count: u32) -> Vec<EventItem> {
+        let realm = match self.manager.realm_by_name(name) {
+            Some(r) => r,
+            None => return Vec::new(),
+        };
+        match libcitadel::EventJournal::history(&realm, count as usize) {
+            Ok(entries) => entries.iter().map(EventItem::new_from_entry).collect(),
+            Err(err) => {
+                warn!("Error reading event history of realm-{}: {}", name, err);
+                Vec::new()
+            }
+        }
+    }
+
+    fn recent_events(&self, count: u32) -> Vec<EventItem> {
+        match libcitadel::EventJournal::recent(count as usize) {
+            Ok(entries) => entries.iter().map(EventItem::new_from_entry).collect(),
+            Err(err) => {
+                warn!("Error reading event journal: {}", err);
+                Vec::new()
+            }
+        }
+    }
+
     fn update_realm_f_s(&self, _name: &str) {
 
     }
@@ -294,6 +509,48 @@ impl RealmsManagerServer {
     #[dbus_interface(signal)]
     pub fn service_started(&self) -> zbus::Result<()> { Ok(()) }
 
+    #[dbus_interface(signal)]
+    pub fn config_changed(&self, realm: &str, variable: &str, value: &OwnedValue) -> zbus::Result<()> { Ok(()) }
+
+    #[dbus_interface(signal)]
+    pub fn realm_config_changed(&self, realm: &str) -> zbus::Result<()> { Ok(()) }
+
+    #[dbus_interface(signal)]
+    pub fn realm_start_failed(&self, realm: &str, error: &str) -> zbus::Result<()> { Ok(()) }
+
+    #[dbus_interface(signal)]
+    pub fn realm_unhealthy(&self, realm: &str, reason: &str) -> zbus::Result<()> { Ok(()) }
+
+    #[dbus_interface(signal)]
+    pub fn realm_restarted(&self, realm: &str, attempt: u32) -> zbus::Result<()> { Ok(()) }
+
+    #[dbus_interface(signal)]
+    pub fn realm_f_s_new(&self, realmfs: &str) -> zbus::Result<()> { Ok(()) }
+
+    #[dbus_interface(signal)]
+    pub fn realm_f_s_removed(&self, realmfs: &str) -> zbus::Result<()> { Ok(()) }
+
+    #[dbus_interface(signal)]
+    pub fn realm_f_s_activated(&self, realmfs: &str) -> zbus::Result<()> { Ok(()) }
+
+    #[dbus_interface(signal)]
+    pub fn realm_f_s_deactivated(&self, realmfs: &str) -> zbus::Result<()> { Ok(()) }
+
+    #[dbus_interface(signal)]
+    pub fn realm_f_s_resized(&self, realmfs: &str, nblocks: u64) -> zbus::Result<()> { Ok(()) }
+
+    #[dbus_interface(signal)]
+    pub fn realm_f_s_updated(&self, realmfs: &str) -> zbus::Result<()> { Ok(()) }
+
+    #[dbus_interface(signal)]
+    pub fn job_progress(&self, job: u32, realm: &str, stage: &str, description: &str) -> zbus::Result<()> { Ok(()) }
+
+    #[dbus_interface(signal)]
+    pub fn job_finished(&self, job: u32, realm: &str, success: bool, error: JobErrorItem) -> zbus::Result<()> { Ok(()) }
+
+    #[dbus_interface(signal)]
+    pub fn realm_stats_updated(&self, realm: &str, stats: RealmStatsItem) -> zbus::Result<()> { Ok(()) }
+
 }
 
 const STATUS_REALM_RUNNING: u8 = 1;
@@ -334,6 +591,116 @@ impl RealmItem {
     }
 }
 
+#[derive(Deserialize,Serialize,Type)]
+struct SnapshotItem {
+    label: String,
+    timestamp: i64,
+    has_overlay: bool,
+}
+
+impl SnapshotItem {
+    fn new_from_snapshot(snapshot: &RealmSnapshot) -> Self {
+        SnapshotItem {
+            label: snapshot.label().to_string(),
+            timestamp: snapshot.timestamp(),
+            has_overlay: snapshot.has_overlay(),
+        }
+    }
+}
+
+/// Resource usage of a realm. Disk usage fields are zero in the periodic
+/// `RealmStatsUpdated` signal.
+#[derive(Deserialize,Serialize,Type,Clone,Default)]
+pub struct RealmStatsItem {
+    cpu_usage_usec: u64,
+    memory_current: u64,
+    memory_peak: u64,
+    io_read_bytes: u64,
+    io_write_bytes: u64,
+    tasks_current: u64,
+    realmfs_size: u64,
+    realmfs_used: u64,
+    overlay_used: u64,
+}
+
+impl RealmStatsItem {
+    pub fn new_from_stats(stats: &RealmStats) -> Self {
+        RealmStatsItem {
+            cpu_usage_usec: stats.cpu_usage_usec(),
+            memory_current: stats.memory_current(),
+            memory_peak: stats.memory_peak(),
+            io_read_bytes: stats.io_read_bytes(),
+            io_write_bytes: stats.io_write_bytes(),
+            tasks_current: stats.tasks_current(),
+            realmfs_size: stats.realmfs_size(),
+            realmfs_used: stats.realmfs_used(),
+            overlay_used: stats.overlay_used(),
+        }
+    }
+}
+
+#[derive(Deserialize,Serialize,Type)]
+struct EventItem {
+    timestamp: u64,
+    kind: String,
+    name: String,
+    reason: String,
+}
+
+impl EventItem {
+    fn new_from_entry(entry: &JournalEntry) -> Self {
+        EventItem {
+            timestamp: entry.timestamp(),
+            kind: entry.kind().as_str().to_string(),
+            name: entry.name().to_string(),
+            reason: entry.reason().to_string(),
+        }
+    }
+}
+
+/// Reason a job failed. `kind` classifies the failure by the step which failed, one of
+/// "dependency", "realmfs-missing", "realmfs-activation", "overlay", "network", "unit"
+/// or "other", and `stage` is the name of that step. All fields are empty on success.
+#[derive(Deserialize,Serialize,Type,Clone,Default)]
+pub struct JobErrorItem {
+    kind: String,
+    stage: String,
+    message: String,
+}
+
+impl JobErrorItem {
+    pub fn new(kind: &str, stage: &str, message: &str) -> Self {
+        JobErrorItem {
+            kind: kind.to_string(),
+            stage: stage.to_string(),
+            message: message.to_string(),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.kind.is_empty()
+    }
+}
+
+#[derive(Deserialize,Serialize,Type)]
+struct ConfigErrorItem {
+    variable: String,
+    message: String,
+}
+
+impl ConfigErrorItem {
+    fn new(variable: &str, message: &str) -> Self {
+        ConfigErrorItem {
+            variable: variable.to_string(),
+            message: message.to_string(),
+        }
+    }
+
+    fn from_error(err: &ConfigError) -> Self {
+        Self::new(err.variable(), err.message())
+    }
+}
+
 #[derive(Deserialize,Serialize,Type)]
 struct RealmConfig {
     items: HashMap<String,String>,
@@ -347,36 +714,14 @@ impl RealmConfig {
     fn new_from_realm(realm: &Realm) -> Self {
         let mut this = RealmConfig { items: HashMap::new() };
         let config = realm.config();
-        this.add_bool("use-gpu", config.gpu());
-        this.add_bool("use-wayland", config.wayland());
-        this.add_bool("use-x11", config.x11());
-        this.add_bool("use-sound", config.sound());
-        this.add_bool("use-shared-dir", config.shared_dir());
-        this.add_bool("use-network", config.network());
-        this.add_bool("use-kvm", config.kvm());
-        this.add_bool("use-ephemeral-home", config.ephemeral_home());
-
-        let overlay = match config.overlay() {
-            OverlayType::None => "none",
-            OverlayType::TmpFS => "tmpfs",
-            OverlayType::Storage => "storage",
-        };
-        this.add("overlay", overlay);
-
-        let scheme = match config.terminal_scheme() {
-            Some(name) => name.to_string(),
-            None => String::new(),
-        };
-        this.add("terminal-scheme", scheme);
-        this.add("realmfs", config.realmfs());
+        for (name, _) in CONFIG_VARIABLES {
+            if let Some(value) = config.get_value(name) {
+                this.add(*name, value.to_string());
+            }
+        }
         this
     }
 
-    fn add_bool(&mut self, name: &str, val: bool) {
-        let valstr = if val { "true".to_string() } else { "false".to_string() };
-        self.add(name, valstr);
-    }
-
     fn add<S,T>(&mut self, k: S, v: T) where S: Into<String>, T: Into<String> {
         self.items.insert(k.into(), v.into());
     }
@@ -0,0 +1,14 @@
+use zbus::{Connection, ObjectServer};
+
+use crate::realms_manager::{RealmsManagerServer, REALMS_SERVER_OBJECT_PATH};
+
+/// Run `func` against `server` registered on a temporary object server so
+/// that it can emit signals on `connection` from outside of a method call.
+pub fn with_server<F>(connection: &Connection, server: &RealmsManagerServer, func: F) -> zbus::Result<()>
+    where
+        F: Fn(&RealmsManagerServer) -> zbus::Result<()>,
+{
+    let mut object_server = ObjectServer::new(connection);
+    object_server.at(REALMS_SERVER_OBJECT_PATH, server.clone())?;
+    object_server.with(REALMS_SERVER_OBJECT_PATH, |iface: &RealmsManagerServer| func(iface))
+}
@@ -0,0 +1,53 @@
+use std::thread;
+use std::time::Duration;
+
+use zbus::Connection;
+use libcitadel::RealmStats;
+
+use crate::realms_manager::{RealmsManagerServer, RealmStatsItem};
+use crate::signals;
+
+// Seconds between RealmStatsUpdated signals
+const STATS_INTERVAL: u64 = 5;
+
+/// Periodically emits the resource usage of each running realm as a signal.
+pub struct StatsMonitor {
+    connection: Connection,
+    realms_server: RealmsManagerServer,
+}
+
+impl StatsMonitor {
+    pub fn new(connection: Connection, realms_server: RealmsManagerServer) -> Self {
+        StatsMonitor { connection, realms_server }
+    }
+
+    pub fn start(self) {
+        thread::spawn(move || loop {
+            thread::sleep(Duration::from_secs(STATS_INTERVAL));
+            self.emit_stats();
+        });
+    }
+
+    fn emit_stats(&self) {
+        for realm in self.realms_server.manager().active_realms(false) {
+            // Disk usage is left out since walking the overlay on every update is too slow
+            let stats = match RealmStats::load_usage(&realm) {
+                Ok(stats) => RealmStatsItem::new_from_stats(&stats),
+                Err(err) => {
+                    warn!("Error reading resource usage of realm-{}: {}", realm.name(), err);
+                    continue;
+                }
+            };
+            if let Err(err) = self.with_server(|server| server.realm_stats_updated(realm.name(), stats.clone())) {
+                warn!("Error emitting RealmStatsUpdated signal: {}", err);
+            }
+        }
+    }
+
+    fn with_server<F>(&self, func: F) -> zbus::Result<()>
+        where
+            F: Fn(&RealmsManagerServer) -> zbus::Result<()>,
+    {
+        signals::with_server(&self.connection, &self.realms_server, func)
+    }
+}
@@ -0,0 +1,225 @@
+use std::collections::HashMap;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use zbus::Connection;
+use libcitadel::{EventJournal, JournalEventKind, Realm, RestartPolicy};
+
+use crate::realms_manager::RealmsManagerServer;
+use crate::signals;
+
+// Seconds between checks of the state of each realm
+const SUPERVISE_INTERVAL: u64 = 5;
+
+// A realm is unhealthy after this many consecutive failed health checks
+const MAX_HEALTH_CHECK_FAILURES: u32 = 3;
+
+// Automatic restarts stop after this many restarts in a row without the realm
+// staying up for RESTART_RESET_SECS
+const MAX_RESTARTS: u32 = 5;
+const RESTART_RESET_SECS: u64 = 600;
+
+#[derive(Default)]
+struct RealmState {
+    was_active: bool,
+    started: Option<Instant>,
+    // Set when the realm is seen to stop. The stop is examined on a later check so
+    // that a `stop_realm()` in progress has finished removing the service unit.
+    stopped: Option<Instant>,
+    last_health_check: Option<Instant>,
+    failures: u32,
+    unhealthy: bool,
+    restarts: u32,
+    restart_at: Option<Instant>,
+}
+
+impl RealmState {
+    fn reset(&mut self, now: Instant) {
+        self.started = Some(now);
+        // the first health check runs one interval after the realm starts
+        self.last_health_check = Some(now);
+        self.failures = 0;
+        self.unhealthy = false;
+    }
+
+    fn up_for(&self, now: Instant) -> Duration {
+        self.started.map(|t| now.duration_since(t)).unwrap_or_default()
+    }
+}
+
+/// Runs the health checks of running realms and restarts realms which stop
+/// unexpectedly or become unhealthy according to their `restart-policy`.
+pub struct RealmSupervisor {
+    connection: Connection,
+    realms_server: RealmsManagerServer,
+    states: HashMap<String, RealmState>,
+}
+
+impl RealmSupervisor {
+    pub fn new(connection: Connection, realms_server: RealmsManagerServer) -> Self {
+        RealmSupervisor { connection, realms_server, states: HashMap::new() }
+    }
+
+    pub fn start(mut self) {
+        thread::spawn(move || loop {
+            thread::sleep(Duration::from_secs(SUPERVISE_INTERVAL));
+            self.supervise();
+        });
+    }
+
+    fn supervise(&mut self) {
+        let realms = self.realms_server.manager().realm_list();
+        self.states.retain(|name, _| realms.iter().any(|r| r.name() == name));
+        for realm in realms {
+            let mut state = self.states.remove(realm.name()).unwrap_or_default();
+            self.supervise_realm(&realm, &mut state);
+            self.states.insert(realm.name().to_string(), state);
+        }
+    }
+
+    fn supervise_realm(&self, realm: &Realm, state: &mut RealmState) {
+        let now = Instant::now();
+        let active = realm.is_active();
+        let policy = realm.config().restart_policy();
+
+        if active && !state.was_active {
+            state.reset(now);
+            state.stopped = None;
+            state.restart_at = None;
+        } else if !active && state.was_active {
+            state.stopped = Some(now);
+        }
+        state.was_active = active;
+
+        if active {
+            if state.restarts > 0 && state.up_for(now) >= Duration::from_secs(RESTART_RESET_SECS) {
+                state.restarts = 0;
+            }
+            self.check_health(realm, policy, state, now);
+        } else if state.stopped.is_some_and(|t| t < now) {
+            state.stopped = None;
+            self.check_stopped(realm, policy, state, now);
+        }
+
+        if state.restart_at.is_some_and(|t| t <= now) {
+            state.restart_at = None;
+            self.restart(realm, state);
+        }
+    }
+
+    fn check_health(&self, realm: &Realm, policy: RestartPolicy, state: &mut RealmState, now: Instant) {
+        let interval = {
+            let config = realm.config();
+            if config.health_check().is_none() {
+                return;
+            }
+            Duration::from_secs(config.health_check_interval().into())
+        };
+        if state.last_health_check.is_some_and(|t| now.duration_since(t) < interval) {
+            return;
+        }
+        state.last_health_check = Some(now);
+
+        match self.realms_server.manager().check_realm_health(realm) {
+            Ok(true) => {
+                if state.unhealthy {
+                    info!("realm-{} is healthy again", realm.name());
+                }
+                state.failures = 0;
+                state.unhealthy = false;
+            },
+            Ok(false) => state.failures += 1,
+            Err(err) => warn!("Error running health check in realm-{}: {}", realm.name(), err),
+        }
+
+        if state.failures >= MAX_HEALTH_CHECK_FAILURES && !state.unhealthy {
+            state.unhealthy = true;
+            self.report_unhealthy(realm, &format!("health check failed {} times in a row", state.failures));
+            if policy.restart_when_unhealthy() {
+                self.schedule_restart(realm, state, now);
+            }
+        }
+    }
+
+    fn check_stopped(&self, realm: &Realm, policy: RestartPolicy, state: &mut RealmState, now: Instant) {
+        let result = match self.realms_server.manager().unexpected_stop_result(realm) {
+            Ok(Some(result)) => result,
+            // stopped with stop_realm()
+            Ok(None) => return,
+            Err(err) => {
+                warn!("Error reading service unit result of realm-{}: {}", realm.name(), err);
+                return;
+            }
+        };
+        let failed = result != "success";
+        if failed {
+            self.report_unhealthy(realm, &format!("realm stopped unexpectedly ({})", result));
+        } else {
+            info!("realm-{} was shut down from inside the realm", realm.name());
+        }
+        if policy.restart_after_stop(failed) {
+            self.schedule_restart(realm, state, now);
+        }
+    }
+
+    fn schedule_restart(&self, realm: &Realm, state: &mut RealmState, now: Instant) {
+        if state.restart_at.is_some() {
+            return;
+        }
+        if state.restarts >= MAX_RESTARTS {
+            self.report_unhealthy(realm, &format!("not restarting after {} restarts in a row", state.restarts));
+            return;
+        }
+        state.restarts += 1;
+        let delay = RestartPolicy::restart_delay(state.restarts);
+        info!("Restarting realm-{} in {} seconds", realm.name(), delay.as_secs());
+        state.restart_at = Some(now + delay);
+    }
+
+    fn restart(&self, realm: &Realm, state: &mut RealmState) {
+        let manager = self.realms_server.manager();
+        let result = if realm.is_active() {
+            manager.restart_realm(realm)
+        } else {
+            match manager.unexpected_stop_result(realm) {
+                Ok(Some(_)) => manager.recover_realm(realm),
+                // stopped with stop_realm() after the restart was scheduled
+                Ok(None) => return,
+                Err(err) => Err(err),
+            }
+        };
+
+        if let Err(err) = result {
+            warn!("Automatic restart of realm-{} failed: {}", realm.name(), err);
+            self.schedule_restart(realm, state, Instant::now());
+            return;
+        }
+
+        state.reset(Instant::now());
+        state.was_active = realm.is_active();
+        let reason = format!("automatic restart {} of {}", state.restarts, MAX_RESTARTS);
+        if let Err(err) = EventJournal::record(JournalEventKind::Restarted, realm.name(), &reason) {
+            warn!("Error recording restart of realm-{} in journal: {}", realm.name(), err);
+        }
+        if let Err(err) = self.with_server(|server| server.realm_restarted(realm.name(), state.restarts)) {
+            warn!("Error emitting RealmRestarted signal: {}", err);
+        }
+    }
+
+    fn report_unhealthy(&self, realm: &Realm, reason: &str) {
+        warn!("realm-{} is unhealthy: {}", realm.name(), reason);
+        if let Err(err) = EventJournal::record(JournalEventKind::Unhealthy, realm.name(), reason) {
+            warn!("Error recording unhealthy realm-{} in journal: {}", realm.name(), err);
+        }
+        if let Err(err) = self.with_server(|server| server.realm_unhealthy(realm.name(), reason)) {
+            warn!("Error emitting RealmUnhealthy signal: {}", err);
+        }
+    }
+
+    fn with_server<F>(&self, func: F) -> zbus::Result<()>
+        where
+            F: Fn(&RealmsManagerServer) -> zbus::Result<()>,
+    {
+        signals::with_server(&self.connection, &self.realms_server, func)
+    }
+}